@@ -0,0 +1,65 @@
+//! ARM Cortex-M Data Watchpoint and Trace unit cycle counter.
+//!
+//! Documented in the ARMv7-M Architecture Reference Manual, Chapter C1.8.
+//! Cores without the counter, such as the Cortex-M0, report that it is not
+//! implemented.
+
+use kernel;
+use kernel::common::registers::ReadWrite;
+use kernel::common::StaticRef;
+
+#[repr(C)]
+struct DwtRegisters {
+    ctrl: ReadWrite<u32, Control::Register>,
+    cyccnt: ReadWrite<u32>,
+}
+
+#[repr(C)]
+struct DebugRegisters {
+    demcr: ReadWrite<u32, DebugExceptionMonitorControl::Register>,
+}
+
+register_bitfields![u32,
+    Control [
+        /// Set if the cycle counter is not implemented.
+        NOCYCCNT 25,
+
+        /// Enable the cycle counter.
+        CYCCNTENA 0
+    ],
+
+    DebugExceptionMonitorControl [
+        /// Enable the DWT and ITM units.
+        TRCENA 24
+    ]
+];
+
+const DWT_BASE: StaticRef<DwtRegisters> =
+    unsafe { StaticRef::new(0xE0001000 as *const DwtRegisters) };
+const DEBUG_BASE: StaticRef<DebugRegisters> =
+    unsafe { StaticRef::new(0xE000EDFC as *const DebugRegisters) };
+
+/// The DWT cycle counter, which counts processor clock cycles.
+pub struct CycleCounter(());
+
+pub static CYCLE_COUNTER: CycleCounter = CycleCounter(());
+
+impl CycleCounter {
+    /// Start the counter. Returns `false` if the core does not have one.
+    pub unsafe fn enable(&self) -> bool {
+        let demcr = &DEBUG_BASE.demcr;
+        demcr.modify(DebugExceptionMonitorControl::TRCENA::SET);
+        if DWT_BASE.ctrl.is_set(Control::NOCYCCNT) {
+            return false;
+        }
+        DWT_BASE.cyccnt.set(0);
+        DWT_BASE.ctrl.modify(Control::CYCCNTENA::SET);
+        true
+    }
+}
+
+impl kernel::CycleCounter for CycleCounter {
+    fn cycles(&self) -> u32 {
+        DWT_BASE.cyccnt.get()
+    }
+}
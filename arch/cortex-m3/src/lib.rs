@@ -10,6 +10,7 @@ extern crate cortexm;
 // valid on cortex-m3.
 pub use cortexm::support;
 
+pub use cortexm::dwt;
 pub use cortexm::nvic;
 pub use cortexm::scb;
 pub use cortexm::syscall;
@@ -16,6 +16,7 @@ pub mod mpu;
 // valid on cortex-m4.
 pub use cortexm::support;
 
+pub use cortexm::dwt;
 pub use cortexm::nvic;
 pub use cortexm::scb;
 pub use cortexm::syscall;
@@ -25,6 +25,7 @@ use kernel::hil::entropy::Entropy32;
 use kernel::hil::rng::Rng;
 use kernel::hil::spi::SpiMaster;
 use kernel::hil::Controller;
+use kernel::introspection::{Peripheral, RegisterField};
 use kernel::Platform;
 
 /// Support routines for debugging I/O.
@@ -50,6 +51,15 @@ const FAULT_RESPONSE: kernel::procs::FaultResponse = kernel::procs::FaultRespons
 #[link_section = ".app_memory"]
 static mut APP_MEMORY: [u8; 49152] = [0; 49152];
 
+// Room for the 32 most recent system calls, for the process console's
+// `strace`.
+static mut SYSCALL_TRACE_BUF: [kernel::syscall::SyscallRecord; 32] =
+    [kernel::syscall::SyscallRecord {
+        time: 0,
+        process: 0,
+        syscall: kernel::syscall::Syscall::YIELD,
+    }; 32];
+
 // Actual memory for holding the active process structures.
 static mut PROCESSES: [Option<&'static kernel::procs::ProcessType>; NUM_PROCS] = [
     None, None, None, None, None, None, None, None, None, None, None, None, None, None, None, None,
@@ -61,10 +71,40 @@ static mut PROCESSES: [Option<&'static kernel::procs::ProcessType>; NUM_PROCS] =
 #[link_section = ".stack_buffer"]
 pub static mut STACK_MEMORY: [u8; 0x1000] = [0; 0x1000];
 
+/// Lets the process console inspect and control processes.
+pub struct ProcessConsoleCapability;
+unsafe impl capabilities::ProcessManagementCapability for ProcessConsoleCapability {}
+
+/// The capsules in `Hail`, as listed by the process console's `caps` command.
+static CAPSULES: &'static [(&'static str, &'static str)] = &[
+    ("console", "capsules::console::Console"),
+    ("process_console", "capsules::process_console::ProcessConsole"),
+    ("gpio", "capsules::gpio::GPIO"),
+    ("alarm", "capsules::alarm::AlarmDriver"),
+    ("ambient_light", "capsules::ambient_light::AmbientLight"),
+    ("temp", "capsules::temperature::TemperatureSensor"),
+    ("ninedof", "capsules::ninedof::NineDof"),
+    ("humidity", "capsules::humidity::HumiditySensor"),
+    ("spi", "capsules::spi::Spi"),
+    ("nrf51822", "capsules::nrf51822_serialization::Nrf51822Serialization"),
+    ("adc", "capsules::adc::Adc"),
+    ("led", "capsules::led::LED"),
+    ("button", "capsules::button::Button"),
+    ("rng", "capsules::rng::RngDriver"),
+    ("ipc", "kernel::ipc::IPC"),
+    ("crc", "capsules::crc::Crc"),
+    ("dac", "capsules::dac::Dac"),
+];
+
 /// A structure representing this platform that holds references to all
 /// capsules for this platform.
 struct Hail {
     console: &'static capsules::console::Console<'static, UartDevice<'static>>,
+    process_console: &'static capsules::process_console::ProcessConsole<
+        'static,
+        UartDevice<'static>,
+        ProcessConsoleCapability,
+    >,
     gpio: &'static capsules::gpio::GPIO<'static, sam4l::gpio::GPIOPin>,
     alarm: &'static capsules::alarm::AlarmDriver<
         'static,
@@ -83,11 +123,100 @@ struct Hail {
     led: &'static capsules::led::LED<'static, sam4l::gpio::GPIOPin>,
     button: &'static capsules::button::Button<'static, sam4l::gpio::GPIOPin>,
     rng: &'static capsules::rng::RngDriver<'static>,
-    ipc: kernel::ipc::IPC,
+    ipc: &'static kernel::ipc::IPC,
     crc: &'static capsules::crc::Crc<'static, sam4l::crccu::Crccu<'static>>,
     dac: &'static capsules::dac::Dac<'static>,
 }
 
+/// The syscall drivers `with_driver` gives apps, as listed by the process
+/// console's `checkdriver` command.
+static DRIVERS: &'static [(usize, &'static str)] = &[
+    (capsules::console::DRIVER_NUM, "console"),
+    (capsules::process_console::DRIVER_NUM, "process console"),
+    (capsules::gpio::DRIVER_NUM, "GPIO"),
+    (capsules::alarm::DRIVER_NUM, "alarm"),
+    (capsules::spi::DRIVER_NUM, "SPI"),
+    (capsules::nrf51822_serialization::DRIVER_NUM, "nRF51822 serialization"),
+    (capsules::ambient_light::DRIVER_NUM, "ambient light"),
+    (capsules::adc::DRIVER_NUM, "ADC"),
+    (capsules::led::DRIVER_NUM, "LED"),
+    (capsules::button::DRIVER_NUM, "button"),
+    (capsules::humidity::DRIVER_NUM, "humidity"),
+    (capsules::temperature::DRIVER_NUM, "temperature"),
+    (capsules::ninedof::DRIVER_NUM, "ninedof"),
+    (capsules::rng::DRIVER_NUM, "RNG"),
+    (capsules::crc::DRIVER_NUM, "CRC"),
+    (capsules::dac::DRIVER_NUM, "DAC"),
+    (kernel::ipc::DRIVER_NUM, "IPC"),
+];
+
+/// Peripherals whose registers the process console's `reg` command may read.
+/// Only peripherals whose registers can be read without side effects are
+/// listed.
+static PERIPHERALS: &'static [Peripheral] = &[
+    Peripheral {
+        name: "pm",
+        base: 0x400E0000,
+        size: 0x400,
+        fields: &[
+            RegisterField {
+                offset: 0x180,
+                name: "RCAUSE.POR",
+                shift: 0,
+                width: 1,
+            },
+            RegisterField {
+                offset: 0x180,
+                name: "RCAUSE.BOD",
+                shift: 1,
+                width: 1,
+            },
+            RegisterField {
+                offset: 0x180,
+                name: "RCAUSE.EXT",
+                shift: 2,
+                width: 1,
+            },
+            RegisterField {
+                offset: 0x180,
+                name: "RCAUSE.WDT",
+                shift: 3,
+                width: 1,
+            },
+            RegisterField {
+                offset: 0x180,
+                name: "RCAUSE.BKUP",
+                shift: 6,
+                width: 1,
+            },
+            RegisterField {
+                offset: 0x180,
+                name: "RCAUSE.OCDRST",
+                shift: 8,
+                width: 1,
+            },
+            RegisterField {
+                offset: 0x180,
+                name: "RCAUSE.POR33",
+                shift: 10,
+                width: 1,
+            },
+            RegisterField {
+                offset: 0x180,
+                name: "RCAUSE.BOD33",
+                shift: 13,
+                width: 1,
+            },
+        ],
+    },
+    Peripheral {
+        name: "bscif",
+        base: 0x400F0400,
+        size: 0x400,
+        fields: &[],
+    },
+];
+
 /// Mapping of integer syscalls to objects that implement syscalls.
 impl Platform for Hail {
     fn with_driver<F, R>(&self, driver_num: usize, f: F) -> R
@@ -96,6 +225,7 @@ impl Platform for Hail {
     {
         match driver_num {
             capsules::console::DRIVER_NUM => f(Some(self.console)),
+            capsules::process_console::DRIVER_NUM => f(Some(self.process_console)),
             capsules::gpio::DRIVER_NUM => f(Some(self.gpio)),
 
             capsules::alarm::DRIVER_NUM => f(Some(self.alarm)),
@@ -115,7 +245,7 @@ impl Platform for Hail {
 
             capsules::dac::DRIVER_NUM => f(Some(self.dac)),
 
-            kernel::ipc::DRIVER_NUM => f(Some(&self.ipc)),
+            kernel::ipc::DRIVER_NUM => f(Some(self.ipc)),
             _ => f(None),
         }
     }
@@ -253,8 +383,6 @@ pub unsafe fn reset_handler() {
     // Setup the process inspection console
     let process_console_uart = static_init!(UartDevice, UartDevice::new(uart_mux, true));
     process_console_uart.setup();
-    pub struct ProcessConsoleCapability;
-    unsafe impl capabilities::ProcessManagementCapability for ProcessConsoleCapability {}
     let process_console = static_init!(
         capsules::process_console::ProcessConsole<UartDevice, ProcessConsoleCapability>,
         capsules::process_console::ProcessConsole::new(
@@ -263,11 +391,25 @@ pub unsafe fn reset_handler() {
             &mut capsules::process_console::WRITE_BUF,
             &mut capsules::process_console::READ_BUF,
             &mut capsules::process_console::COMMAND_BUF,
+            &mut capsules::process_console::OUTPUT_BUF,
+            board_kernel.create_grant(&memory_allocation_capability),
             board_kernel,
             ProcessConsoleCapability,
         )
     );
     hil::uart::UART::set_client(process_console_uart, process_console);
+    let output_recorder = static_init!(
+        capsules::process_console::OutputRecorder,
+        capsules::process_console::OutputRecorder::new(
+            board_kernel.create_grant(&memory_allocation_capability)
+        )
+    );
+    console.set_output_client(output_recorder);
+    process_console.set_output_capture(output_recorder);
+    process_console.set_capsule_list(CAPSULES);
+    process_console.set_driver_list(DRIVERS);
+    process_console.set_input_queue(&mut capsules::process_console::INPUT_BUF);
+    process_console.set_command_log(&mut capsules::process_console::CMDLOG_BUF);
     process_console.initialize();
 
     // Initialize USART3 for Uart
@@ -292,6 +434,34 @@ pub unsafe fn reset_handler() {
         MuxAlarm::new(&sam4l::ast::AST)
     );
     ast.configure(mux_alarm);
+    board_kernel.set_idle_clock(&sam4l::ast::AST, &main_loop_capability);
+    if cortexm4::dwt::CYCLE_COUNTER.enable() {
+        board_kernel.set_cycle_counter(&cortexm4::dwt::CYCLE_COUNTER, &main_loop_capability);
+    }
+    board_kernel.set_peripherals(PERIPHERALS, &main_loop_capability);
+    board_kernel.set_syscall_trace_buffer(
+        &mut SYSCALL_TRACE_BUF,
+        &main_loop_capability,
+    );
+    process_console.set_reset_cause(&sam4l::pm::PM);
+    process_console.set_uart_status(&sam4l::usart::USART0);
+    process_console.set_brown_out(&sam4l::bscif::BOD33);
+    process_console.set_clock_scaling(&sam4l::pm::PM);
+    sam4l::flashcalw::FLASH_CONTROLLER.configure();
+    // The SAM4L's flash pages are 512 bytes.
+    let app_flash = static_init!(
+        capsules::process_console::AppFlashPages<'static, sam4l::flashcalw::FLASHCALW>,
+        capsules::process_console::AppFlashPages::new(&sam4l::flashcalw::FLASH_CONTROLLER, 512)
+    );
+    hil::flash::HasClient::set_client(&sam4l::flashcalw::FLASH_CONTROLLER, process_console);
+    process_console.set_app_flash(app_flash);
+    process_console.set_alarm_list(mux_alarm);
+    let console_alarm = static_init!(
+        VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+        VirtualMuxAlarm::new(mux_alarm)
+    );
+    console_alarm.set_client(process_console);
+    process_console.set_alarm(console_alarm);
 
     let sensors_i2c = static_init!(MuxI2C<'static>, MuxI2C::new(&sam4l::i2c::I2C1));
     sam4l::i2c::I2C1.set_master_client(sensors_i2c);
@@ -559,8 +729,15 @@ pub unsafe fn reset_handler() {
     // );
     // sam4l::gpio::PA[16].set_client(debug_process_restart);
 
+    let ipc = static_init!(
+        kernel::ipc::IPC,
+        kernel::ipc::IPC::new(board_kernel, &memory_allocation_capability)
+    );
+    process_console.set_ipc(ipc);
+
     let hail = Hail {
         console: console,
+        process_console: process_console,
         gpio: gpio,
         alarm: alarm,
         ambient_light: ambient_light,
@@ -573,7 +750,7 @@ pub unsafe fn reset_handler() {
         led: led,
         button: button,
         rng: rng,
-        ipc: kernel::ipc::IPC::new(board_kernel, &memory_allocation_capability),
+        ipc: ipc,
         crc: crc,
         dac: dac,
     };
@@ -598,6 +775,8 @@ pub unsafe fn reset_handler() {
         kernel::debug::DebugWriterWrapper::new(debugger)
     );
     kernel::debug::set_debug_writer_wrapper(debug_wrapper);
+    kernel::debug::set_debug_log_buffer(&mut kernel::debug::LOG_BUF);
+    kernel::debug::set_debug_writer_client(process_console);
 
     // Reset the nRF and setup the UART bus.
     hail.nrf51822.reset();
@@ -615,7 +794,34 @@ pub unsafe fn reset_handler() {
         ///
         /// This symbol is defined in the linker script.
         static _sapps: u8;
+
+        // Bounds of the kernel and of the flash for apps, also defined in the
+        // linker script, for the process console to report flash usage.
+        static _stext: u8;
+        static _etext: u8;
+        static _srelocate: u8;
+        static _erelocate: u8;
+        static _erom: u8;
+        static _eapps: u8;
     }
+    let flash_layout = static_init!(
+        capsules::process_console::FlashLayout,
+        capsules::process_console::FlashLayout {
+            kernel: (
+                &_stext as *const u8 as usize,
+                &_erom as *const u8 as usize - &_stext as *const u8 as usize,
+            ),
+            kernel_used: (&_etext as *const u8 as usize - &_stext as *const u8 as usize)
+                + (&_erelocate as *const u8 as usize - &_srelocate as *const u8 as usize),
+            apps: (
+                &_sapps as *const u8 as usize,
+                &_eapps as *const u8 as usize - &_sapps as *const u8 as usize,
+            ),
+        }
+    );
+    process_console.set_flash_layout(flash_layout);
+    let serial_num = static_init!([u8; 15], sam4l::serial_num::SerialNum::new().get());
+    process_console.set_hardware_id(serial_num);
 
     kernel::procs::load_processes(
         board_kernel,
@@ -626,5 +832,5 @@ pub unsafe fn reset_handler() {
         FAULT_RESPONSE,
         &process_management_capability,
     );
-    board_kernel.kernel_loop(&hail, chip, Some(&hail.ipc), &main_loop_capability);
+    board_kernel.kernel_loop(&hail, chip, Some(hail.ipc), &main_loop_capability);
 }
@@ -45,6 +45,8 @@ impl Component for ProcessConsoleComponent {
         &'static process_console::ProcessConsole<'static, UartDevice<'static>, Capability>;
 
     unsafe fn finalize(&mut self) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
         // Create virtual device for console.
         let console_uart = static_init!(UartDevice, UartDevice::new(self.uart_mux, true));
         console_uart.setup();
@@ -57,11 +59,14 @@ impl Component for ProcessConsoleComponent {
                 &mut process_console::WRITE_BUF,
                 &mut process_console::READ_BUF,
                 &mut process_console::COMMAND_BUF,
+                &mut process_console::OUTPUT_BUF,
+                self.board_kernel.create_grant(&grant_cap),
                 self.board_kernel,
                 Capability,
             )
         );
         hil::uart::UART::set_client(console_uart, console);
+        console.set_input_queue(&mut process_console::INPUT_BUF);
         console.initialize();
 
         // Create virtual device for kernel debug.
@@ -82,6 +87,7 @@ impl Component for ProcessConsoleComponent {
             kernel::debug::DebugWriterWrapper::new(debugger)
         );
         kernel::debug::set_debug_writer_wrapper(debug_wrapper);
+        kernel::debug::set_debug_log_buffer(&mut kernel::debug::LOG_BUF);
 
         console
     }
@@ -121,11 +121,48 @@ static mut APP_MEMORY: [u8; 16384] = [0; 16384];
 
 static mut PROCESSES: [Option<&'static kernel::procs::ProcessType>; NUM_PROCS] = [None, None];
 
+// Room for the 32 most recent system calls, for the process console's
+// `strace`.
+static mut SYSCALL_TRACE_BUF: [kernel::syscall::SyscallRecord; 32] =
+    [kernel::syscall::SyscallRecord {
+        time: 0,
+        process: 0,
+        syscall: kernel::syscall::Syscall::YIELD,
+    }; 32];
+
 /// Dummy buffer that causes the linker to reserve enough space for the stack.
 #[no_mangle]
 #[link_section = ".stack_buffer"]
 pub static mut STACK_MEMORY: [u8; 0x2000] = [0; 0x2000];
 
+/// The capsules in `Imix`, as listed by the process console's `caps` command.
+static CAPSULES: &'static [(&'static str, &'static str)] = &[
+    ("pconsole", "capsules::process_console::ProcessConsole"),
+    ("console", "capsules::console::Console"),
+    ("gpio", "capsules::gpio::GPIO"),
+    ("alarm", "capsules::alarm::AlarmDriver"),
+    ("temp", "capsules::temperature::TemperatureSensor"),
+    ("humidity", "capsules::humidity::HumiditySensor"),
+    ("ambient_light", "capsules::ambient_light::AmbientLight"),
+    ("adc", "capsules::adc::Adc"),
+    ("led", "capsules::led::LED"),
+    ("button", "capsules::button::Button"),
+    ("rng", "capsules::rng::RngDriver"),
+    ("analog_comparator", "capsules::analog_comparator::AnalogComparator"),
+    ("spi", "capsules::spi::Spi"),
+    ("ipc", "kernel::ipc::IPC"),
+    ("ninedof", "capsules::ninedof::NineDof"),
+    ("radio_driver", "capsules::ieee802154::RadioDriver"),
+    ("udp_driver", "capsules::net::udp::UDPDriver"),
+    ("crc", "capsules::crc::Crc"),
+    ("usb_driver", "capsules::usb_user::UsbSyscallDriver"),
+    ("nrf51822", "capsules::nrf51822_serialization::Nrf51822Serialization"),
+    (
+        "nonvolatile_storage",
+        "capsules::nonvolatile_storage_driver::NonvolatileStorage",
+    ),
+];
+
 struct Imix {
     pconsole: &'static capsules::process_console::ProcessConsole<
         'static,
@@ -147,7 +184,7 @@ struct Imix {
         sam4l::acifc::Acifc<'static>,
     >,
     spi: &'static capsules::spi::Spi<'static, VirtualSpiMasterDevice<'static, sam4l::spi::SpiHw>>,
-    ipc: kernel::ipc::IPC,
+    ipc: &'static kernel::ipc::IPC,
     ninedof: &'static capsules::ninedof::NineDof<'static>,
     radio_driver: &'static capsules::ieee802154::RadioDriver<'static>,
     udp_driver: &'static capsules::net::udp::UDPDriver<'static>,
@@ -183,6 +220,7 @@ impl kernel::Platform for Imix {
     {
         match driver_num {
             capsules::console::DRIVER_NUM => f(Some(self.console)),
+            capsules::process_console::DRIVER_NUM => f(Some(self.pconsole)),
             capsules::gpio::DRIVER_NUM => f(Some(self.gpio)),
             capsules::alarm::DRIVER_NUM => f(Some(self.alarm)),
             capsules::spi::DRIVER_NUM => f(Some(self.spi)),
@@ -201,7 +239,7 @@ impl kernel::Platform for Imix {
             capsules::nrf51822_serialization::DRIVER_NUM => f(Some(self.nrf51822)),
             capsules::nonvolatile_storage_driver::DRIVER_NUM => f(Some(self.nonvolatile_storage)),
             capsules::rng::DRIVER_NUM => f(Some(self.rng)),
-            kernel::ipc::DRIVER_NUM => f(Some(&self.ipc)),
+            kernel::ipc::DRIVER_NUM => f(Some(self.ipc)),
             _ => f(None),
         }
     }
@@ -328,6 +366,14 @@ pub unsafe fn reset_handler() {
 
     let pconsole = ProcessConsoleComponent::new(board_kernel, uart_mux, 115200).finalize();
     let console = ConsoleComponent::new(board_kernel, uart_mux, 115200).finalize();
+    let output_recorder = static_init!(
+        capsules::process_console::OutputRecorder,
+        capsules::process_console::OutputRecorder::new(board_kernel.create_grant(&grant_cap))
+    );
+    console.set_output_client(output_recorder);
+    pconsole.set_output_capture(output_recorder);
+    pconsole.set_capsule_list(CAPSULES);
+    kernel::debug::set_debug_writer_client(pconsole);
 
     // Allow processes to communicate over BLE through the nRF51822
     let nrf_serialization =
@@ -340,6 +386,16 @@ pub unsafe fn reset_handler() {
         MuxAlarm::new(&sam4l::ast::AST)
     );
     ast.configure(mux_alarm);
+    board_kernel.set_idle_clock(&sam4l::ast::AST, &main_cap);
+    board_kernel.set_syscall_trace_buffer(&mut SYSCALL_TRACE_BUF, &main_cap);
+    pconsole.set_reset_cause(&sam4l::pm::PM);
+    pconsole.set_alarm_list(mux_alarm);
+    let console_alarm = static_init!(
+        VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+        VirtualMuxAlarm::new(mux_alarm)
+    );
+    console_alarm.set_client(pconsole);
+    pconsole.set_alarm(console_alarm);
     let alarm = AlarmDriverComponent::new(board_kernel, mux_alarm).finalize();
 
     // # I2C and I2C Sensors
@@ -425,6 +481,12 @@ pub unsafe fn reset_handler() {
         mux_alarm,
     ).finalize();
 
+    let ipc = static_init!(
+        kernel::ipc::IPC,
+        kernel::ipc::IPC::new(board_kernel, &grant_cap)
+    );
+    pconsole.set_ipc(ipc);
+
     let imix = Imix {
         pconsole,
         console,
@@ -440,7 +502,7 @@ pub unsafe fn reset_handler() {
         analog_comparator,
         crc,
         spi: spi_syscalls,
-        ipc: kernel::ipc::IPC::new(board_kernel, &grant_cap),
+        ipc,
         ninedof,
         radio_driver,
         udp_driver,
@@ -483,5 +545,5 @@ pub unsafe fn reset_handler() {
         &process_mgmt_cap,
     );
 
-    board_kernel.kernel_loop(&imix, chip, Some(&imix.ipc), &main_cap);
+    board_kernel.kernel_loop(&imix, chip, Some(imix.ipc), &main_cap);
 }
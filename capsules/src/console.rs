@@ -56,6 +56,13 @@ pub struct App {
     read_len: usize,
 }
 
+/// Gets a copy of everything apps write to the console, for example to keep
+/// their recent output for the process console.
+pub trait OutputClient {
+    /// `app` wrote `bytes`.
+    fn app_output(&self, app: AppId, bytes: &[u8]);
+}
+
 pub static mut WRITE_BUF: [u8; 64] = [0; 64];
 pub static mut READ_BUF: [u8; 64] = [0; 64];
 
@@ -67,6 +74,7 @@ pub struct Console<'a, U: UART> {
     rx_in_progress: OptionalCell<AppId>,
     rx_buffer: TakeCell<'static, [u8]>,
     baud_rate: u32,
+    output_client: OptionalCell<&'a OutputClient>,
 }
 
 impl<U: UART> Console<'a, U> {
@@ -85,9 +93,15 @@ impl<U: UART> Console<'a, U> {
             rx_in_progress: OptionalCell::empty(),
             rx_buffer: TakeCell::new(rx_buffer),
             baud_rate: baud_rate,
+            output_client: OptionalCell::empty(),
         }
     }
 
+    /// Give `client` a copy of everything apps write.
+    pub fn set_output_client(&self, client: &'a OutputClient) {
+        self.output_client.set(client);
+    }
+
     pub fn initialize(&self) {
         self.uart.configure(uart::UARTParameters {
             baud_rate: self.baud_rate,
@@ -141,6 +155,10 @@ impl<U: UART> Console<'a, U> {
                     }
                     buffer[i] = *c;
                 }
+                self.output_client.map(|client| {
+                    let len = cmp::min(app.write_remaining, buffer.len());
+                    client.app_output(app_id, &buffer[..len]);
+                });
 
                 // Check if everything we wanted to print
                 // fit in the buffer.
@@ -27,6 +27,7 @@ pub enum NUM {
     NVM_STORAGE = 0x50001,
     NRF51822_SERIALIZATION = 0x80004,
     PCA9544A = 0x80002,
+    PROCESS_CONSOLE = 0x90000,
     RNG = 0x40001,
     SD_CARD = 0x50002,
     SPI = 0x20001,
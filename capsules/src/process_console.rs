@@ -5,12 +5,364 @@
 //! --------
 //!
 //! This module provides a simple text-based console to inspect and control
-//! which processes are running. The console has five commands:
+//! which processes are running. The console has the following commands:
 //!  - 'help' prints the available commands and arguments
+//!  - 'menu' prints the available commands numbered, then runs the one whose
+//!    number is entered on the next line. A command whose first argument
+//!    names a process is instead put on the command line for the process to
+//!    be typed after it. An invalid number prints the menu again, and an
+//!    empty line leaves it.
 //!  - 'status' prints the current system status
+//!  - 'dumpstate' prints everything the console knows about the state of the
+//!    system as one report to paste into a bug report, in sections with
+//!    headers: the kernel's status and why the chip last reset, each
+//!    process's state, fault policy and restart count, and how each process's
+//!    memory is used. The registers of a faulted process are only printed by
+//!    the kernel at the time of the fault, so they are not part of it.
 //!  - 'list' lists the current processes with their IDs and running state
-//!  - 'stop n' stops the process with name n
-//!  - 'start n' starts the stopped process with name n
+//!  - 'stop n' stops the process with name or ID n
+//!  - 'start n' starts the stopped process with name or ID n
+//!  - 'restart faulted' restarts every process that is in the fault state,
+//!    leaving the others alone
+//!  - 'restarts [n]' prints how many times each process, or the process with
+//!    name or ID n, has been restarted since boot, for example
+//!    `blink: 3 restarts`. This counts every restart, whether by the console,
+//!    by the process's fault policy or by anything else that restarts it.
+//!    'restarts clear' starts counting from zero again.
+//!  - 'faultpolicy n [panic|stop|restart]' prints or changes how the kernel
+//!    responds when the process with name or ID n faults
+//!  - 'compact n' reports how fragmented the grant region of the process with
+//!    name or ID n is. Grants are allocated downwards from the end of process
+//!    memory and never freed, so the free space between the heap and the
+//!    grants is always a single block and there is nothing to compact.
+//!  - 'grantaudit' reports which grants have allocated memory: for each
+//!    process how many of the grants it has allocated and how large its grant
+//!    region is, then for each grant, by the index it was created with, how
+//!    many processes it is allocated in, and finally the totals. Grants are
+//!    only allocated once a capsule uses them for a process, so no grant is
+//!    allocated but unused; the report shows which capsules hold memory in
+//!    which processes.
+//!  - 'grantmap' shows how each process's grant region is laid out, from its
+//!    lowest address up: the memory each grant allocated, by the index it was
+//!    created with, with its offset into the region and its size, memory
+//!    allocated other than by a grant, and the kernel's own data for the
+//!    process at the top. A grant's size runs up to the next allocation
+//!    above it, so it includes any padding.
+//!  - 'set' prints the current settings, and 'set name value' changes one:
+//!    - 'width n': the terminal's width for wrapping output (80; 0 for none)
+//!    - 'separator c|none': separates commands on one line (`;`)
+//!    - 'prefix c|none': typed lines must start with c to be run (none)
+//!    - 'rxchunk n': how many bytes each UART receive asks for (1)
+//!    - 'color on|off': ANSI colors in `list` (off)
+//!    - 'raw on|off': raw input for host tools, without echo or editing (off)
+//!    - 'echo on|off': echo what is typed (on)
+//!    - 'maskchar c|none': shown for each character typed with echo off (none)
+//!    - 'emptyline newline|ignore': what entering an empty line does (newline)
+//!    - 'maxrate n': most bytes of output a second, needs `set_alarm` (0, none)
+//!    - 'confirmtimeout ms': how long confirmation waits (5000; 0 for ever)
+//!    - 'statusline on|off': a `#STATUS` line before every prompt (off)
+//!    - 'debugparse on|off': hex of unrecognized commands (off)
+//!    - 'coalesce on|off': hold debug output while a command runs (off)
+//!    - 'keymode single|line': single keys run commands (line)
+//!    - 'typeahead on|off': keep the key that ends a watch (off)
+//!    - 'resultline on|off': a `#RESULT` line after every command line (off)
+//!  - 'tail n' prints the most recent output the process with name or ID n
+//!    wrote to the console capsule, if the board registered something that
+//!    captures it, such as an `OutputRecorder`, with `set_output_capture`.
+//!    Non-printable characters are escaped.
+//!  - 'timers' lists the armed virtual alarms, if the board registered its
+//!    alarm multiplexer with `set_alarm_list`, as the time until each fires
+//!    and the address of the alarm's client, for example `+1234ms  0x20001a3c`.
+//!  - 'idle' prints the share of time the kernel has spent asleep, for example
+//!    `Idle: 87.3%`, if the board gave the kernel an idle clock. 'idle clear'
+//!    restarts the measurement.
+//!  - 'quantum [ms]' prints or sets how many milliseconds (1-1000) a process
+//!    may run before the kernel pre-empts it
+//!  - 'snapshot' records the state and counters of every process, and 'diff'
+//!    prints what has changed since, such as restarts, syscalls made, heap
+//!    growth and state changes, one process per line
+//!  - 'ipc n' prints whether the process with name or ID n provides an IPC
+//!    service and which processes are connected to it as clients, if the
+//!    board registered its IPC driver with `set_ipc`
+//!  - 'callbacks n' prints the callbacks queued for the process with name or
+//!    ID n that it has not yet run, one line per function they call with how
+//!    many are queued, followed by queued IPC notifications. The kernel does
+//!    not record which driver scheduled a callback, so they are told apart by
+//!    the function in the process they call. 'callbacks n clear' drops all of
+//!    them once confirmed.
+//!  - 'perf n' prints how many processor cycles the process with name or ID n
+//!    has run for since boot, with thousands separated by commas, if the
+//!    board gave the kernel a cycle counter with `Kernel::set_cycle_counter`,
+//!    such as the Cortex-M DWT cycle counter. The cycles the kernel spends
+//!    handling the process's system calls are not counted. Instructions are
+//!    not counted, since no counter the kernel uses counts them.
+//!  - 'args n' prints the entry point of the process with name or ID n and the
+//!    arguments the kernel starts it with: the start of its code in flash,
+//!    the start and length of its memory, and its initial memory break
+//!  - 'txqueue' prints how many bytes are waiting in the output queue out of
+//!    its size, the most there have been since boot, and how many bytes of
+//!    output have been dropped because the queue was full, for example
+//!    `Output queue: 12/1024 bytes, peak 530, 0 dropped`. 'txqueue clear'
+//!    resets the peak and the dropped count.
+//!  - 'uartstat' prints, for each UART the console uses, its configuration,
+//!    whether the console is transmitting or receiving on it, how many bytes
+//!    it has sent and received, how many transfers ended in an error and the
+//!    last error. If the board gave the console the hardware UART with
+//!    `set_uart_status`, it also prints the transfers the driver has in
+//!    progress; when the UART is shared, these may be another capsule's.
+//!  - 'bod' prints the threshold of the brown-out detector the board gave the
+//!    console with `set_brown_out`, in the chip's own units with the range it
+//!    accepts, its raw control register, and whether the supply has dropped
+//!    below the threshold since the chip reset. 'bod n' changes the
+//!    threshold to n.
+//!  - 'resetreason' prints why the chip last reset, such as `Watchdog` or
+//!    `BrownOut`, along with the raw reset cause register, if the board gave
+//!    the console its chip's reset cause with `set_reset_cause`.
+//!    'resetreason clear' clears the latched causes on chips that allow it.
+//!  - 'prio n [value]' would print or change the scheduling priority of the
+//!    process with name or ID n, but the kernel's scheduler is round-robin and
+//!    has no priorities, so it reports `Not applicable`
+//!  - 'run name' runs the steps of the command chain called name, one of
+//!    those the board registered with `set_chains`, one after another as if
+//!    they had been pasted, once the line it is on has finished. A step that
+//!    fails, such as one naming no process or printing its usage, stops the
+//!    chain, unless the step starts with `-`, which marks it as optional.
+//!    'run' alone lists the chains with their steps.
+//!  - 'runq' prints the order the scheduler runs processes in, which is
+//!    round-robin in process ID order, one line per process with its state,
+//!    marking the process most recently switched to with `*`. Only running
+//!    processes are given the processor; the others are passed over until a
+//!    callback is ready for them.
+//!  - 'watch n' prints a line each time the process with name or ID n
+//!    changes state, such as from running to faulted, until any key is
+//!    pressed. It checks ten times a second, so it needs the board to give the
+//!    console an alarm with `set_alarm`.
+//!  - 'flashdump n [len]' hex dumps the first `len` bytes, 256 unless given,
+//!    of the flash of the process with name or ID n, starting with its TBF
+//!    header, in the same format as `memdump`
+//!  - 'dmesg' prints the kernel debug output kept in the debug log, oldest
+//!    first, if the board set one with `kernel::debug::set_debug_log_buffer`,
+//!    so messages printed before a terminal was connected can still be read.
+//!    Lines are not timestamped. 'dmesg clear' empties the log.
+//!  - 'strace [n] [p]' prints the last n system calls, or all that were kept,
+//!    made by any process or only by the process with name or ID p, oldest
+//!    first, one per line: when it was made in ticks of the kernel's idle
+//!    clock, the index of the process and the call with its arguments, for
+//!    example `     81234   2  command 0x1 1 0 0`. It needs the board to give
+//!    the kernel a buffer to record calls in with
+//!    `Kernel::set_syscall_trace_buffer`.
+//!  - 'defcalls' prints which deferred calls are pending, as a bit mask, and
+//!    for each deferred call task that has been used since boot how many
+//!    times it was set and how many times the chip ran it, for example
+//!    `Task  0: set 12, run 12`. Tasks are numbered as in the chip's deferred
+//!    call task list. 'defcalls clear' resets the counts.
+//!  - 'adc n' takes a single sample on channel n of the ADC the board gave
+//!    the console with `set_adc`, and prints the raw value and, if the ADC
+//!    knows its reference voltage, the voltage in millivolts, for example
+//!    `Channel 0: 2048 (1650mV)`.
+//!  - 'temp' reads the temperature sensor the board gave the console with
+//!    `set_temperature` and prints the temperature in degrees Celsius, for
+//!    example `Temperature: 23.45 C`. 'temp watch' prints a reading every
+//!    second until any key is pressed, which needs the board to give the
+//!    console an alarm with `set_alarm`.
+//!  - 'date' reads the real-time clock the board gave the console with
+//!    `set_date_time` and prints the date and time, for example
+//!    `2018-08-16 14:03:27`. 'date set YYYY-MM-DD HH:MM:SS' sets the clock,
+//!    after checking that the date exists, and then reads it back to show
+//!    that it took.
+//!  - 'clock' prints the frequency the CPU runs at, if the board gave the
+//!    console a clock it can scale with `set_clock_scaling`, and 'clock f'
+//!    switches it to f Hz, one of the frequencies the clock lists when given
+//!    another. The switch waits until the console's output has been sent,
+//!    and the console then configures its UARTs again so they keep the same
+//!    baud rate. Other peripherals that divide down the CPU clock are not
+//!    configured again.
+//!  - 'logflush' makes the log storage the board gave the console with
+//!    `set_log_storage` write any entries it has buffered to flash, and
+//!    'logrotate' makes it start a new log segment. Each prints when the log
+//!    reports that it is done, so the log is known to be durable before a
+//!    planned reset.
+//!  - 'probe i2c' scans the I2C bus for devices, if the board gave the
+//!    console a device on the bus to scan with using `set_i2c`, by reading a
+//!    byte from each address from 0x08 to 0x77. It prints each address that
+//!    answers, for example `Device at 0x40`, then how many did. 'probe spi'
+//!    asks the SPI device the board gave the console with `set_spi` for its
+//!    JEDEC ID, as SPI flash chips report it, for example
+//!    `SPI ID: ef 40 18`.
+//!  - 'irqtest [n]' measures how late alarm callbacks run. It sets `n`
+//!    alarms (8 unless given, at most 64) one after another, each 1ms ahead,
+//!    and prints the least, most and average time between when each alarm was
+//!    due and when its callback ran, to within one alarm tick. It needs the
+//!    board to give the console an alarm with `set_alarm`.
+//!  - 'time c' runs the command c and then prints how long it took, for
+//!    example `Elapsed: 3ms`. For commands that carry on printing after they
+//!    start, such as `memdump`, that is until their output is finished. It
+//!    needs the board to give the console an alarm with `set_alarm`.
+//!  - 'exit' or 'quit' ends the session: the console says goodbye and then
+//!    ignores input until the next byte it receives, which brings back the
+//!    banner and the prompt
+//!  - 'errors' reports whether each UART is receiving normally or has stopped
+//!    after repeated receive errors, and whether its transmit has stalled
+//!  - 'caps' lists the capsules the board registered with `set_capsule_list`,
+//!    one per line with its name and type.
+//!  - 'checkdriver num [command]' says whether driver number num is among
+//!    the drivers the board registered with `set_driver_list`, for example
+//!    `Driver 0x6 (GPIO): available, command 2: allowed`. Apps calling a
+//!    driver that is not registered get `ENODEVICE`. The kernel does not
+//!    filter system calls, so every command to an available driver reaches
+//!    it; an `ENOSUPPORT` comes from the driver itself.
+//!  - 'mounts' lists the storage volumes the board registered with
+//!    `set_storage_volumes`, one per line with its name, where it starts, its
+//!    size and, for volumes that keep track of it, how much of it is free.
+//!  - 'cmdlog' prints the most recent commands run, oldest first, if the board
+//!    gave the console a buffer to log them in with `set_command_log`: when
+//!    each ran in milliseconds on the console's alarm, if it has one, where it
+//!    came from (a UART, the startup script, an app or, for lines without
+//!    the command prefix, the unknown command handler) and the command, cut
+//!    short after 32 characters. It is a record of what was actually run,
+//!    however it was started. 'cmdlog clear' empties it.
+//!  - 'flashinfo' reports how the flash is used, if the board gave the
+//!    console its layout with `set_flash_layout`: how much of the kernel's
+//!    region the kernel uses, how much of the applications' region the loaded
+//!    processes use and how much is left after the last of them for new apps,
+//!    and a summary line with the totals, all in KB.
+//!  - 'hwid' prints the chip's unique identifier in hex, such as the serial
+//!    number programmed in the factory, if the board gave it to the console
+//!    with `set_hardware_id`, and otherwise `unavailable`
+//!  - 'gpio n read|high|low|toggle' reads or drives the n-th of the GPIO pins
+//!    the board registered with `set_gpio_pins`. `read` prints the pin's level
+//!    without changing its configuration; the others make it an output first.
+//!    'gpio' prints how many pins there are.
+//!  - 'dbggpio' prints whether each of the kernel's debug GPIOs, toggled by
+//!    `debug_gpio!` in kernel code for timing with an oscilloscope, has a pin
+//!    and is on. 'dbggpio n on|off' turns driving debug GPIO n on or off, so
+//!    trace points can be chosen without rebuilding the kernel.
+//!  - 'features' lists the optional features of the capsules crate, one per
+//!    line, marked `[x]` if this build has it and `[ ]` if not
+//!  - 'describe' prints a single line of JSON describing the console, for host
+//!    tools to discover what this build supports. For example:
+//!    `{"version":1,"commands":["help",...],"features":{"debug_commands":false},
+//!    "buffers":{"command":32,"app_command":32,"output":1024}}`. The `version`
+//!    field changes whenever the meaning of existing fields does; new fields
+//!    may be added without changing it.
+//!
+//! When the `debug_commands` feature of the capsules crate is enabled, the
+//! console also supports:
+//!  - 'memdump addr len' hex dumps `len` bytes of memory starting at address
+//!    `addr`. The address must be word aligned, the length at most
+//!    256 bytes, and the whole range must lie within a process's flash, its
+//!    RAM below the app break, or a region the board registered with the
+//!    kernel's `set_readable_regions`.
+//!  - 'mpu [on|off]' prints or changes whether processes run with the MPU
+//!    enabled. Turning it off removes all memory protection, so the console
+//!    asks for confirmation first; it is only meant for telling whether a
+//!    fault is caused by the MPU configuration.
+//!  - 'send b...' transmits the given bytes, each written as a number such
+//!    as `0x1b` or `27`, over the UART as they are, for testing whatever parses
+//!    the console's output.
+//!  - 'injecterror framing|overrun|parity|break' makes the console handle
+//!    the next receive from the UART as if it had failed with that error, to
+//!    exercise its error handling without hardware that causes one. The bytes
+//!    received are handled as that error would have them handled. It does not
+//!    change the state of the UART itself.
+//!  - 'memlimit n [bytes|none]' prints or sets a limit on the memory the
+//!    process with name or ID n may use, its own memory and its grants
+//!    together, for reproducing low-memory conditions. The kernel fails grant
+//!    allocations that would take the process over the limit. A limit below
+//!    what the process already uses is rejected, and 'none' removes it.
+//!  - 'reg p offset' reads the 32-bit register at byte offset `offset` of the
+//!    peripheral named p, one of those the board registered with the
+//!    kernel's `set_peripherals`, and prints it in hex. Only registers of a listed
+//!    peripheral can be read. Bitfields the board described for the register
+//!    are printed after it, one per line.
+//!  - 'sched pause' stops the kernel from running any process, after asking
+//!    for confirmation, freezing every process where it is while other
+//!    commands inspect them. The kernel and the console keep running.
+//!    'sched resume' lets processes run again, and 'sched' prints whether
+//!    the scheduler is paused.
+//!  - 'erase n' erases the flash the stopped process with name or ID n was
+//!    loaded from, after asking for confirmation, using the app flash the
+//!    board gave the console with `set_app_flash`. The process is left
+//!    faulted so it cannot run again, and `restart faulted` passes over it;
+//!    it stays in the process list until the board resets. Since the kernel
+//!    stops loading apps at the first slot without one, apps after it are
+//!    not loaded either until a new app is written to the slot.
+//!
+//! Numbers given to commands, such as process indices, addresses and byte
+//! values, are read as decimal unless they start with `0x`, so `stop 2` and
+//! `stop 0x2` are the same command.
+//!
+//! Several commands can be given on one line, separated by `;`, as in
+//! `stop 2; start 3; list`. They run one after another, each once the one
+//! before it has finished printing, and empty commands between separators are
+//! skipped. Commands run by apps are not split.
+//!
+//! Ending a command with `|` and some text, as in `list | blink`, prints only
+//! the lines of its output that contain that text. There can only be one such
+//! filter, of at most 16 characters.
+//!
+//! A command can be continued onto the next line by ending the line with a
+//! backslash, for example `stop \` followed by `blink`. The console then prints
+//! a `> ` prompt and keeps appending to the same command. Backspace, sent as
+//! either BS (0x08) or DEL (0x7f), only erases characters on the current line.
+//!
+//! Pressing Tab completes the word being typed. The first word of a command is
+//! completed with command names, and the word after a command that takes a
+//! process, such as `stop`, with the names of the processes. When several
+//! names fit, the word is completed as far as they agree, and pressing Tab
+//! again lists them. When none fit, the console rings the bell.
+//!
+//! Pressing Ctrl-L or Ctrl-R while typing a command prints the prompt and what
+//! has been typed so far again on a new line, for when other output, such as
+//! debug messages, has been printed over it.
+//!
+//! A board can give the console a startup script, such as a region of flash
+//! holding newline-separated commands, with `set_startup_script`. When the
+//! console starts it runs each line as if it had been typed, carrying on past
+//! lines that fail. The script ends at the first 0x00 or 0xFF byte, so erased
+//! flash holds an empty script, and only its first 256 bytes are run.
+//!
+//! Input that arrives while a command is running is normally discarded. A
+//! board can instead give the console a buffer to queue it in with
+//! `set_input_queue`, for example `INPUT_BUF`, so that a pasted block of
+//! commands runs one line after another. Queued input is handled as if it
+//! were typed once the command before it completes. Input that does not fit
+//! in the queue is dropped, and the console says how much once the command
+//! running at the time completes.
+//!
+//! On boards whose receive line picks up noise before a terminal is
+//! connected, `set_settle_time` makes the console discard what it receives
+//! until the line has been quiet for a while after starting, so that the noise
+//! does not end up in the first command. Once the line has settled the console
+//! says how many bytes it discarded.
+//!
+//! A host tool that sends a command as soon as it connects would see the
+//! banner and prompt mixed in with the echo of its command. With
+//! `set_host_detection`, the console waits a while after starting before
+//! printing them; input arriving in that time is taken to come from a tool,
+//! and the console switches to raw input instead, as with `set raw on`.
+//!
+//! A serial break resets the console: whatever has been typed or queued is
+//! discarded, output from a command still printing is cut short, the rest of
+//! the startup script is skipped, and the prompt is printed again.
+//! Listings cut short this way, from 'dmesg', 'strace', 'cmdlog', 'memdump',
+//! 'dumpstate', 'grantaudit' and 'grantmap', can be continued from where
+//! they stopped with 'resume', even after running other commands, as long as
+//! no other break came in between and, if the board gave the console an
+//! alarm with `set_alarm`, no more than a minute has passed.
+//!
+//! A board can pass commands the console does not recognize on to something
+//! else, such as an application, with `set_unknown_command_handler`. Commands
+//! it does not handle either, or all unrecognized commands if there is no
+//! handler, are answered with the commands they are a prefix of, for example
+//! `Unknown command 'lis'. Did you mean 'list'?`.
+//!
+//! When the UART is shared with another protocol, `set prefix c` makes the
+//! console only run typed lines that start with the character c, as in
+//! `#list` for `set prefix #`. Other lines are handed whole to the unknown
+//! command handler, if there is one, and otherwise ignored. `set prefix none`,
+//! the default, runs every line. Commands from the startup script and from
+//! apps never need the prefix.
 //!
 //! Setup
 //! -----
@@ -29,20 +381,97 @@
 //!                  &mut console::WRITE_BUF,
 //!                  &mut console::READ_BUF,
 //!                  &mut console::COMMAND_BUF,
+//!                  &mut console::OUTPUT_BUF,
+//!                  kernel.create_grant(&grant_cap),
 //!                  kernel,
 //!                  Capability);
 //! hil::uart::UART::set_client(&usart::USART0, pconsole);
 //!
-//! pconsole.initialize();
+//! if pconsole.initialize() != ReturnCode::SUCCESS {
+//!     // A UART rejected its configuration, for example the baud rate, so
+//!     // the console cannot be used at that speed.
+//! }
+//!
+//! // After the kernel debug writer has been set up:
+//! kernel::debug::set_debug_writer_client(pconsole);
 //! pconsole.start();
 //! ```
 //!
 //! Buffer use and output
 //! ---------------------
-//! `ProcessConsole` does not use its own write buffer for output:
-//! it uses the debug!() buffer, so as not to repeat all of its buffering and
-//! to maintain a correct ordering with debug!() calls. The write buffer of
-//! `ProcessConsole` is used solely for echoing what someone types.
+//! Command output, the echo of what someone types and the prompt all go into
+//! the output queue (`OUTPUT_BUF`), which is transmitted a write buffer at a
+//! time. Commands with long output, such as `memdump`, produce it a line at a
+//! time as the queue drains so that it never overflows. Output longer than a
+//! write buffer is split across as many transmissions as it takes, in order,
+//! and all of it goes to the same UART even if input arrives on the other one
+//! in the meantime.
+//!
+//! Because the console and the debug writer share the UART, kernel debug
+//! output can land in the middle of a line being typed. To keep the two from
+//! interleaving, the console does not transmit while the debug writer has
+//! output pending. Once the debug writer drains (the console must be
+//! registered with `kernel::debug::set_debug_writer_client`), the console
+//! carries on, reprinting the prompt followed by whatever has been typed so
+//! far if the debug output was printed after them. When a command is entered
+//! the console always ends the line with `\r\n` before running it, so the
+//! command's output starts on a line of its own whatever the terminal does
+//! with the Enter key.
+//!
+//! Output is only ever dropped a whole line at a time. When a command's output
+//! was cut short, because it did not fit in the output queue or in an app's
+//! output buffer or because a serial break interrupted it, the console ends
+//! it with a `...[truncated]` line so that partial output is not mistaken for
+//! complete output.
+//!
+//! Fallback UART
+//! -------------
+//! A board can give the console a second UART of the same type, for example
+//! when the primary one may be unplugged:
+//!
+//! ```rust
+//! pconsole.set_fallback_uart(&usart::USART1,
+//!                            &mut console::FALLBACK_WRITE_BUF,
+//!                            &mut console::FALLBACK_READ_BUF);
+//! hil::uart::UART::set_client(&usart::USART1, pconsole);
+//! ```
+//!
+//! The console listens on both, and sends its output to whichever one last
+//! received input. Kernel debug output still goes only to the debug writer's
+//! UART.
+//!
+//! A UART can stop completing transmissions, for example when another capsule
+//! has taken over as its client. If the board gave the console an alarm with
+//! `set_alarm`, the console notices when a transmission has not completed a
+//! second after it started by the time the next command runs. It then stops
+//! waiting for that UART, so that output to the other UART is not held up,
+//! prints `UART n unavailable`, and `errors` reports the UART's transmit as
+//! stalled. Output for the stalled UART stays queued until it hands back its
+//! buffer.
+//!
+//! Running commands from userspace
+//! -------------------------------
+//!
+//! `ProcessConsole` is also a syscall driver, so that an app can run console
+//! commands and parse their output, for example to inspect the output of
+//! `list`:
+//!
+//! ```c
+//! // Share the command text and a buffer for its output
+//! allow(PROCESS_CONSOLE_DRIVER_NUM, 1, "list", 4);
+//! allow(PROCESS_CONSOLE_DRIVER_NUM, 2, output, output_len);
+//! // Called with the number of bytes of output once the command finishes
+//! subscribe(PROCESS_CONSOLE_DRIVER_NUM, 1, my_callback);
+//! command(PROCESS_CONSOLE_DRIVER_NUM, 1, 4);
+//! ```
+//!
+//! The output of a command run this way is written into the app's buffer, one
+//! line per `\n`, instead of the UART. Output that does not fit is dropped.
+//!
+//! Apps can only run commands that report on the system without changing it,
+//! such as 'list', 'status' and 'help', and only in their reporting forms, so
+//! 'callbacks n' can be run but not 'callbacks n clear'. Any other command
+//! returns `ENOSUPPORT` without running.
 //!
 //! Using ProcessConsole
 //! --------------------
@@ -63,7 +492,6 @@
 //! Using "/dev/cu.usbserial-c098e513000c - Hail IoT Module - TockOS"
 //!
 //! Listening for serial output.
-//! ProcessConsole::start
 //! Starting process console
 //! Initialization complete. Entering main loop
 //! Hello World!
@@ -89,257 +517,7427 @@
 //! Process blink stopped
 //! ```
 
+use console;
 use core::cell::Cell;
 use core::cmp;
+use core::fmt::{self, Write};
 use core::str;
+use driver;
 use kernel::capabilities::ProcessManagementCapability;
-use kernel::common::cells::TakeCell;
+use kernel::common::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::common::deferred_call;
+use kernel::common::{Queue, RingBuffer};
+use kernel::debug;
+use kernel::hil::adc;
+use kernel::hil::brown_out::BrownOutDetector;
+use kernel::hil::clock::ClockScaling;
+use kernel::hil::date_time::{DateTime, DateTimeClient, DateTimeValues};
+use kernel::hil::flash;
+use kernel::hil::gpio;
+use kernel::hil::i2c::{self, I2CClient, I2CDevice};
+use kernel::hil::reset::ResetCause;
+use kernel::hil::sensors::{TemperatureClient, TemperatureDriver};
+use kernel::hil::spi::{SpiMasterClient, SpiMasterDevice};
+use kernel::hil::time::{self, Alarm, Frequency};
 use kernel::hil::uart::{self, Client, UART};
 use kernel::introspection::KernelInfo;
+use kernel::ipc::IPC;
+use kernel::procs::{FaultResponse, ProcessType, State, Task};
+use kernel::syscall::Syscall;
 use kernel::Kernel;
 use kernel::ReturnCode;
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, Shared};
+use virtual_i2c;
+
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = driver::NUM::PROCESS_CONSOLE as usize;
+
+// Print a line of command output to wherever the current command's output is
+// directed.
+macro_rules! output {
+    ($console:expr, $($arg:tt)+) => ({
+        $console.output_line(format_args!($($arg)+))
+    });
+}
 
-// Since writes are character echoes, we do not need more than 4 bytes:
-// the longest write is 3 bytes for a backspace (backspace, space, backspace).
-pub static mut WRITE_BUF: [u8; 4] = [0; 4];
-// Since reads are byte-by-byte, to properly echo what's typed,
-// we can use a very small read buffer.
+// Print a line saying why the command being run failed, and mark it as
+// failed, which stops a `run` chain.
+macro_rules! fail {
+    ($console:expr, $($arg:tt)+) => ({
+        $console.command_failed.set(true);
+        $console.result_failed.set(true);
+        output!($console, $($arg)+)
+    });
+}
+
+/// Like `fail!`, for a command that failed with the error `code`, which the
+/// result line reports.
+macro_rules! fail_code {
+    ($console:expr, $code:expr, $($arg:tt)+) => ({
+        $console.result_code.set(Some($code));
+        fail!($console, $($arg)+)
+    });
+}
+
+// Output is transmitted from the output queue a write buffer at a time.
+pub static mut WRITE_BUF: [u8; 64] = [0; 64];
+// Since reads are byte-by-byte unless changed with `set rxchunk`, to properly
+// echo what's typed, we can use a very small read buffer.
 pub static mut READ_BUF: [u8; 4] = [0; 4];
+// Buffers for the optional fallback UART.
+pub static mut FALLBACK_WRITE_BUF: [u8; 64] = [0; 64];
+pub static mut FALLBACK_READ_BUF: [u8; 4] = [0; 4];
+// Output waiting to be transmitted. It must hold at least a full line of
+// output plus the prompt and a full command; the longest line is the one
+// `describe` prints.
+pub static mut OUTPUT_BUF: [u8; 1024] = [0; 1024];
+// Input received while a command runs, if the board sets it as the input
+// queue.
+pub static mut INPUT_BUF: [u8; 128] = [0; 128];
 // Commands can be up to 32 bytes long: since commands themselves are 4-5
 // characters, limiting arguments to 25 bytes or so seems fine for now.
 pub static mut COMMAND_BUF: [u8; 32] = [0; 32];
+// Buffers for `probe` to read devices on the I2C and SPI buses into.
+pub static mut I2C_PROBE_BUF: [u8; 1] = [0; 1];
+pub static mut SPI_PROBE_WRITE_BUF: [u8; 4] = [0; 4];
+pub static mut SPI_PROBE_READ_BUF: [u8; 4] = [0; 4];
+// The most recent commands run, if the board sets it as the command log.
+pub static mut CMDLOG_BUF: [CommandLogEntry; 16] = [COMMAND_LOG_EMPTY; 16];
 
-pub struct ProcessConsole<'a, U: UART, C: ProcessManagementCapability> {
-    uart: &'a U,
-    tx_in_progress: Cell<bool>,
-    tx_buffer: TakeCell<'static, [u8]>,
-    rx_in_progress: Cell<bool>,
-    rx_buffer: TakeCell<'static, [u8]>,
-    baud_rate: u32,
-    command_buffer: TakeCell<'static, [u8]>,
-    command_index: Cell<usize>,
-    running: Cell<bool>,
-    kernel: &'static Kernel,
-    capability: C,
+const PROMPT: &'static [u8] = b"tock$ ";
+// Printed when the console starts, unless it is quiet.
+const BANNER: &'static str = "Starting process console";
+// Printed after output that was cut short.
+const TRUNCATED: &'static str = "...[truncated]";
+// Printed instead of the prompt while a command is continued onto another line
+// with a trailing backslash.
+const CONTINUATION_PROMPT: &'static [u8] = b"> ";
+
+// Longest region `memdump` will print, and how many bytes go on each line.
+const MEMDUMP_MAX_LEN: usize = 256;
+const MEMDUMP_LINE_LEN: usize = 16;
+
+// Commands an app runs can be at most as long as those typed on the UART.
+const APP_COMMAND_LEN: usize = 32;
+
+// Room a command buffer needs for arguments after the longest command name,
+// such as a process name and a short option. Smaller buffers get a warning.
+const ARGUMENTS_LEN: usize = 16;
+
+// Most whitespace-separated tokens, including the command name, a command
+// may have unless the board changes it with `set_max_tokens`.
+const DEFAULT_MAX_TOKENS: usize = 8;
+
+// Range of time slices `quantum` accepts, in milliseconds.
+const QUANTUM_MIN_MS: u32 = 1;
+const QUANTUM_MAX_MS: u32 = 1000;
+
+// Longest startup script that is run.
+const SCRIPT_MAX_LEN: usize = 256;
+
+// How many processes `snapshot` records.
+const SNAPSHOT_LEN: usize = 8;
+
+// After this many receives in a row fail, the console stops re-arming the
+// receive straight away and retries when it next has output to send.
+const RX_MAX_FAILURES: usize = 3;
+
+// How many alarms `irqtest` sets unless told otherwise, and at most.
+const IRQTEST_DEFAULT_SAMPLES: usize = 8;
+const IRQTEST_MAX_SAMPLES: usize = 64;
+
+// Longest line of the debug log `dmesg` prints; longer ones are split.
+const DMESG_LINE_LEN: usize = 80;
+
+// How long a transmission may take before the console stops waiting for it.
+const TX_STALL_MS: u32 = 1000;
+
+// How often `watch` checks the state of the process it watches.
+const WATCH_PERIOD_MS: u32 = 100;
+
+// How long `resume` can continue a listing after a break cut it short.
+const RESUME_KEEP_MS: u32 = 60000;
+
+// How often `temp watch` reads the temperature.
+const TEMP_WATCH_PERIOD_MS: u32 = 1000;
+
+// Most kinds of queued task `callbacks` counts separately.
+const CALLBACK_KINDS: usize = 8;
+
+// The I2C addresses `probe i2c` scans, leaving out those the bus reserves.
+const I2C_PROBE_FIRST: u8 = 0x08;
+const I2C_PROBE_LAST: u8 = 0x77;
+// The command that asks a SPI flash for its JEDEC ID.
+const SPI_READ_ID: u8 = 0x9f;
+
+// How long a question asked by `confirm` waits for an answer before the
+// command is aborted, so an unanswered question cannot leave the console
+// stuck, unless changed with `set confirmtimeout`. The wait needs the alarm.
+const CONFIRM_TIMEOUT_MS: u32 = 5000;
+
+// Longest command kept in the command log; longer ones are cut short.
+const CMDLOG_TEXT_LEN: usize = 32;
+
+// Most captured output `tail` prints.
+const TAIL_LEN: usize = 64;
+
+// Terminal width assumed until told otherwise, the narrowest `set width`
+// accepts, and the narrowest that fits the `list` table.
+const DEFAULT_WIDTH: usize = 80;
+const MIN_WIDTH: usize = 16;
+const LIST_TABLE_WIDTH: usize = 80;
+// How far lines wrapped to the terminal width are indented after the first,
+// and the longest field that is moved to the next line whole rather than
+// split.
+const WRAP_INDENT: usize = 4;
+const WRAP_FIELD_LEN: usize = 32;
+// Width of the name column in the `list` table.
+const LIST_NAME_WIDTH: usize = 20;
+
+// Longest text output can be filtered on with `|`.
+const FILTER_LEN: usize = 16;
+
+// Version of the JSON printed by `describe`.
+const DESCRIBE_VERSION: usize = 1;
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    command: Option<AppSlice<Shared, u8>>,
+    output: Option<AppSlice<Shared, u8>>,
+    output_len: usize,
 }
 
-impl<U: UART, C: ProcessManagementCapability> ProcessConsole<'a, U, C> {
-    pub fn new(
-        uart: &'a U,
-        baud_rate: u32,
-        tx_buffer: &'static mut [u8],
-        rx_buffer: &'static mut [u8],
-        cmd_buffer: &'static mut [u8],
-        kernel: &'static Kernel,
-        capability: C,
-    ) -> ProcessConsole<'a, U, C> {
-        ProcessConsole {
-            uart: uart,
-            tx_in_progress: Cell::new(false),
-            tx_buffer: TakeCell::new(tx_buffer),
-            rx_in_progress: Cell::new(false),
-            rx_buffer: TakeCell::new(rx_buffer),
-            baud_rate: baud_rate,
-            command_buffer: TakeCell::new(cmd_buffer),
-            command_index: Cell::new(0),
-            running: Cell::new(false),
-            kernel: kernel,
-            capability: capability,
+/// Formats into a byte buffer, silently dropping whatever does not fit.
+struct BufWriter<'b> {
+    buf: &'b mut [u8],
+    len: usize,
+}
+
+impl BufWriter<'b> {
+    fn new(buf: &'b mut [u8]) -> BufWriter<'b> {
+        BufWriter { buf: buf, len: 0 }
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn remaining(&self) -> usize {
+        self.buf.len() - self.len
+    }
+}
+
+impl Write for BufWriter<'b> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let n = cmp::min(s.len(), self.buf.len() - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+/// Counts the bytes formatted into it.
+struct LenCounter {
+    len: usize,
+}
+
+impl Write for LenCounter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.len += s.len();
+        Ok(())
+    }
+}
+
+/// Checks whether the text formatted into it contains `pattern`.
+struct Contains<'p> {
+    pattern: &'p [u8],
+    // The last `pattern.len()` bytes written. Once it has filled up, the
+    // oldest is at `written % pattern.len()`.
+    window: [u8; FILTER_LEN],
+    written: usize,
+    found: bool,
+}
+
+impl Contains<'p> {
+    fn new(pattern: &'p [u8]) -> Contains<'p> {
+        Contains {
+            pattern: pattern,
+            window: [0; FILTER_LEN],
+            written: 0,
+            found: pattern.is_empty(),
         }
     }
+}
 
-    pub fn initialize(&self) {
-        self.uart.configure(uart::UARTParameters {
-            baud_rate: self.baud_rate,
-            stop_bits: uart::StopBits::One,
-            parity: uart::Parity::None,
-            hw_flow_control: false,
-        });
+impl Write for Contains<'p> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let len = self.pattern.len();
+        for byte in s.bytes() {
+            if self.found {
+                break;
+            }
+            self.window[self.written % len] = byte;
+            self.written += 1;
+            if self.written >= len {
+                let (window, written) = (&self.window, self.written);
+                self.found = self
+                    .pattern
+                    .iter()
+                    .enumerate()
+                    .all(|(i, byte)| window[(written + i) % len] == *byte);
+            }
+        }
+        Ok(())
     }
+}
 
-    pub fn start(&self) -> ReturnCode {
-        if self.running.get() == false {
-            self.rx_buffer.take().map(|buffer| {
-                self.rx_in_progress.set(true);
-                self.uart.receive(buffer, 1);
-                self.running.set(true);
-                debug!("Starting process console");
-            });
+/// Wraps the text formatted into it at `width` columns, breaking lines
+/// between fields separated by spaces and indenting the lines after the
+/// first. ANSI escape sequences take up no columns. `finish` must be called
+/// once the text is complete.
+struct WrapWriter<W: Write> {
+    inner: W,
+    width: usize,
+    column: usize,
+    // Spaces seen since the last field, written before the next one unless
+    // the line breaks there instead.
+    spaces: usize,
+    // The field being formatted, and how many columns it takes up.
+    field: [u8; WRAP_FIELD_LEN],
+    field_len: usize,
+    field_columns: usize,
+    // The field continues one too long to hold that was written out in
+    // part, so the line cannot break before it.
+    split: bool,
+    escape: bool,
+}
+
+impl<W: Write> WrapWriter<W> {
+    fn new(inner: W, width: usize) -> WrapWriter<W> {
+        WrapWriter {
+            inner: inner,
+            width: width,
+            column: 0,
+            spaces: 0,
+            field: [0; WRAP_FIELD_LEN],
+            field_len: 0,
+            field_columns: 0,
+            split: false,
+            escape: false,
         }
-        ReturnCode::SUCCESS
     }
 
-    // Process the command in the command buffer and clear the buffer.
-    fn read_command(&self) {
-        self.command_buffer.map(|command| {
-            let mut terminator = 0;
-            let len = command.len();
-            for i in 0..len {
-                if command[i] == 0 {
-                    terminator = i;
-                    break;
+    // Write out the field so far, on the next line if it does not fit on
+    // this one.
+    fn write_field(&mut self) -> fmt::Result {
+        if self.field_len == 0 {
+            return Ok(());
+        }
+        if !self.split
+            && self.column > WRAP_INDENT
+            && self.column + self.spaces + self.field_columns > self.width
+        {
+            self.inner.write_str("\r\n")?;
+            self.column = 0;
+            self.spaces = WRAP_INDENT;
+        }
+        for _ in 0..self.spaces {
+            self.inner.write_str(" ")?;
+        }
+        self.column += self.spaces + self.field_columns;
+        self.spaces = 0;
+        // Fields only ever hold whole characters, as they are split at ASCII
+        // bytes or at the end of a string.
+        let field = str::from_utf8(&self.field[..self.field_len]).unwrap_or("");
+        self.inner.write_str(field)?;
+        self.field_len = 0;
+        self.field_columns = 0;
+        Ok(())
+    }
+
+    fn finish(mut self) -> W {
+        let _ = self.write_field();
+        self.inner
+    }
+}
+
+impl<W: Write> Write for WrapWriter<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            match c {
+                ' ' if !self.escape => {
+                    self.write_field()?;
+                    self.split = false;
+                    self.spaces += 1;
                 }
-            }
-            //debug!("Command: {}-{} {:?}", start, terminator, command);
-            // A command is valid only if it starts inside the buffer,
-            // ends before the beginning of the buffer, and ends after
-            // it starts.
-            if terminator > 0 {
-                let cmd_str = str::from_utf8(&command[0..terminator]);
-                match cmd_str {
-                    Ok(s) => {
-                        let clean_str = s.trim();
-                        if clean_str.starts_with("help") {
-                            debug!("Welcome to the process console.");
-                            debug!("Valid commands are: help status list stop start");
-                        } else if clean_str.starts_with("start") {
-                            let argument = clean_str.split_whitespace().nth(1);
-                            argument.map(|name| {
-                                self.kernel.process_each_capability(
-                                    &self.capability,
-                                    |_i, proc| {
-                                        let proc_name = proc.get_process_name();
-                                        if proc_name == name {
-                                            proc.resume();
-                                            debug!("Process {} resumed.", name);
-                                        }
-                                    },
-                                );
-                            });
-                        } else if clean_str.starts_with("stop") {
-                            let argument = clean_str.split_whitespace().nth(1);
-                            argument.map(|name| {
-                                self.kernel.process_each_capability(
-                                    &self.capability,
-                                    |_i, proc| {
-                                        let proc_name = proc.get_process_name();
-                                        if proc_name == name {
-                                            proc.stop();
-                                            debug!("Process {} stopped", proc_name);
-                                        }
-                                    },
-                                );
-                            });
-                        } else if clean_str.starts_with("list") {
-                            debug!(" PID    Name                Quanta  Syscalls  Dropped Callbacks    State");
-                            self.kernel
-                                .process_each_capability(&self.capability, |i, proc| {
-                                    let pname = proc.get_process_name();
-                                    debug!(
-                                        "  {:02}\t{:<20}{:6}{:10}{:19}  {:?}",
-                                        i,
-                                        pname,
-                                        proc.debug_timeslice_expiration_count(),
-                                        proc.debug_syscall_count(),
-                                        proc.debug_dropped_callback_count(),
-                                        proc.get_state()
-                                    );
-                                });
-                        } else if clean_str.starts_with("status") {
-                            let info: KernelInfo = KernelInfo::new(self.kernel);
-                            debug!(
-                                "Total processes: {}",
-                                info.number_loaded_processes(&self.capability)
-                            );
-                            debug!(
-                                "Active processes: {}",
-                                info.number_active_processes(&self.capability)
-                            );
-                            debug!(
-                                "Timeslice expirations: {}",
-                                info.timeslice_expirations(&self.capability)
-                            );
-                        } else {
-                            debug!("Valid commands are: help status list stop start");
-                        }
+                '\t' if !self.escape => {
+                    self.write_field()?;
+                    self.split = false;
+                    for _ in 0..self.spaces {
+                        self.inner.write_str(" ")?;
+                    }
+                    self.inner.write_str("\t")?;
+                    self.column = (self.column + self.spaces) / 8 * 8 + 8;
+                    self.spaces = 0;
+                }
+                _ => {
+                    if self.field_len + c.len_utf8() > self.field.len() {
+                        // Too long to move whole.
+                        self.write_field()?;
+                        self.split = true;
+                    }
+                    c.encode_utf8(&mut self.field[self.field_len..]);
+                    self.field_len += c.len_utf8();
+                    if c == '\x1b' {
+                        self.escape = true;
+                    } else if self.escape {
+                        self.escape = !c.is_ascii_alphabetic();
+                    } else {
+                        self.field_columns += 1;
                     }
-                    Err(_e) => debug!("Invalid command: {:?}", command),
                 }
             }
-        });
-        self.command_buffer.map(|command| {
-            command[0] = 0;
-        });
-        self.command_index.set(0);
+        }
+        Ok(())
+    }
+}
+
+/// Formats into the output queue, silently dropping whatever does not fit.
+struct QueueWriter<'q> {
+    queue: &'q mut RingBuffer<'static, u8>,
+}
+
+impl Write for QueueWriter<'q> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            if !self.queue.enqueue(byte) {
+                break;
+            }
+        }
+        Ok(())
     }
+}
+
+// Names of the commands the console understands, used for help and for
+// suggesting corrections to unknown commands.
+const COMMANDS: &'static [&'static str] = &[
+    "help",
+    "menu",
+    "status",
+    "dumpstate",
+    "list",
+    "stop",
+    "start",
+    "restart",
+    "restarts",
+    "faultpolicy",
+    "compact",
+    "grantaudit",
+    "grantmap",
+    "resume",
+    "set",
+    "tail",
+    "timers",
+    "caps",
+    "checkdriver",
+    "mounts",
+    "cmdlog",
+    "flashinfo",
+    "hwid",
+    "gpio",
+    "dbggpio",
+    "idle",
+    "quantum",
+    "snapshot",
+    "diff",
+    "ipc",
+    "callbacks",
+    "errors",
+    "args",
+    "perf",
+    "txqueue",
+    "uartstat",
+    "bod",
+    "resetreason",
+    "prio",
+    "run",
+    "runq",
+    "watch",
+    "flashdump",
+    "dmesg",
+    "defcalls",
+    "strace",
+    "adc",
+    "temp",
+    "date",
+    "clock",
+    "logflush",
+    "logrotate",
+    "probe",
+    "irqtest",
+    "time",
+    "exit",
+    "quit",
+    "features",
+    "describe",
+];
+// The optional features of this crate the console knows about, and whether
+// this build has each.
+const FEATURES: &'static [(&'static str, bool)] =
+    &[("debug_commands", cfg!(feature = "debug_commands"))];
+// Commands only available with the `debug_commands` feature.
+const DEBUG_COMMANDS: &'static [&'static str] = &[
+    "memdump",
+    "mpu",
+    "send",
+    "injecterror",
+    "memlimit",
+    "sched",
+    "erase",
+    "reg",
+];
+// The commands apps may run through the syscall driver, which only report on
+// the system, with how many arguments each may be given.
+const APP_COMMANDS: &'static [(&'static str, usize)] = &[
+    ("help", 1),
+    ("status", 0),
+    ("list", 0),
+    ("dumpstate", 0),
+    ("restarts", 1),
+    ("grantaudit", 0),
+    ("timers", 0),
+    ("idle", 0),
+    ("ipc", 1),
+    ("callbacks", 1),
+    ("perf", 1),
+    ("args", 1),
+    ("txqueue", 0),
+    ("uartstat", 0),
+    ("resetreason", 0),
+    ("errors", 0),
+    ("caps", 0),
+    ("checkdriver", 2),
+    ("mounts", 0),
+    ("flashinfo", 0),
+    ("hwid", 0),
+    ("features", 0),
+    ("describe", 0),
+];
+
+// Arguments that make a command in `APP_COMMANDS` change the system rather
+// than report on it, so apps may not give them.
+const APP_DENIED_ARGS: &'static [(&'static str, &'static str)] = &[("restarts", "clear")];
+
+// Whether an app may run `command`: one of `APP_COMMANDS`, with no more
+// arguments than it allows before any output filter, and none of the
+// arguments in `APP_DENIED_ARGS`.
+fn app_command_allowed(command: &str) -> bool {
+    let mut words = command.split('|').next().unwrap_or("").split_whitespace();
+    let verb = match words.next() {
+        Some(verb) => verb,
+        None => return false,
+    };
+    let denied = words.clone().any(|arg| {
+        APP_DENIED_ARGS
+            .iter()
+            .any(|&(name, denied)| name == verb && denied == arg)
+    });
+    let args = words.count();
+    !denied
+        && APP_COMMANDS
+            .iter()
+            .any(|&(name, max_args)| name == verb && args <= max_args)
+}
+
+// The keys `set keymode single` runs commands with, the command each runs
+// and whether it needs an argument typed after it.
+const SINGLE_KEYS: &'static [(u8, &'static str, bool)] = &[
+    (b'h', "help", false),
+    (b'l', "list", false),
+    (b'm', "menu", false),
+    (b'i', "status", false),
+    (b's', "stop", true),
+    (b'g', "start", true),
+];
+
+// Commands whose first argument names a process, so Tab completes it with
+// process names.
+const PROCESS_COMMANDS: &'static [&'static str] = &[
+    "stop",
+    "start",
+    "restarts",
+    "faultpolicy",
+    "compact",
+    "tail",
+    "ipc",
+    "callbacks",
+    "args",
+    "perf",
+    "prio",
+    "watch",
+    "flashdump",
+    "memlimit",
+    "erase",
+];
+
+/// Displays the names of the available commands that start with `prefix`,
+/// each preceded by a space.
+struct CommandNames<'s> {
+    prefix: &'s str,
+}
 
-    fn write_byte(&self, byte: u8) -> ReturnCode {
-        if self.tx_in_progress.get() {
-            ReturnCode::EBUSY
+impl CommandNames<'s> {
+    fn names(&self) -> impl Iterator<Item = &'static str> + 's {
+        let debug: &'static [&'static str] = if cfg!(feature = "debug_commands") {
+            DEBUG_COMMANDS
         } else {
-            self.tx_in_progress.set(true);
-            self.tx_buffer.take().map(|buffer| {
-                buffer[0] = byte;
-                self.uart.transmit(buffer, 1);
-            });
-            ReturnCode::SUCCESS
+            &[]
+        };
+        let prefix = self.prefix;
+        COMMANDS
+            .iter()
+            .chain(debug.iter())
+            .map(|name| *name)
+            .filter(move |name| name.starts_with(prefix))
+    }
+}
+
+impl fmt::Display for CommandNames<'s> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for name in self.names() {
+            write!(f, " {}", name)?;
+        }
+        Ok(())
+    }
+}
+
+/// The names that could complete a word: how many there are, the first of
+/// them, and how long a prefix they all share.
+#[derive(Clone, Copy)]
+struct Completion {
+    count: usize,
+    first: &'static str,
+    common: usize,
+}
+
+impl Completion {
+    fn new() -> Completion {
+        Completion {
+            count: 0,
+            first: "",
+            common: 0,
         }
     }
 
-    fn write_bytes(&self, bytes: &[u8]) -> ReturnCode {
-        if self.tx_in_progress.get() {
-            ReturnCode::EBUSY
+    fn add(&mut self, name: &'static str) {
+        if self.count == 0 {
+            self.first = name;
+            self.common = name.len();
         } else {
-            self.tx_in_progress.set(true);
-            self.tx_buffer.take().map(|buffer| {
-                let len = cmp::min(bytes.len(), buffer.len());
-                for i in 0..len {
-                    buffer[i] = bytes[i];
-                }
-                self.uart.transmit(buffer, len);
-            });
-            ReturnCode::SUCCESS
+            self.common = self
+                .first
+                .bytes()
+                .zip(name.bytes())
+                .take(self.common)
+                .take_while(|&(a, b)| a == b)
+                .count();
         }
+        self.count += 1;
     }
 }
 
-impl<U: UART, C: ProcessManagementCapability> Client for ProcessConsole<'a, U, C> {
-    fn transmit_complete(&self, buffer: &'static mut [u8], _error: uart::Error) {
-        // Either print more from the AppSlice or send a callback to the
-        // application.
-        self.tx_buffer.replace(buffer);
-        self.tx_in_progress.set(false);
+/// Displays the JSON `describe` prints.
+struct Description {
+    command_capacity: usize,
+    output_capacity: usize,
+}
+
+impl fmt::Display for Description {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{{\"version\":{},\"commands\":[", DESCRIBE_VERSION)?;
+        let commands = CommandNames { prefix: "" };
+        for (i, name) in commands.names().enumerate() {
+            write!(f, "{}\"{}\"", if i == 0 { "" } else { "," }, name)?;
+        }
+        write!(f, "],\"features\":{{")?;
+        for (i, &(name, enabled)) in FEATURES.iter().enumerate() {
+            write!(
+                f,
+                "{}\"{}\":{}",
+                if i == 0 { "" } else { "," },
+                name,
+                enabled
+            )?;
+        }
+        write!(
+            f,
+            "}},\"buffers\":{{\"command\":{},\"app_command\":{},\"output\":{}}}}}",
+            self.command_capacity, APP_COMMAND_LEN, self.output_capacity
+        )
     }
+}
 
-    fn receive_complete(&self, read_buf: &'static mut [u8], rx_len: usize, error: uart::Error) {
-        let mut execute = false;
-        if error == uart::Error::CommandComplete {
-            match rx_len {
-                0 => debug!("ProcessConsole had read of 0 bytes"),
-                1 => {
-                    self.command_buffer.map(|command| {
-                        let index = self.command_index.get() as usize;
-                        if read_buf[0] == ('\n' as u8) || read_buf[0] == ('\r' as u8) {
-                            execute = true;
-                            self.write_bytes(&['\r' as u8, '\n' as u8]);
-                        } else if read_buf[0] == ('\x08' as u8) && index > 0 {
-                            // Backspace, echo and remove last byte
-                            // Note echo is '\b \b' to erase
-                            self.write_bytes(&['\x08' as u8, ' ' as u8, '\x08' as u8]);
-                            command[index - 1] = '\0' as u8;
-                            self.command_index.set(index - 1);
-                        } else if index < (command.len() - 1) && read_buf[0] < 128 {
-                            // For some reason, sometimes reads return > 127 but no error,
-                            // which causes utf-8 decoding failure, so check byte is < 128. -pal
-
-                            // Echo the byte and store it
-                            self.write_byte(read_buf[0]);
-                            command[index] = read_buf[0];
-                            self.command_index.set(index + 1);
-                            command[index + 1] = 0;
-                        }
-                    });
-                }
-                _ => debug!(
-                    "ProcessConsole issues reads of 1 byte, but receive_complete was length {}",
-                    rx_len
-                ),
-            };
+/// Displays a system call and its arguments, as `strace` prints them.
+struct TracedSyscall(Syscall);
+
+impl fmt::Display for TracedSyscall {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Syscall::YIELD => write!(f, "yield"),
+            Syscall::SUBSCRIBE {
+                driver_number,
+                subdriver_number,
+                callback_ptr,
+                appdata,
+            } => write!(
+                f,
+                "subscribe {:#x} {} {:p} {:#x}",
+                driver_number, subdriver_number, callback_ptr, appdata
+            ),
+            Syscall::COMMAND {
+                driver_number,
+                subdriver_number,
+                arg0,
+                arg1,
+            } => write!(
+                f,
+                "command {:#x} {} {} {}",
+                driver_number, subdriver_number, arg0, arg1
+            ),
+            Syscall::ALLOW {
+                driver_number,
+                subdriver_number,
+                allow_address,
+                allow_size,
+            } => write!(
+                f,
+                "allow {:#x} {} {:p} {}",
+                driver_number, subdriver_number, allow_address, allow_size
+            ),
+            Syscall::MEMOP { operand, arg0 } => write!(f, "memop {} {:#x}", operand, arg0),
         }
-        self.rx_in_progress.set(true);
-        self.uart.receive(read_buf, 1);
-        if execute {
-            self.read_command();
+    }
+}
+
+/// A style of terminal text.
+#[derive(Clone, Copy)]
+enum Style {
+    Plain,
+    Bold,
+    Red,
+    Green,
+}
+
+/// Displays a value wrapped in the ANSI escape sequences that give it `style`,
+/// or just the value if `enabled` is false.
+struct Styled<T> {
+    value: T,
+    style: Style,
+    enabled: bool,
+}
+
+impl<T: fmt::Display> fmt::Display for Styled<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let code = match self.style {
+            Style::Plain => return self.value.fmt(f),
+            Style::Bold => "1",
+            Style::Red => "31",
+            Style::Green => "32",
+        };
+        if !self.enabled {
+            return self.value.fmt(f);
+        }
+        write!(f, "\x1b[{}m", code)?;
+        self.value.fmt(f)?;
+        f.write_str("\x1b[0m")
+    }
+}
+
+// The style `list` shows a process state in.
+fn state_style(state: State) -> Style {
+    match state {
+        State::Running => Style::Green,
+        State::Fault => Style::Red,
+        _ => Style::Plain,
+    }
+}
+
+/// Displays a string cut down to at most `max` characters, ending in an
+/// ellipsis if anything was cut.
+struct Truncated<'s> {
+    s: &'s str,
+    max: usize,
+}
+
+impl fmt::Display for Truncated<'s> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.s.chars().count() <= self.max {
+            return f.pad(self.s);
+        }
+        let end = self
+            .s
+            .char_indices()
+            .nth(self.max.saturating_sub(1))
+            .map_or(self.s.len(), |(i, _)| i);
+        write!(f, "{}\u{2026}", &self.s[..end])?;
+        // Pad as if the ellipsis was a single byte, so columns still line up.
+        for _ in self.max..f.width().unwrap_or(0) {
+            f.write_str(" ")?;
+        }
+        Ok(())
+    }
+}
+
+/// Displays bytes as text, escaping anything that is not printable ASCII.
+struct Escaped<'b>(&'b [u8]);
+
+impl fmt::Display for Escaped<'b> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in self.0 {
+            match *byte {
+                b'\\' => f.write_str("\\\\")?,
+                b'\t' => f.write_str("\\t")?,
+                0x20...0x7e => f.write_char(*byte as char)?,
+                _ => write!(f, "\\x{:02x}", byte)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Keeps the most recent output of each process, for `tail` to print.
+/// `OutputRecorder` implements this for what apps write to the console
+/// capsule.
+pub trait OutputCapture {
+    /// Copy the most recent output of `app` into `buf`, oldest byte first, and
+    /// return how many bytes were copied, or `None` if nothing is kept for
+    /// `app`.
+    fn recent_output(&self, app: AppId, buf: &mut [u8]) -> Option<usize>;
+}
+
+// How many of the most recent bytes each app wrote are kept.
+const RECENT_OUTPUT_LEN: usize = 32;
+
+/// The most recent bytes an app wrote to the console capsule.
+#[derive(Default)]
+pub struct RecentOutput {
+    bytes: [u8; RECENT_OUTPUT_LEN],
+    next: usize,
+    wrapped: bool,
+}
+
+impl RecentOutput {
+    fn record(&mut self, byte: u8) {
+        self.bytes[self.next] = byte;
+        self.next += 1;
+        if self.next == RECENT_OUTPUT_LEN {
+            self.next = 0;
+            self.wrapped = true;
+        }
+    }
+
+    // Copy the most recent bytes, oldest first, into as much of `buf` as
+    // they fill, returning how many were copied.
+    fn copy_into(&self, buf: &mut [u8]) -> usize {
+        let (older, newer) = if self.wrapped {
+            (&self.bytes[self.next..], &self.bytes[..self.next])
+        } else {
+            (&self.bytes[..0], &self.bytes[..self.next])
+        };
+        let total = older.len() + newer.len();
+        let skip = total.saturating_sub(buf.len());
+        let mut len = 0;
+        for byte in older.iter().chain(newer.iter()).skip(skip) {
+            buf[len] = *byte;
+            len += 1;
         }
+        len
+    }
+}
+
+/// Keeps the most recent output of each app, for `tail`, in a grant of its
+/// own, so only boards that set one as the console capsule's output client
+/// use the memory.
+pub struct OutputRecorder {
+    apps: Grant<RecentOutput>,
+}
+
+impl OutputRecorder {
+    pub fn new(grant: Grant<RecentOutput>) -> OutputRecorder {
+        OutputRecorder { apps: grant }
+    }
+}
+
+impl console::OutputClient for OutputRecorder {
+    fn app_output(&self, app: AppId, bytes: &[u8]) {
+        let _ = self.apps.enter(app, |recent, _| {
+            for &byte in bytes {
+                recent.record(byte);
+            }
+        });
+    }
+}
+
+impl OutputCapture for OutputRecorder {
+    fn recent_output(&self, app: AppId, buf: &mut [u8]) -> Option<usize> {
+        self.apps.enter(app, |recent, _| recent.copy_into(buf)).ok()
+    }
+}
+
+/// Lists the armed alarms of an alarm multiplexer, for `timers` to print.
+/// `virtual_alarm::MuxAlarm` implements this.
+pub trait AlarmList {
+    /// The current time, in ticks.
+    fn now(&self) -> u32;
+
+    /// How many ticks there are per second.
+    fn frequency(&self) -> u32;
+
+    /// Call `f` with the time each armed alarm fires at and the address of
+    /// its client.
+    fn each_armed(&self, f: &mut FnMut(u32, usize));
+}
+
+/// An alarm for commands that wait, such as `irqtest`, which measures how
+/// late alarm callbacks run, and `watch`. Every `hil::time::Alarm` is one; the
+/// console must also be set as its client.
+pub trait ConsoleAlarm {
+    /// The current time, in ticks.
+    fn now(&self) -> u32;
+
+    /// How many ticks there are per second.
+    fn frequency(&self) -> u32;
+
+    /// Fire the alarm once the counter reaches `tics`.
+    fn set_alarm(&self, tics: u32);
+}
+
+impl<A: Alarm> ConsoleAlarm for A {
+    fn now(&self) -> u32 {
+        Alarm::now(self)
+    }
+
+    fn frequency(&self) -> u32 {
+        <A::Frequency as Frequency>::frequency()
+    }
+
+    fn set_alarm(&self, tics: u32) {
+        Alarm::set_alarm(self, tics)
+    }
+}
+
+/// A storage volume for `mounts` to list, such as a region of flash a capsule
+/// keeps data in.
+pub trait StorageVolume {
+    /// What the volume is called.
+    fn name(&self) -> &'static str;
+
+    /// The address the volume starts at and its size in bytes.
+    fn region(&self) -> (usize, usize);
+
+    /// How many bytes of the volume are free, if the volume keeps track.
+    fn free(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// A region of storage with a fixed place and size, which does not track
+/// how much of it is used.
+pub struct StorageRegion {
+    pub name: &'static str,
+    pub start: usize,
+    pub len: usize,
+}
+
+impl StorageVolume for StorageRegion {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn region(&self) -> (usize, usize) {
+        (self.start, self.len)
+    }
+}
+
+/// Where the kernel and apps are placed in flash, for `flashinfo`.
+pub struct FlashLayout {
+    /// The start and length of the flash region the kernel is placed in.
+    pub kernel: (usize, usize),
+    /// How many bytes of that region the kernel uses.
+    pub kernel_used: usize,
+    /// The start and length of the flash region apps are placed in.
+    pub apps: (usize, usize),
+}
+
+/// Where a command that was run came from.
+#[derive(Clone, Copy)]
+enum CommandSource {
+    // Typed on the UART with this index.
+    Uart(usize),
+    Script,
+    App,
+    // A line without the command prefix, handed to the unknown command
+    // handler.
+    Passthrough,
+}
+
+impl fmt::Display for CommandSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CommandSource::Uart(index) => write!(f, "uart{}", index),
+            CommandSource::Script => write!(f, "script"),
+            CommandSource::App => write!(f, "app"),
+            CommandSource::Passthrough => write!(f, "passthru"),
+        }
+    }
+}
+
+/// A command in the command log `cmdlog` prints.
+#[derive(Clone, Copy)]
+pub struct CommandLogEntry {
+    // Milliseconds on the console's alarm when it ran, if it has one.
+    time: Option<u32>,
+    source: CommandSource,
+    text: [u8; CMDLOG_TEXT_LEN],
+    len: usize,
+}
+
+const COMMAND_LOG_EMPTY: CommandLogEntry = CommandLogEntry {
+    time: None,
+    source: CommandSource::Script,
+    text: [0; CMDLOG_TEXT_LEN],
+    len: 0,
+};
+
+/// Displays a number with its thousands separated by commas.
+struct Thousands(u64);
+
+impl fmt::Display for Thousands {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0 < 1000 {
+            return write!(f, "{}", self.0);
+        }
+        write!(f, "{},{:03}", Thousands(self.0 / 1000), self.0 % 1000)
+    }
+}
+
+/// Displays bytes in hex, each after a space.
+struct HexBytes<'b>(&'b [u8]);
+
+impl fmt::Display for HexBytes<'b> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, " {:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// Displays frequencies in Hz, each after a space.
+struct Frequencies(&'static [u32]);
+
+impl fmt::Display for Frequencies {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for frequency in self.0 {
+            write!(f, " {}", frequency)?;
+        }
+        Ok(())
+    }
+}
+
+/// Displays the steps of a `run` chain after a space, separated by `; `.
+struct Steps(&'static [&'static str]);
+
+impl fmt::Display for Steps {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, step) in self.0.iter().enumerate() {
+            f.write_str(if i == 0 { " " } else { "; " })?;
+            f.write_str(step)?;
+        }
+        Ok(())
+    }
+}
+
+/// Displays bytes as one unbroken hex number.
+struct HexId<'b>(&'b [u8]);
+
+impl fmt::Display for HexId<'b> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// Displays a number of bytes in KB, to a tenth of a KB.
+struct Kb(usize);
+
+impl fmt::Display for Kb {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{} KB", self.0 / 1024, self.0 % 1024 * 10 / 1024)
+    }
+}
+
+/// Displays a date and time as `YYYY-MM-DD HH:MM:SS`.
+struct Date(DateTimeValues);
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let d = self.0;
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            d.year, d.month, d.day, d.hour, d.minute, d.second
+        )
+    }
+}
+
+/// Storage a capsule keeps a log in, for `logflush` and `logrotate`. Both
+/// operations finish by calling `LogStorageClient::log_done`, so the console
+/// must also be set as the log's client.
+pub trait LogStorage {
+    /// Start writing any buffered entries to flash.
+    fn flush(&self) -> ReturnCode;
+
+    /// Start finishing the current log segment and starting a new one.
+    fn rotate(&self) -> ReturnCode;
+}
+
+/// Told when an operation a `LogStorage` started has finished.
+pub trait LogStorageClient {
+    /// The last operation started has finished with `result`.
+    fn log_done(&self, result: ReturnCode);
+}
+
+/// Flash that apps are loaded from, for `erase` to erase app slots in a page
+/// at a time. Each erase finishes with a call to the console as the flash's
+/// `hil::flash::Client`, so the console must also be set as its client.
+pub trait AppFlash {
+    /// The size of a flash page in bytes. Page `n` starts at address
+    /// `n * page_size()`.
+    fn page_size(&self) -> usize;
+
+    /// Start erasing page `page_number`.
+    fn erase_page(&self, page_number: usize) -> ReturnCode;
+}
+
+/// Makes flash with pages of `page_size` bytes usable as `AppFlash`.
+pub struct AppFlashPages<'f, F: flash::Flash + 'f> {
+    flash: &'f F,
+    page_size: usize,
+}
+
+impl<F: flash::Flash> AppFlashPages<'f, F> {
+    pub fn new(flash: &'f F, page_size: usize) -> AppFlashPages<'f, F> {
+        AppFlashPages {
+            flash: flash,
+            page_size: page_size,
+        }
+    }
+}
+
+impl<F: flash::Flash> AppFlash for AppFlashPages<'f, F> {
+    fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    fn erase_page(&self, page_number: usize) -> ReturnCode {
+        self.flash.erase_page(page_number)
+    }
+}
+
+/// An ADC for `adc` to take samples with, whose channels are numbered from 0.
+/// `AdcChannels` makes one from a `hil::adc::Adc` and its channels. The
+/// console must also be set as the ADC's client.
+pub trait ConsoleAdc {
+    /// How many channels there are.
+    fn channel_count(&self) -> usize;
+
+    /// Take a single sample on `channel`, which is less than
+    /// `channel_count()`. The sample is passed to `sample_ready`.
+    fn sample(&self, channel: usize) -> ReturnCode;
+
+    /// How many bits of resolution the samples have.
+    fn resolution_bits(&self) -> usize;
+
+    /// The reference voltage in millivolts, if known.
+    fn reference_mv(&self) -> Option<usize>;
+}
+
+/// The channels of a `hil::adc::Adc`, numbered by their index in `channels`,
+/// for the console to sample.
+pub struct AdcChannels<'a, A: adc::Adc + 'a>
+where
+    A::Channel: 'a,
+{
+    adc: &'a A,
+    channels: &'a [&'a A::Channel],
+}
+
+impl<A: adc::Adc> AdcChannels<'a, A> {
+    pub fn new(adc: &'a A, channels: &'a [&'a A::Channel]) -> AdcChannels<'a, A> {
+        AdcChannels {
+            adc: adc,
+            channels: channels,
+        }
+    }
+}
+
+impl<A: adc::Adc> ConsoleAdc for AdcChannels<'a, A> {
+    fn channel_count(&self) -> usize {
+        self.channels.len()
+    }
+
+    fn sample(&self, channel: usize) -> ReturnCode {
+        match self.channels.get(channel) {
+            Some(channel) => self.adc.sample(channel),
+            None => ReturnCode::EINVAL,
+        }
+    }
+
+    fn resolution_bits(&self) -> usize {
+        self.adc.get_resolution_bits()
+    }
+
+    fn reference_mv(&self) -> Option<usize> {
+        self.adc.get_voltage_reference_mv()
+    }
+}
+
+/// What `snapshot` records about a process, for `diff` to compare against.
+#[derive(Clone, Copy)]
+struct ProcessSnapshot {
+    name: &'static str,
+    state: State,
+    restarts: usize,
+    syscalls: usize,
+    dropped_callbacks: usize,
+    heap_end: usize,
+}
+
+/// Handles commands the console does not recognize, for example by passing
+/// them on to an application.
+pub trait UnknownCommandHandler {
+    /// Handle `command`, the whole command line. Returns `false` if the
+    /// command is not handled either, in which case the console reports it as
+    /// an unknown command.
+    fn unknown_command(&self, command: &str) -> bool;
+}
+
+// Whether the line typed so far ends with a backslash, which continues the
+// command on the next line rather than ending it.
+fn continues_line(line: &[u8]) -> bool {
+    line.last() == Some(&b'\\')
+}
+
+// Apply a byte typed on the command line to `command`, which holds `index`
+// bytes followed by a NUL, of which those before `line_start` belong to lines
+// already continued with a backslash. Returns the new index and the bytes to
+// echo: a stored byte is echoed as itself, a backspace as '\b \b' to erase
+// it, and a byte that does not fit as the bell. Other bytes are ignored.
+fn edit_line(
+    command: &'a mut [u8],
+    index: usize,
+    line_start: usize,
+    byte: u8,
+) -> (usize, &'a [u8]) {
+    if byte == ('\x08' as u8) || byte == 0x7f {
+        // Backspace. Many terminals send DEL (0x7f) for the backspace key, so
+        // treat it the same.
+        if index > line_start {
+            command[index - 1] = 0;
+            (index - 1, b"\x08 \x08")
+        } else {
+            (index, b"")
+        }
+    } else if byte >= 128 {
+        // For some reason, sometimes reads return > 127 but no error,
+        // which causes utf-8 decoding failure, so check byte is < 128. -pal
+        (index, b"")
+    } else if index < command.len() - 1 {
+        command[index] = byte;
+        command[index + 1] = 0;
+        (index + 1, &command[index..index + 1])
+    } else {
+        // The command buffer is full: ring the bell rather than silently
+        // dropping the byte.
+        (index, b"\x07")
+    }
+}
+
+// Whether `arg` names the process at index `index` called `name`, either by
+// its name or by its index in the process table.
+fn process_matches(index: usize, name: &str, arg: &str) -> bool {
+    name == arg || parse_int(arg.as_bytes()).ok() == Some(index)
+}
+
+/// Why a command argument could not be read as a number.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ParseError {
+    /// There were no digits, for example just `0x`.
+    Empty,
+    /// A character is not a digit in the number's radix.
+    InvalidDigit,
+    /// The number does not fit in a `usize`.
+    Overflow,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseError::Empty => write!(f, "has no digits"),
+            ParseError::InvalidDigit => write!(f, "is not a number"),
+            ParseError::Overflow => write!(f, "is too large"),
+        }
+    }
+}
+
+// Read a number typed as a command argument: hex if it starts with `0x`,
+// decimal otherwise.
+fn parse_int(text: &[u8]) -> Result<usize, ParseError> {
+    let (digits, radix) = if text.starts_with(b"0x") {
+        (&text[2..], 16)
+    } else {
+        (text, 10)
+    };
+    if digits.is_empty() {
+        return Err(ParseError::Empty);
+    }
+    digits.iter().fold(Ok(0), |value: Result<usize, _>, &byte| {
+        let digit = (byte as char)
+            .to_digit(radix)
+            .ok_or(ParseError::InvalidDigit)?;
+        value?
+            .checked_mul(radix as usize)
+            .and_then(|v| v.checked_add(digit as usize))
+            .ok_or(ParseError::Overflow)
+    })
+}
+
+// Read a date typed as `YYYY-MM-DD` and a time typed as `HH:MM:SS`,
+// returning `None` unless they are a date that exists and a time of day.
+fn parse_date_time(date: &str, time: &str) -> Option<DateTimeValues> {
+    fn fields(text: &str, separator: char, widths: [usize; 3]) -> Option<[u16; 3]> {
+        let mut values = [0; 3];
+        let mut parts = text.split(separator);
+        for (value, &width) in values.iter_mut().zip(widths.iter()) {
+            let part = parts.next()?;
+            if part.len() != width || !part.bytes().all(|byte| byte.is_ascii_digit()) {
+                return None;
+            }
+            *value = part.parse().ok()?;
+        }
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(values)
+    }
+    let [year, month, day] = fields(date, '-', [4, 2, 2])?;
+    let [hour, minute, second] = fields(time, ':', [2, 2, 2])?;
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days = match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if leap => 29,
+        2 => 28,
+        _ => return None,
+    };
+    if day < 1 || day > days || hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+    Some(DateTimeValues {
+        year: year,
+        month: month as u8,
+        day: day as u8,
+        hour: hour as u8,
+        minute: minute as u8,
+        second: second as u8,
+    })
+}
+
+/// What bytes received on the UART are used for. `receive_complete` hands
+/// each byte to the handler for the current mode.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum InputMode {
+    /// Bytes edit the command line, and a line terminator runs it.
+    Command,
+    /// A command typed on the UART is still running or printing its output.
+    /// Input is discarded, so a new command cannot start in the meantime.
+    Busy,
+    /// The session was ended with `exit`. Nothing is echoed, and the next
+    /// byte received starts a new session.
+    Dormant,
+    /// A command asked `Are you sure? [y/N]`. The next byte received answers,
+    /// and `y` carries out the action.
+    Confirm(Action),
+}
+
+/// A dangerous action a command only carries out once confirmed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Action {
+    MpuOff,
+    SchedPause,
+    // Erase the flash of the process at this index.
+    Erase(usize),
+    // Drop the tasks queued for the process at this index.
+    ClearCallbacks(usize),
+}
+
+/// What a task queued for a process does, for `callbacks` to count tasks by.
+#[derive(Clone, Copy, PartialEq)]
+enum PendingTask {
+    /// A callback to the function at this address.
+    Callback(usize),
+    /// An IPC notification involving the process at this index.
+    Ipc(usize),
+}
+
+/// The sections of the `dumpstate` report, in the order they are printed.
+#[derive(Clone, Copy, PartialEq)]
+enum DumpSection {
+    Kernel,
+    Processes,
+    Memory,
+}
+
+/// Work a command carries on with after `execute` returns: multi-line output
+/// that is printed one line at a time, each time the output queue drains, so
+/// that long output does not overflow the queue, or alarms `irqtest` and
+/// `watch` are waiting for.
+#[derive(Clone, Copy, PartialEq)]
+enum Stream {
+    Idle,
+    MemDump {
+        addr: usize,
+        remaining: usize,
+    },
+    // Latencies are in ticks of the latency alarm.
+    IrqTest {
+        samples: usize,
+        remaining: usize,
+        due: u32,
+        min: u32,
+        max: u32,
+        total: u32,
+    },
+    // Debug log output from `position` up to `end`.
+    Dmesg {
+        position: usize,
+        end: usize,
+    },
+    // Waiting for the sample `adc` asked for on `channel`.
+    Adc {
+        channel: usize,
+    },
+    // Waiting for a temperature reading, which `temp watch` follows with
+    // another.
+    Temperature {
+        watch: bool,
+    },
+    // `temp watch` is waiting for the alarm to take the next reading.
+    TemperatureWatch,
+    // Waiting for the real-time clock to be read. `date set` reads it back
+    // after setting it to `expected`.
+    DateRead {
+        expected: Option<DateTimeValues>,
+    },
+    // Waiting for the real-time clock to be set to this.
+    DateSet(DateTimeValues),
+    // `clock` switches the CPU clock to `frequency` once the output queue
+    // has drained.
+    Clock {
+        frequency: u32,
+    },
+    // `erase` is waiting for page `page` to be erased, which is followed by
+    // the ones up to `end`, of the pages from `start`.
+    Erase {
+        start: usize,
+        page: usize,
+        end: usize,
+    },
+    // Waiting for the log storage to finish flushing, or rotating if
+    // `rotate`.
+    LogStorage {
+        rotate: bool,
+    },
+    // `probe i2c` is waiting to hear whether a device answers at `addr`;
+    // `found` have so far.
+    I2cProbe {
+        addr: u8,
+        found: usize,
+    },
+    // `probe spi` is waiting for a device's ID.
+    SpiProbe,
+    // Commands in the command log from `position` up to `end`.
+    CommandLog {
+        position: usize,
+        end: usize,
+    },
+    // The commands `menu` lists, from the one numbered `number`.
+    Menu {
+        number: usize,
+    },
+    // System calls in the trace from `position` up to `end`, of the process
+    // at index `process` or of all of them.
+    Strace {
+        position: usize,
+        end: usize,
+        process: Option<usize>,
+    },
+    // `dumpstate` prints line `line` of `section` next. Lines after the
+    // header are about processes, from the one at index `line - 1` on.
+    DumpState {
+        section: DumpSection,
+        line: usize,
+    },
+    // `grantaudit` reports on the grant at index `grant` next.
+    GrantAudit {
+        grant: usize,
+    },
+    // `grantmap` shows the grant region of the process at index `process`
+    // next, from address `after`, or from its header if `after` is 0.
+    GrantMap {
+        process: usize,
+        after: usize,
+    },
+    // The process at `index` was last seen in `state`.
+    Watch {
+        index: usize,
+        state: State,
+    },
+}
+
+/// A UART the console talks over, with its own transmit and receive buffers.
+struct Channel<'a, U: UART> {
+    uart: OptionalCell<&'a U>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    // Addresses of the buffers, to tell which channel a completed transfer
+    // came from.
+    tx_address: Cell<usize>,
+    rx_address: Cell<usize>,
+    // A transmission took so long the console stopped waiting for it.
+    tx_stalled: Cell<bool>,
+    // Length of the receive buffer and how many bytes each receive asks for,
+    // as set with `set rxchunk`. A receive completes only once that many
+    // bytes have arrived, so typed input is not echoed until then.
+    rx_capacity: Cell<usize>,
+    rx_chunk: Cell<usize>,
+    rx_in_progress: Cell<bool>,
+    // How many receives in a row have failed, and whether the console is
+    // still re-arming receives straight away.
+    rx_failures: Cell<usize>,
+    rx_healthy: Cell<bool>,
+    // The bytes of the receive buffer from the first up to the second still
+    // to be handled, held back until the command they arrived behind
+    // completes. The buffer is not re-armed while there are any.
+    rx_held: Cell<(usize, usize)>,
+    // Totals for `uartstat`: bytes handed to the UART to send, bytes
+    // received, transfers that ended in an error and the last such error.
+    tx_bytes: Cell<usize>,
+    rx_bytes: Cell<usize>,
+    errors: Cell<usize>,
+    last_error: Cell<Option<uart::Error>>,
+}
+
+impl<U: UART> Channel<'a, U> {
+    fn empty() -> Channel<'a, U> {
+        Channel {
+            uart: OptionalCell::empty(),
+            tx_buffer: TakeCell::empty(),
+            rx_buffer: TakeCell::empty(),
+            tx_address: Cell::new(0),
+            rx_address: Cell::new(0),
+            tx_stalled: Cell::new(false),
+            rx_capacity: Cell::new(0),
+            rx_chunk: Cell::new(1),
+            rx_in_progress: Cell::new(false),
+            rx_failures: Cell::new(0),
+            rx_healthy: Cell::new(true),
+            rx_held: Cell::new((0, 0)),
+            tx_bytes: Cell::new(0),
+            rx_bytes: Cell::new(0),
+            errors: Cell::new(0),
+            last_error: Cell::new(None),
+        }
+    }
+
+    fn set(&self, uart: &'a U, tx_buffer: &'static mut [u8], rx_buffer: &'static mut [u8]) {
+        self.uart.set(uart);
+        self.tx_address.set(tx_buffer.as_ptr() as usize);
+        self.rx_address.set(rx_buffer.as_ptr() as usize);
+        self.rx_capacity.set(rx_buffer.len());
+        self.tx_buffer.replace(tx_buffer);
+        self.rx_buffer.replace(rx_buffer);
+    }
+
+    fn configure(&self, baud_rate: u32) -> ReturnCode {
+        self.uart.map_or(ReturnCode::SUCCESS, |uart| {
+            uart.configure(uart::UARTParameters {
+                baud_rate: baud_rate,
+                stop_bits: uart::StopBits::One,
+                parity: uart::Parity::None,
+                hw_flow_control: false,
+            })
+        })
+    }
+
+    // Count a transfer that ended with `error` for `uartstat`.
+    fn record_error(&self, error: uart::Error) {
+        if error != uart::Error::CommandComplete {
+            self.errors.set(self.errors.get() + 1);
+            self.last_error.set(Some(error));
+        }
+    }
+
+    fn receive(&self) {
+        let (start, end) = self.rx_held.get();
+        if start < end {
+            return;
+        }
+        self.uart.map(|uart| {
+            self.rx_buffer.take().map(|buffer| {
+                self.rx_in_progress.set(true);
+                let len = cmp::min(self.rx_chunk.get(), buffer.len());
+                uart.receive(buffer, len);
+            });
+        });
+    }
+}
+
+pub struct ProcessConsole<'a, U: UART, C: ProcessManagementCapability> {
+    // The primary UART and an optional fallback. Output goes to whichever
+    // most recently received input.
+    channels: [Channel<'a, U>; 2],
+    active_channel: Cell<usize>,
+    tx_in_progress: Cell<bool>,
+    // The channel the transmission in progress is on, and when it started in
+    // ticks of the alarm, if there is one.
+    tx_channel: Cell<usize>,
+    tx_started: Cell<u32>,
+    // Output is held to this many bytes a second, as set with `set maxrate`,
+    // or 0 for no limit, and the time on the alarm when the next
+    // transmission may start. Limiting the rate needs the alarm.
+    max_rate: Cell<usize>,
+    tx_resume: Cell<Option<u32>>,
+    // How long `confirm` waits for an answer, or 0 for ever, and when on the
+    // alarm the question being asked times out.
+    confirm_timeout_ms: Cell<u32>,
+    confirm_deadline: Cell<u32>,
+    // The error `injecterror` makes the next receive complete with.
+    injected_error: Cell<Option<uart::Error>>,
+    // The last transmission left output queued. The rest goes to the same
+    // channel, so that a message longer than a write buffer is not split
+    // across UARTs.
+    tx_continues: Cell<bool>,
+    output_queue: MapCell<RingBuffer<'static, u8>>,
+    // The last byte transmitted did not end a line, so debug output printed
+    // after it lands mid-line.
+    mid_line: Cell<bool>,
+    output_capture: OptionalCell<&'a OutputCapture>,
+    alarm_list: OptionalCell<&'a AlarmList>,
+    alarm: OptionalCell<&'a ConsoleAlarm>,
+    adc: OptionalCell<&'a ConsoleAdc>,
+    log_storage: OptionalCell<&'a LogStorage>,
+    app_flash: OptionalCell<&'a AppFlash>,
+    temperature: OptionalCell<&'a TemperatureDriver>,
+    date_time: OptionalCell<&'a DateTime>,
+    clock_scaling: OptionalCell<&'a ClockScaling>,
+    // The I2C device `probe i2c` moves along the bus, and the SPI device
+    // `probe spi` asks for its ID, with their buffers.
+    i2c: OptionalCell<&'a virtual_i2c::I2CDevice<'a>>,
+    i2c_buffer: TakeCell<'static, [u8]>,
+    spi: OptionalCell<&'a SpiMasterDevice>,
+    spi_write_buffer: TakeCell<'static, [u8]>,
+    spi_read_buffer: TakeCell<'static, [u8]>,
+    reset_cause: OptionalCell<&'a ResetCause>,
+    brown_out: OptionalCell<&'a BrownOutDetector>,
+    // The underlying UART of the primary channel, for `uartstat`.
+    uart_status: OptionalCell<&'a uart::UARTStatus>,
+    // The most recent commands run, and how many have been logged since it
+    // was set or cleared.
+    command_log: TakeCell<'static, [CommandLogEntry]>,
+    command_log_count: Cell<usize>,
+    // Where the kernel and apps are in flash, for `flashinfo`.
+    flash_layout: OptionalCell<&'a FlashLayout>,
+    hardware_id: OptionalCell<&'a [u8]>,
+    ipc: OptionalCell<&'a IPC>,
+    unknown_command_handler: OptionalCell<&'a UnknownCommandHandler>,
+    // Commands with more tokens than this are rejected.
+    max_tokens: Cell<usize>,
+    // Processes as of the last `snapshot`, by index, and whether one was taken.
+    snapshot: Cell<[Option<ProcessSnapshot>; SNAPSHOT_LEN]>,
+    snapshot_taken: Cell<bool>,
+    // What remains to be run of the startup script.
+    script: Cell<&'static [u8]>,
+    // `run_script` is running lines of the script.
+    script_running: Cell<bool>,
+    // The chains `run` knows, and the name and remaining steps of the one
+    // running, if any, with the step run last.
+    chains: Cell<&'static [(&'static str, &'static [&'static str])]>,
+    chain: Cell<Option<(&'static str, &'static [&'static str])>>,
+    chain_step: Cell<&'static str>,
+    // `run_chain` is running steps of the chain.
+    chain_running: Cell<bool>,
+    // The command being run printed why it failed.
+    command_failed: Cell<bool>,
+    // Don't announce that the console started.
+    quiet: Cell<bool>,
+    // Width of the terminal in columns, as set with `set width`. Longer
+    // lines are wrapped, and narrower than 80 columns `list` prints one field
+    // per line. 0 means nothing is wrapped.
+    width: Cell<usize>,
+    // Output may use ANSI colors, as set with `set color`: `list` prints its
+    // header in bold, running processes in green and faulted ones in red.
+    // Output to apps and `describe` never has colors.
+    color: Cell<bool>,
+    // Input is taken raw rather than edited, as set with `set raw`: nothing
+    // is echoed, no prompt is printed, there is no line editing, completion
+    // or continuation, and a whole line is run as one command without being
+    // split at the separator.
+    raw: Cell<bool>,
+    // Typed input is echoed, as set with `set echo`, and otherwise the
+    // character shown for each character typed, as set with `set maskchar`,
+    // or 0 for none. Line editing still works with echo off, for entering
+    // passwords, and backspace erases one mask character.
+    echo_input: Cell<bool>,
+    mask: Cell<u8>,
+    // Entering an empty line does nothing at all, as set with `set
+    // emptyline`, not even moving to a new line, rather than starting a new
+    // line with a prompt. A line of only spaces is empty too.
+    empty_line_ignored: Cell<bool>,
+    // Every prompt is preceded by a status line, as set with `set
+    // statusline`.
+    status_line: Cell<bool>,
+    // Commands that are not recognized are followed by the bytes received
+    // for them in hex, such as `raw: 6c 69 73 74`, as set with `set
+    // debugparse`, to show stray characters, and whether the last one run was
+    // not recognized.
+    debug_parse: Cell<bool>,
+    parse_failed: Cell<bool>,
+    // Debug output is held while commands run, as set with `set coalesce`,
+    // whether it is being held now, and whether to let it go once the
+    // output queue empties.
+    coalesce: Cell<bool>,
+    debug_held: Cell<bool>,
+    debug_release: Cell<bool>,
+    // Keys typed at an empty prompt run commands, as set with `set keymode`,
+    // and whether `:` has opened the line for a command typed in full.
+    single_key: Cell<bool>,
+    key_line: Cell<bool>,
+    // The key that ends a watch is kept for the next command, as set with
+    // `set typeahead`.
+    typeahead: Cell<bool>,
+    // Command lines end with a result line, as set with `set resultline`,
+    // and whether the line being run owes one, has failed, and with what
+    // error.
+    result_line: Cell<bool>,
+    result_pending: Cell<bool>,
+    result_failed: Cell<bool>,
+    result_code: Cell<Option<ReturnCode>>,
+    // The listing a break cut short, for `resume`, and when.
+    suspended: Cell<Option<Stream>>,
+    suspended_at: Cell<u32>,
+    // The next line entered picks a command from the menu, and the command
+    // picked to be put on the command line for its arguments to be typed.
+    menu_selecting: Cell<bool>,
+    menu_prefill: Cell<Option<&'static str>>,
+    // Sizes of the command buffer and output queue, for `describe`.
+    command_capacity: usize,
+    output_capacity: usize,
+    baud_rate: u32,
+    command_buffer: TakeCell<'static, [u8]>,
+    command_index: Cell<usize>,
+    // Where the line being typed starts in the command buffer; non-zero when
+    // earlier lines were continued with a trailing backslash.
+    line_start: Cell<usize>,
+    running: Cell<bool>,
+    // The prompt and current line need to be reprinted once the
+    // UART is free of debug output.
+    redraw_pending: Cell<bool>,
+    input_mode: Cell<InputMode>,
+    // Input received in `InputMode::Busy`, to be handled once the command
+    // completes, and how many bytes of such input did not fit.
+    input_queue: MapCell<RingBuffer<'static, u8>>,
+    input_dropped: Cell<usize>,
+    // `replay_input` is handling queued input.
+    replaying: Cell<bool>,
+    // Input is discarded until the UART has been quiet for `settle_ms`
+    // since `quiet_since`, and `noise` counts the bytes discarded.
+    settle_ms: Cell<u32>,
+    settling: Cell<bool>,
+    quiet_since: Cell<u32>,
+    noise: Cell<usize>,
+    // How long after starting input makes the console take a host tool to be
+    // connected, and until when on the alarm it is still waiting to see.
+    detect_ms: Cell<u32>,
+    detect_until: Cell<Option<u32>>,
+    // Character separating commands on one line, or 0 for none.
+    separator: Cell<u8>,
+    // Character typed lines must start with to be run, or 0 for none.
+    prefix: Cell<u8>,
+    // Where the next command of a line with several starts in the command
+    // buffer, or 0 if there is none waiting.
+    next_segment: Cell<usize>,
+    stream: Cell<Stream>,
+    // Some of the current command's output was dropped.
+    truncated: Cell<bool>,
+    // The most bytes the output queue has held and how many bytes of output
+    // have been dropped because it was full, since boot or `txqueue clear`.
+    queue_peak: Cell<usize>,
+    queue_dropped: Cell<usize>,
+    // When the command being timed by `time` started, in alarm ticks.
+    timing: Cell<Option<u32>>,
+    // Only output lines containing the first `.1` bytes of this are printed.
+    filter: Cell<([u8; FILTER_LEN], usize)>,
+    // The capsules on the board, as (name, type), for `caps`.
+    capsules: Cell<&'static [(&'static str, &'static str)]>,
+    // The syscall drivers on the board, as (number, name), for
+    // `checkdriver`.
+    drivers: Cell<&'static [(usize, &'static str)]>,
+    // The storage volumes `mounts` lists.
+    storage_volumes: Cell<&'a [&'a StorageVolume]>,
+    // Pins `gpio` can read and drive, by index.
+    gpio_pins: Cell<&'a [&'a gpio::Pin]>,
+    // Set while running a command issued by an app through the syscall
+    // interface, whose output goes to that app's buffer instead of the UART.
+    output_app: OptionalCell<AppId>,
+    apps: Grant<App>,
+    kernel: &'static Kernel,
+    capability: C,
+}
+
+impl<U: UART, C: ProcessManagementCapability> ProcessConsole<'a, U, C> {
+    pub fn new(
+        uart: &'a U,
+        baud_rate: u32,
+        tx_buffer: &'static mut [u8],
+        rx_buffer: &'static mut [u8],
+        cmd_buffer: &'static mut [u8],
+        output_buffer: &'static mut [u8],
+        grant: Grant<App>,
+        kernel: &'static Kernel,
+        capability: C,
+    ) -> ProcessConsole<'a, U, C> {
+        let command_capacity = cmd_buffer.len();
+        let output_capacity = output_buffer.len();
+        let primary = Channel::empty();
+        primary.set(uart, tx_buffer, rx_buffer);
+        ProcessConsole {
+            channels: [primary, Channel::empty()],
+            active_channel: Cell::new(0),
+            tx_in_progress: Cell::new(false),
+            tx_channel: Cell::new(0),
+            tx_started: Cell::new(0),
+            max_rate: Cell::new(0),
+            tx_resume: Cell::new(None),
+            confirm_timeout_ms: Cell::new(CONFIRM_TIMEOUT_MS),
+            confirm_deadline: Cell::new(0),
+            injected_error: Cell::new(None),
+            tx_continues: Cell::new(false),
+            output_queue: MapCell::new(RingBuffer::new(output_buffer)),
+            mid_line: Cell::new(false),
+            output_capture: OptionalCell::empty(),
+            alarm_list: OptionalCell::empty(),
+            alarm: OptionalCell::empty(),
+            adc: OptionalCell::empty(),
+            log_storage: OptionalCell::empty(),
+            app_flash: OptionalCell::empty(),
+            temperature: OptionalCell::empty(),
+            date_time: OptionalCell::empty(),
+            clock_scaling: OptionalCell::empty(),
+            i2c: OptionalCell::empty(),
+            i2c_buffer: TakeCell::empty(),
+            spi: OptionalCell::empty(),
+            spi_write_buffer: TakeCell::empty(),
+            spi_read_buffer: TakeCell::empty(),
+            reset_cause: OptionalCell::empty(),
+            brown_out: OptionalCell::empty(),
+            uart_status: OptionalCell::empty(),
+            command_log: TakeCell::empty(),
+            command_log_count: Cell::new(0),
+            flash_layout: OptionalCell::empty(),
+            hardware_id: OptionalCell::empty(),
+            ipc: OptionalCell::empty(),
+            unknown_command_handler: OptionalCell::empty(),
+            max_tokens: Cell::new(DEFAULT_MAX_TOKENS),
+            snapshot: Cell::new([None; SNAPSHOT_LEN]),
+            snapshot_taken: Cell::new(false),
+            script: Cell::new(&[]),
+            script_running: Cell::new(false),
+            chains: Cell::new(&[]),
+            chain: Cell::new(None),
+            chain_step: Cell::new(""),
+            chain_running: Cell::new(false),
+            command_failed: Cell::new(false),
+            quiet: Cell::new(false),
+            width: Cell::new(DEFAULT_WIDTH),
+            color: Cell::new(false),
+            raw: Cell::new(false),
+            echo_input: Cell::new(true),
+            mask: Cell::new(0),
+            empty_line_ignored: Cell::new(false),
+            status_line: Cell::new(false),
+            debug_parse: Cell::new(false),
+            parse_failed: Cell::new(false),
+            coalesce: Cell::new(false),
+            debug_held: Cell::new(false),
+            debug_release: Cell::new(false),
+            single_key: Cell::new(false),
+            key_line: Cell::new(false),
+            typeahead: Cell::new(false),
+            result_line: Cell::new(false),
+            result_pending: Cell::new(false),
+            result_failed: Cell::new(false),
+            result_code: Cell::new(None),
+            suspended: Cell::new(None),
+            suspended_at: Cell::new(0),
+            menu_selecting: Cell::new(false),
+            menu_prefill: Cell::new(None),
+            command_capacity: command_capacity,
+            output_capacity: output_capacity,
+            baud_rate: baud_rate,
+            command_buffer: TakeCell::new(cmd_buffer),
+            command_index: Cell::new(0),
+            line_start: Cell::new(0),
+            running: Cell::new(false),
+            redraw_pending: Cell::new(false),
+            input_mode: Cell::new(InputMode::Command),
+            input_queue: MapCell::empty(),
+            input_dropped: Cell::new(0),
+            queue_peak: Cell::new(0),
+            queue_dropped: Cell::new(0),
+            replaying: Cell::new(false),
+            settle_ms: Cell::new(0),
+            detect_ms: Cell::new(0),
+            detect_until: Cell::new(None),
+            settling: Cell::new(false),
+            quiet_since: Cell::new(0),
+            noise: Cell::new(0),
+            separator: Cell::new(b';'),
+            prefix: Cell::new(0),
+            next_segment: Cell::new(0),
+            stream: Cell::new(Stream::Idle),
+            truncated: Cell::new(false),
+            timing: Cell::new(None),
+            filter: Cell::new(([0; FILTER_LEN], 0)),
+            capsules: Cell::new(&[]),
+            drivers: Cell::new(&[]),
+            storage_volumes: Cell::new(&[]),
+            gpio_pins: Cell::new(&[]),
+            output_app: OptionalCell::empty(),
+            apps: grant,
+            kernel: kernel,
+            capability: capability,
+        }
+    }
+
+    /// Configure the UARTs. Returns the error of the first UART that rejects
+    /// its configuration, such as the baud rate, or `SUCCESS`. Every UART is
+    /// configured even if an earlier one fails.
+    pub fn initialize(&self) -> ReturnCode {
+        let mut result = ReturnCode::SUCCESS;
+        for channel in self.channels.iter() {
+            let configured = channel.configure(self.baud_rate);
+            if result == ReturnCode::SUCCESS {
+                result = configured;
+            }
+        }
+        result
+    }
+
+    /// Check that the console holds all of its buffers, and that none of them
+    /// is empty or was also given to it as another of its buffers, as can
+    /// happen when a board passes the same static buffer twice. Each problem
+    /// is reported with `debug!`, since the console cannot work without its
+    /// buffers. Returns `EINVAL` if there are any, or `SUCCESS`. `start`
+    /// also checks.
+    pub fn validate_buffers(&self) -> ReturnCode {
+        let mut result = ReturnCode::SUCCESS;
+        let command = self.command_buffer.map_or(None, |command| {
+            Some((command.as_ptr() as usize, command.len()))
+        });
+        match command {
+            Some((_, len)) if len >= 2 => {}
+            Some(_) => {
+                debug!("ProcessConsole: command buffer is too short to hold a command");
+                result = ReturnCode::EINVAL;
+            }
+            None => {
+                debug!("ProcessConsole: command buffer is missing");
+                result = ReturnCode::EINVAL;
+            }
+        }
+        if self.output_capacity == 0 {
+            debug!("ProcessConsole: output buffer is empty");
+            result = ReturnCode::EINVAL;
+        }
+        for (i, channel) in self.channels.iter().enumerate() {
+            if channel.uart.is_none() {
+                continue;
+            }
+            // While a transfer is underway its buffer is with the UART.
+            let transmitting = self.tx_in_progress.get() && self.tx_channel.get() == i;
+            let tx = channel
+                .tx_buffer
+                .map_or(!transmitting, |buffer| buffer.is_empty());
+            let rx = channel
+                .rx_buffer
+                .map_or(!channel.rx_in_progress.get(), |buffer| buffer.is_empty());
+            if tx {
+                debug!(
+                    "ProcessConsole: UART {} write buffer is missing or empty",
+                    i
+                );
+                result = ReturnCode::EINVAL;
+            }
+            if rx {
+                debug!("ProcessConsole: UART {} read buffer is missing or empty", i);
+                result = ReturnCode::EINVAL;
+            }
+            let (tx_address, rx_address) = (channel.tx_address.get(), channel.rx_address.get());
+            let shared = tx_address == rx_address
+                || command.map_or(false, |(address, _)| {
+                    address == tx_address || address == rx_address
+                })
+                || self.channels[..i].iter().any(|other| {
+                    other.uart.is_some()
+                        && (other.tx_address.get() == tx_address
+                            || other.rx_address.get() == rx_address)
+                });
+            if shared {
+                debug!(
+                    "ProcessConsole: UART {} buffers are also used as other console buffers",
+                    i
+                );
+                result = ReturnCode::EINVAL;
+            }
+        }
+        result
+    }
+
+    /// Also accept commands on a second UART. Output goes to whichever UART
+    /// last received input. Must be called before `initialize()`, and the
+    /// console must be set as the client of `uart`.
+    pub fn set_fallback_uart(
+        &self,
+        uart: &'a U,
+        tx_buffer: &'static mut [u8],
+        rx_buffer: &'static mut [u8],
+    ) {
+        self.channels[1].set(uart, tx_buffer, rx_buffer);
+    }
+
+    /// Queue input that arrives while a command is running in `buffer`, such
+    /// as `INPUT_BUF`, instead of discarding it, so that several commands can
+    /// be pasted at once.
+    pub fn set_input_queue(&self, buffer: &'static mut [u8]) {
+        self.input_queue.put(RingBuffer::new(buffer));
+    }
+
+    /// Give the console back the transmit and receive buffers of its primary
+    /// UART, for recovering a console whose UART was reset while a transfer
+    /// was in flight and so never returned them. The console resets its
+    /// transfer state, listens for input again and carries on with any
+    /// queued output.
+    ///
+    /// The UART must no longer hold or use either buffer, for example because
+    /// it has been reset, and the buffers are usually the same statics passed
+    /// to `new()`. Otherwise the UART and the console write to the same
+    /// buffer. The fallback UART's buffers can be handed back with
+    /// `set_fallback_uart()`.
+    pub fn reclaim_buffers(&self, tx_buffer: &'static mut [u8], rx_buffer: &'static mut [u8]) {
+        let channel = &self.channels[0];
+        channel.tx_address.set(tx_buffer.as_ptr() as usize);
+        channel.rx_address.set(rx_buffer.as_ptr() as usize);
+        channel.rx_capacity.set(rx_buffer.len());
+        channel.tx_stalled.set(false);
+        channel.tx_buffer.replace(tx_buffer);
+        channel.rx_buffer.replace(rx_buffer);
+        channel.rx_in_progress.set(false);
+        channel.rx_held.set((0, 0));
+        if self.tx_channel.get() == 0 {
+            self.tx_in_progress.set(false);
+        }
+        if self.running.get() {
+            channel.receive();
+        }
+        self.pump();
+    }
+
+    /// Register the capsules the board instantiated, as (name, type) pairs,
+    /// for `caps` to list.
+    pub fn set_capsule_list(&self, capsules: &'static [(&'static str, &'static str)]) {
+        self.capsules.set(capsules);
+    }
+
+    /// Register the syscall drivers the board's `with_driver` gives apps, as
+    /// (driver number, name) pairs, for `checkdriver` to look up.
+    pub fn set_driver_list(&self, drivers: &'static [(usize, &'static str)]) {
+        self.drivers.set(drivers);
+    }
+
+    /// Register named chains of commands for `run` to run, as (name, steps)
+    /// pairs. A step starting with `-` is optional: the chain carries on if
+    /// it fails.
+    pub fn set_chains(&self, chains: &'static [(&'static str, &'static [&'static str])]) {
+        self.chains.set(chains);
+    }
+
+    /// Register the storage volumes on the board for `mounts` to list.
+    pub fn set_storage_volumes(&self, volumes: &'a [&'a StorageVolume]) {
+        self.storage_volumes.set(volumes);
+    }
+
+    /// Keep a log of the commands run in `buffer`, for example `CMDLOG_BUF`,
+    /// for `cmdlog` to print. Once it is full the oldest are dropped.
+    pub fn set_command_log(&self, buffer: &'static mut [CommandLogEntry]) {
+        self.command_log.replace(buffer);
+        self.command_log_count.set(0);
+    }
+
+    /// Tell the console where the kernel and apps are in flash, for
+    /// `flashinfo` to report how much of it is used.
+    pub fn set_flash_layout(&self, layout: &'a FlashLayout) {
+        self.flash_layout.set(layout);
+    }
+
+    /// Set the chip's unique identifier for `hwid` to print, most significant
+    /// byte first.
+    pub fn set_hardware_id(&self, id: &'a [u8]) {
+        self.hardware_id.set(id);
+    }
+
+    /// Register GPIO pins for `gpio` to read and drive, addressed by their
+    /// index in `pins`. Only pins that are safe to change from the console,
+    /// such as free header pins, should be included.
+    pub fn set_gpio_pins(&self, pins: &'a [&'a gpio::Pin]) {
+        self.gpio_pins.set(pins);
+    }
+
+    /// Set where `tail` gets the recent output of processes from.
+    pub fn set_output_capture(&self, capture: &'a OutputCapture) {
+        self.output_capture.set(capture);
+    }
+
+    /// Pass commands the console does not recognize to `handler` before
+    /// reporting them as unknown.
+    pub fn set_unknown_command_handler(&self, handler: &'a UnknownCommandHandler) {
+        self.unknown_command_handler.set(handler);
+    }
+
+    /// Set the IPC driver whose connections `ipc` lists.
+    pub fn set_ipc(&self, ipc: &'a IPC) {
+        self.ipc.set(ipc);
+    }
+
+    /// Set the alarm multiplexer whose alarms `timers` lists.
+    pub fn set_alarm_list(&self, alarms: &'a AlarmList) {
+        self.alarm_list.set(alarms);
+    }
+
+    /// Set the alarm used by commands that wait, such as `irqtest` and
+    /// `watch`. The console must also be set as the alarm's client, and
+    /// nothing else may use it.
+    pub fn set_alarm(&self, alarm: &'a ConsoleAlarm) {
+        self.alarm.set(alarm);
+    }
+
+    /// Set the ADC `adc` samples with. The console must also be set as the
+    /// ADC's client, so an ADC used by the userspace ADC driver cannot be
+    /// shared with the console.
+    pub fn set_adc(&self, adc: &'a ConsoleAdc) {
+        self.adc.set(adc);
+    }
+
+    /// Set the sensor `temp` reads. The console must also be set as the
+    /// sensor's client, so a sensor used by the userspace temperature driver
+    /// cannot be shared with the console.
+    pub fn set_temperature(&self, sensor: &'a TemperatureDriver) {
+        self.temperature.set(sensor);
+    }
+
+    /// Set the real-time clock `date` reads and sets. The console must also
+    /// be set as the clock's client.
+    pub fn set_date_time(&self, clock: &'a DateTime) {
+        self.date_time.set(clock);
+    }
+
+    /// Set the CPU clock `clock` reports and switches the frequency of.
+    pub fn set_clock_scaling(&self, clock: &'a ClockScaling) {
+        self.clock_scaling.set(clock);
+    }
+
+    /// Set the flash `erase` erases app slots in, for example an
+    /// `AppFlashPages` over the chip's flash controller. The console must
+    /// also be set as the flash's client.
+    pub fn set_app_flash(&self, flash: &'a AppFlash) {
+        self.app_flash.set(flash);
+    }
+
+    /// Set the log storage `logflush` and `logrotate` act on. The console must
+    /// also be set as its client.
+    pub fn set_log_storage(&self, log: &'a LogStorage) {
+        self.log_storage.set(log);
+    }
+
+    /// Give `probe i2c` a device on the I2C bus to scan it with, for example
+    /// with `I2C_PROBE_BUF`. The device's address is changed as it scans. The
+    /// console must also be set as the device's client.
+    pub fn set_i2c(&self, device: &'a virtual_i2c::I2CDevice<'a>, buffer: &'static mut [u8]) {
+        self.i2c.set(device);
+        self.i2c_buffer.replace(buffer);
+    }
+
+    /// Give `probe spi` a device on a SPI bus to read the ID of, for example
+    /// with `SPI_PROBE_WRITE_BUF` and `SPI_PROBE_READ_BUF`. The console must
+    /// also be set as the device's client.
+    pub fn set_spi(
+        &self,
+        device: &'a SpiMasterDevice,
+        write_buffer: &'static mut [u8],
+        read_buffer: &'static mut [u8],
+    ) {
+        self.spi.set(device);
+        self.spi_write_buffer.replace(write_buffer);
+        self.spi_read_buffer.replace(read_buffer);
+    }
+
+    /// Discard input until the UART has been quiet for `ms` milliseconds after
+    /// the console starts, each byte received before then restarting the
+    /// wait. This keeps noise on the receive line from becoming part of the
+    /// first command. It needs the alarm given with `set_alarm`, and must be
+    /// called before `start()`. 0, the default, accepts input straight away.
+    pub fn set_settle_time(&self, ms: u32) {
+        self.settle_ms.set(ms);
+    }
+
+    /// Tell a host tool from a person by when input arrives: if any arrives
+    /// within `ms` milliseconds of the console starting, a tool must have
+    /// sent it on connecting, so the console switches to raw input, as with
+    /// `set raw on`, without printing the banner or a prompt. Otherwise it
+    /// prints them once the time is up. It needs the alarm given with
+    /// `set_alarm`, must be called before `start()`, and is not used with a
+    /// startup script. 0, the default, prints them straight away.
+    pub fn set_host_detection(&self, ms: u32) {
+        self.detect_ms.set(ms);
+    }
+
+    /// Set where `resetreason` reads why the chip last reset.
+    pub fn set_reset_cause(&self, reset_cause: &'a ResetCause) {
+        self.reset_cause.set(reset_cause);
+    }
+
+    /// Set the brown-out detector `bod` reports on and configures.
+    pub fn set_brown_out(&self, detector: &'a BrownOutDetector) {
+        self.brown_out.set(detector);
+    }
+
+    /// Let `uartstat` report the transfers `uart`, the hardware UART the
+    /// primary UART sends through, has in progress.
+    pub fn set_uart_status(&self, uart: &'a uart::UARTStatus) {
+        self.uart_status.set(uart);
+    }
+
+    /// Reject commands made of more than `max_tokens` whitespace-separated
+    /// tokens, counting the command name, with "Too many arguments". The
+    /// default is 8.
+    pub fn set_max_tokens(&self, max_tokens: usize) {
+        self.max_tokens.set(max_tokens);
+    }
+
+    /// Run the commands in `script`, one per line, when the console starts.
+    /// The script ends at the first 0x00 or 0xFF byte or after 256 bytes,
+    /// whichever comes first.
+    pub fn set_startup_script(&self, script: &'static [u8]) {
+        let script = &script[..cmp::min(script.len(), SCRIPT_MAX_LEN)];
+        let end = script
+            .iter()
+            .position(|byte| *byte == 0x00 || *byte == 0xff)
+            .unwrap_or(script.len());
+        self.script.set(&script[..end]);
+    }
+
+    /// Don't print "Starting process console" when the console starts, for
+    /// boards where the console shares the UART with other output. Warnings
+    /// about the console's configuration are still printed.
+    pub fn set_quiet(&self, quiet: bool) {
+        self.quiet.set(quiet);
+    }
+
+    pub fn start(&self) -> ReturnCode {
+        if self.running.get() == false {
+            for channel in self.channels.iter() {
+                channel.receive();
+            }
+            self.running.set(true);
+            let detecting = self.start_host_detection();
+            if !self.quiet.get() && !detecting {
+                debug!("{}", BANNER);
+            }
+            let _ = self.validate_buffers();
+            // One byte of the command buffer holds the terminating 0.
+            let longest = CommandNames { prefix: "" }
+                .names()
+                .map(|name| name.len())
+                .max()
+                .unwrap_or(0);
+            let needed = longest + ARGUMENTS_LEN + 1;
+            if self.command_capacity < needed {
+                debug!(
+                    "ProcessConsole: command buffer of {} bytes cannot hold the longest commands, which need {}",
+                    self.command_capacity,
+                    needed
+                );
+            }
+            if self.settle_ms.get() > 0 {
+                match self.alarm.map(|alarm| alarm.now()) {
+                    Some(now) => {
+                        self.settling.set(true);
+                        self.quiet_since.set(now);
+                    }
+                    None => debug!("ProcessConsole: settle time needs an alarm, ignoring it"),
+                }
+            }
+            // While detecting a host tool, `fired` prints the prompt if no
+            // input arrives in time.
+            if !detecting {
+                if self.script.get().is_empty() {
+                    self.redraw();
+                } else {
+                    self.input_mode.set(InputMode::Busy);
+                    self.run_script();
+                }
+            }
+        }
+        ReturnCode::SUCCESS
+    }
+
+    // Run the startup script a line at a time, as if each line were typed.
+    // If a line's output is still streaming when it returns, the rest of the
+    // script runs once the line's command completes.
+    fn run_script(&self) {
+        self.script_running.set(true);
+        while self.stream.get() == Stream::Idle
+            && self.input_mode.get() == InputMode::Busy
+            && !self.script.get().is_empty()
+        {
+            let script = self.script.get();
+            let end = script
+                .iter()
+                .position(|byte| *byte == b'\n')
+                .unwrap_or(script.len());
+            self.script.set(&script[cmp::min(end + 1, script.len())..]);
+            let line = match script[..end].last() {
+                Some(&b'\r') => &script[..end - 1],
+                _ => &script[..end],
+            };
+            if line.is_empty() {
+                continue;
+            }
+            let fits = self.command_buffer.map_or(false, |command| {
+                if line.len() >= command.len() {
+                    return false;
+                }
+                command[..line.len()].copy_from_slice(line);
+                command[line.len()] = 0;
+                true
+            });
+            if !fits {
+                output!(self, "Script line too long: {}", Escaped(line));
+                continue;
+            }
+            self.enqueue(PROMPT);
+            self.enqueue(line);
+            self.enqueue(b"\r\n");
+            self.read_command();
+        }
+        self.script_running.set(false);
+        if self.stream.get() == Stream::Idle {
+            self.command_complete();
+        }
+    }
+
+    fn run(&self, command: &str) {
+        let chains = self.chains.get();
+        let name = match command.split_whitespace().nth(1) {
+            Some(name) => name,
+            None => {
+                if chains.is_empty() {
+                    output!(self, "No chains registered");
+                }
+                for &(name, steps) in chains {
+                    output!(self, "{}:{}", name, Steps(steps));
+                }
+                return;
+            }
+        };
+        let chain = match chains.iter().find(|&&(chain, _)| chain == name) {
+            Some(&chain) => chain,
+            None => {
+                fail!(self, "No chain {}", name);
+                return;
+            }
+        };
+        if self.chain.get().is_some() {
+            fail!(
+                self,
+                "run: chain {} is already running",
+                self.chain.get().map_or("", |(name, _)| name)
+            );
+            return;
+        }
+        // `command_complete` starts the chain once this line has finished.
+        self.chain.set(Some(chain));
+        self.chain_step.set("");
+    }
+
+    // Run the steps of the chain started by `run`, as if each were typed,
+    // until one fails. If a step's output is still streaming when it returns,
+    // the rest of the chain runs once the step's command completes.
+    fn run_chain(&self) {
+        self.chain_running.set(true);
+        while self.stream.get() == Stream::Idle && self.input_mode.get() == InputMode::Busy {
+            let (name, steps) = match self.chain.get() {
+                Some(chain) => chain,
+                None => break,
+            };
+            let step = self.chain_step.get();
+            if self.command_failed.get() && !step.is_empty() && !step.starts_with('-') {
+                output!(self, "Chain {} stopped: '{}' failed", name, step);
+                self.chain.set(None);
+                break;
+            }
+            let (step, rest) = match steps.split_first() {
+                Some((step, rest)) => (*step, rest),
+                None => {
+                    self.chain.set(None);
+                    break;
+                }
+            };
+            self.chain.set(Some((name, rest)));
+            self.chain_step.set(step);
+            self.command_failed.set(false);
+            let line = step.trim_left_matches('-').trim().as_bytes();
+            let fits = self.command_buffer.map_or(false, |command| {
+                if line.len() >= command.len() {
+                    return false;
+                }
+                command[..line.len()].copy_from_slice(line);
+                command[line.len()] = 0;
+                true
+            });
+            if !fits {
+                fail!(self, "Chain step too long: {}", Escaped(line));
+                continue;
+            }
+            self.enqueue(PROMPT);
+            self.enqueue(line);
+            self.enqueue(b"\r\n");
+            self.read_command();
+        }
+        self.chain_running.set(false);
+        if self.stream.get() == Stream::Idle {
+            self.command_complete();
+        }
+    }
+
+    // Run the command line in the command buffer, a `separator`-separated
+    // command at a time, and clear the buffer. If a command is still printing
+    // its output or waiting for confirmation when it returns, the rest of the
+    // line runs once it completes.
+    fn read_command(&self) {
+        if self.next_segment.get() == 0 && !self.script_running.get() && !self.take_prefix() {
+            return;
+        }
+        // A raw line is one command, whatever it contains.
+        let separator = if self.raw.get() {
+            0
+        } else {
+            self.separator.get()
+        };
+        self.command_buffer.map(|command| {
+            let end = command.iter().position(|&byte| byte == 0).unwrap_or(0);
+            if end > 0 {
+                self.input_mode.set(InputMode::Busy);
+                self.hold_debug_output();
+            }
+            if command[..end].iter().all(|byte| byte.is_ascii_whitespace()) {
+                // An empty line leaves the menu.
+                self.menu_selecting.set(false);
+            }
+            let mut start = self.next_segment.get();
+            while start < end
+                && self.stream.get() == Stream::Idle
+                && self.input_mode.get() == InputMode::Busy
+            {
+                let stop = command[start..end]
+                    .iter()
+                    .position(|&byte| separator != 0 && byte == separator)
+                    .map_or(end, |i| start + i);
+                if !self.result_pending.get() {
+                    self.start_result();
+                }
+                match str::from_utf8(&command[start..stop]) {
+                    Ok(s) => {
+                        if !s.trim().is_empty() {
+                            self.parse_failed.set(false);
+                            self.execute(s.trim());
+                            if self.debug_parse.get() && self.parse_failed.get() {
+                                output!(self, "raw:{}", HexBytes(&command[start..stop]));
+                            }
+                        }
+                    }
+                    Err(_e) => fail!(self, "Invalid command: {:?}", &command[start..stop]),
+                }
+                start = stop + 1;
+            }
+            self.next_segment.set(if start < end { start } else { 0 });
+        });
+        let waiting = match self.input_mode.get() {
+            InputMode::Confirm(_) => true,
+            _ => self.stream.get() != Stream::Idle,
+        };
+        if self.next_segment.get() != 0 && waiting {
+            // `command_complete` carries on with the rest of the line.
+            return;
+        }
+        self.next_segment.set(0);
+        self.command_buffer.map(|command| {
+            command[0] = 0;
+        });
+        self.command_index.set(0);
+        self.line_start.set(0);
+        if self.stream.get() == Stream::Idle {
+            self.command_complete();
+        }
+    }
+
+    // With a command prefix set, check that the line typed starts with it and
+    // remove it. A line without it is not for the console: it goes to the
+    // unknown command handler, if there is one, and is otherwise ignored.
+    // Returns whether the console should run the line.
+    fn take_prefix(&self) -> bool {
+        let prefix = self.prefix.get();
+        if prefix == 0 {
+            return true;
+        }
+        let for_console = self.command_buffer.map_or(true, |command| {
+            let end = command.iter().position(|&byte| byte == 0).unwrap_or(0);
+            match command[..end]
+                .iter()
+                .position(|byte| !byte.is_ascii_whitespace())
+            {
+                Some(i) if command[i] == prefix => {
+                    command[i] = b' ';
+                    true
+                }
+                Some(_) => {
+                    if let Ok(line) = str::from_utf8(&command[..end]) {
+                        self.log_command(line.trim(), CommandSource::Passthrough);
+                        self.unknown_command_handler
+                            .map(|handler| handler.unknown_command(line.trim()));
+                    }
+                    command[0] = 0;
+                    false
+                }
+                // An empty line just prints the prompt again.
+                None => true,
+            }
+        });
+        if !for_console {
+            self.command_index.set(0);
+            self.line_start.set(0);
+            self.command_complete();
+        }
+        for_console
+    }
+
+    // Run a single command, with an optional `| text` filter on its output.
+    // Its output goes wherever `output_app` directs it.
+    fn execute(&self, line: &str) {
+        self.filter.set(([0; FILTER_LEN], 0));
+        self.check_tx_stalled();
+        if line.split_whitespace().next() == Some("time") {
+            self.time(line["time".len()..].trim());
+            return;
+        }
+        let source = if self.output_app.is_some() {
+            CommandSource::App
+        } else if self.script_running.get() {
+            CommandSource::Script
+        } else {
+            CommandSource::Uart(self.active_channel.get())
+        };
+        self.log_command(line, source);
+        if self.menu_selecting.get() {
+            self.menu_selecting.set(false);
+            self.menu_select(line.trim());
+            return;
+        }
+        let mut stages = line.splitn(2, '|');
+        let clean_str = stages.next().unwrap_or("").trim();
+        if let Some(pattern) = stages.next().map(|pattern| pattern.trim()) {
+            if pattern.contains('|') {
+                fail!(self, "Only one filter is supported");
+                return;
+            }
+            if pattern.len() > FILTER_LEN {
+                fail!(self, "Filter longer than {} characters", FILTER_LEN);
+                return;
+            }
+            let mut filter = [0; FILTER_LEN];
+            filter[..pattern.len()].copy_from_slice(pattern.as_bytes());
+            self.filter.set((filter, pattern.len()));
+        }
+        if clean_str
+            .split_whitespace()
+            .nth(self.max_tokens.get())
+            .is_some()
+        {
+            fail!(self, "Too many arguments");
+            return;
+        }
+        if clean_str.starts_with("menu") {
+            self.menu();
+        } else if clean_str.starts_with("help") {
+            output!(self, "Welcome to the process console.");
+            self.print_valid_commands();
+        } else if clean_str.starts_with("start") {
+            let argument = clean_str.split_whitespace().nth(1);
+            argument.map(|name| {
+                self.kernel
+                    .process_each_capability(&self.capability, |i, proc| {
+                        let proc_name = proc.get_process_name();
+                        if process_matches(i, proc_name, name) {
+                            proc.resume();
+                            output!(self, "Process {} resumed.", proc_name);
+                        }
+                    });
+            });
+        } else if clean_str.starts_with("stop") {
+            let argument = clean_str.split_whitespace().nth(1);
+            argument.map(|name| {
+                self.kernel
+                    .process_each_capability(&self.capability, |i, proc| {
+                        let proc_name = proc.get_process_name();
+                        if process_matches(i, proc_name, name) {
+                            proc.stop();
+                            output!(self, "Process {} stopped", proc_name);
+                        }
+                    });
+            });
+        } else if clean_str.starts_with("list") {
+            self.list();
+        } else if clean_str.starts_with("status") {
+            let info: KernelInfo = KernelInfo::new(self.kernel);
+            output!(
+                self,
+                "Total processes: {}",
+                info.number_loaded_processes(&self.capability)
+            );
+            output!(
+                self,
+                "Active processes: {}",
+                info.number_active_processes(&self.capability)
+            );
+            output!(
+                self,
+                "Timeslice expirations: {}",
+                info.timeslice_expirations(&self.capability)
+            );
+        } else if clean_str.starts_with("restarts") {
+            self.restarts(clean_str);
+        } else if clean_str.starts_with("restart") {
+            match clean_str.split_whitespace().nth(1) {
+                Some("faulted") => {
+                    let count = Cell::new(0);
+                    let erased = Cell::new(0);
+                    self.kernel
+                        .process_each_capability(&self.capability, |_i, proc| {
+                            if proc.get_state() != State::Fault {
+                                return;
+                            }
+                            if self.flash_erased(proc) {
+                                erased.set(erased.get() + 1);
+                            } else {
+                                proc.restart();
+                                count.set(count.get() + 1);
+                            }
+                        });
+                    output!(self, "Restarted {} faulted processes", count.get());
+                    if erased.get() > 0 {
+                        output!(self, "Skipped {} erased by `erase`", erased.get());
+                    }
+                }
+                _ => fail!(self, "Usage: restart faulted"),
+            }
+        } else if clean_str.starts_with("faultpolicy") {
+            self.fault_policy(clean_str);
+        } else if clean_str.starts_with("compact") {
+            self.compact(clean_str);
+        } else if clean_str.starts_with("dumpstate") {
+            self.stream.set(Stream::DumpState {
+                section: DumpSection::Kernel,
+                line: 0,
+            });
+            self.stream_next();
+        } else if clean_str.starts_with("grantaudit") {
+            self.grant_audit();
+        } else if clean_str.starts_with("resume") {
+            self.resume();
+        } else if clean_str.starts_with("grantmap") {
+            self.stream.set(Stream::GrantMap {
+                process: 0,
+                after: 0,
+            });
+            self.stream_next();
+        } else if clean_str.starts_with("set") {
+            self.set(clean_str);
+        } else if clean_str.starts_with("tail") {
+            self.tail(clean_str);
+        } else if clean_str.starts_with("argv") {
+            // Hidden from `help`: prints how a command line is split into
+            // arguments, one per line with its index and length, to make
+            // parsing problems easy to reproduce.
+            for (i, arg) in clean_str.split_whitespace().enumerate() {
+                output!(self, "argv[{}] ({}): {}", i, arg.len(), arg);
+            }
+        } else if clean_str.starts_with("timers") {
+            self.timers();
+        } else if clean_str.starts_with("caps") {
+            if self.capsules.get().is_empty() {
+                output!(self, "No capsules registered");
+            }
+            for &(name, type_name) in self.capsules.get() {
+                output!(self, "{:<20}{}", name, type_name);
+            }
+        } else if clean_str.starts_with("checkdriver") {
+            self.check_driver(clean_str);
+        } else if clean_str.starts_with("mounts") {
+            if self.storage_volumes.get().is_empty() {
+                output!(self, "No storage volumes registered");
+            }
+            for volume in self.storage_volumes.get() {
+                let (start, len) = volume.region();
+                match volume.free() {
+                    Some(free) => output!(
+                        self,
+                        "{:<20}{:#010x}  {} bytes, {} free",
+                        volume.name(),
+                        start,
+                        len,
+                        free
+                    ),
+                    None => output!(self, "{:<20}{:#010x}  {} bytes", volume.name(), start, len),
+                }
+            }
+        } else if clean_str.starts_with("dbggpio") {
+            self.dbggpio(clean_str);
+        } else if clean_str.starts_with("cmdlog") {
+            self.cmdlog(clean_str);
+        } else if clean_str.starts_with("flashinfo") {
+            self.flash_info();
+        } else if clean_str.starts_with("hwid") {
+            match self.hardware_id.map(|id| *id) {
+                Some(id) => output!(self, "{}", HexId(id)),
+                None => output!(self, "unavailable"),
+            }
+        } else if clean_str.starts_with("gpio") {
+            self.gpio(clean_str);
+        } else if clean_str.starts_with("idle") {
+            self.idle(clean_str);
+        } else if clean_str.starts_with("quantum") {
+            self.quantum(clean_str);
+        } else if clean_str.starts_with("snapshot") {
+            self.take_snapshot();
+        } else if clean_str.starts_with("diff") {
+            self.diff();
+        } else if clean_str.starts_with("ipc") {
+            self.ipc(clean_str);
+        } else if clean_str.starts_with("callbacks") {
+            self.callbacks(clean_str);
+        } else if clean_str.starts_with("errors") {
+            for (i, channel) in self.channels.iter().enumerate() {
+                if channel.uart.is_some() {
+                    output!(
+                        self,
+                        "UART {}: receive {}, {} errors in a row, transmit {}",
+                        i,
+                        if channel.rx_healthy.get() {
+                            "ok"
+                        } else {
+                            "down"
+                        },
+                        channel.rx_failures.get(),
+                        if channel.tx_stalled.get() {
+                            "stalled"
+                        } else {
+                            "ok"
+                        }
+                    );
+                }
+            }
+        } else if clean_str.starts_with("args") {
+            self.args(clean_str);
+        } else if clean_str.starts_with("txqueue") {
+            match clean_str.split_whitespace().nth(1) {
+                None => output!(
+                    self,
+                    "Output queue: {}/{} bytes, peak {}, {} dropped",
+                    self.output_queue.map_or(0, |queue| queue.len()),
+                    self.output_capacity,
+                    self.queue_peak.get(),
+                    self.queue_dropped.get()
+                ),
+                Some("clear") => {
+                    self.queue_peak.set(0);
+                    self.queue_dropped.set(0);
+                    output!(self, "Output queue peak and dropped count cleared");
+                }
+                Some(_) => fail!(self, "Usage: txqueue [clear]"),
+            }
+        } else if clean_str.starts_with("uartstat") {
+            self.uart_stat();
+        } else if clean_str.starts_with("bod") {
+            self.brown_out(clean_str);
+        } else if clean_str.starts_with("resetreason") {
+            self.reset_reason(clean_str);
+        } else if clean_str.starts_with("prio") {
+            let mut args = clean_str.split_whitespace().skip(1);
+            match (args.next(), args.next()) {
+                (Some(target), _) => {
+                    let found = Cell::new(false);
+                    self.kernel
+                        .process_each_capability(&self.capability, |i, proc| {
+                            if process_matches(i, proc.get_process_name(), target) {
+                                found.set(true);
+                                output!(
+                                    self,
+                                    "{}: Not applicable, processes are scheduled round-robin",
+                                    proc.get_process_name()
+                                );
+                            }
+                        });
+                    if !found.get() {
+                        fail!(self, "No process {}", target);
+                    }
+                }
+                (None, _) => fail!(self, "Usage: prio <process> [value]"),
+            }
+        } else if clean_str.starts_with("runq") {
+            self.run_queue();
+        } else if clean_str.starts_with("run") {
+            self.run(clean_str);
+        } else if clean_str.starts_with("watch") {
+            self.watch(clean_str);
+        } else if clean_str.starts_with("flashdump") {
+            self.flashdump(clean_str);
+        } else if clean_str.starts_with("dmesg") {
+            match clean_str.split_whitespace().nth(1) {
+                None => {
+                    let (oldest, next) = debug::debug_log_range();
+                    if oldest == next {
+                        output!(self, "The debug log is empty");
+                    } else {
+                        self.stream.set(Stream::Dmesg {
+                            position: oldest,
+                            end: next,
+                        });
+                        self.stream_next();
+                    }
+                }
+                Some("clear") => {
+                    debug::debug_log_clear();
+                    output!(self, "Debug log cleared");
+                }
+                Some(_) => fail!(self, "Usage: dmesg [clear]"),
+            }
+        } else if clean_str.starts_with("strace") {
+            self.strace(clean_str);
+        } else if clean_str.starts_with("defcalls") {
+            self.defcalls(clean_str);
+        } else if clean_str.starts_with("adc") {
+            self.adc(clean_str);
+        } else if clean_str.starts_with("temp") {
+            self.temp(clean_str);
+        } else if clean_str.starts_with("date") {
+            self.date(clean_str);
+        } else if clean_str.starts_with("clock") {
+            self.clock(clean_str);
+        } else if clean_str.starts_with("logflush") {
+            self.log_storage(false);
+        } else if clean_str.starts_with("logrotate") {
+            self.log_storage(true);
+        } else if clean_str.starts_with("perf") {
+            self.perf(clean_str);
+        } else if clean_str.starts_with("probe") {
+            self.probe(clean_str);
+        } else if clean_str.starts_with("irqtest") {
+            self.irqtest(clean_str);
+        } else if clean_str.starts_with("exit") || clean_str.starts_with("quit") {
+            output!(self, "Goodbye");
+            self.script.set(&[]);
+            self.input_mode.set(InputMode::Dormant);
+        } else if clean_str.starts_with("features") {
+            for &(name, enabled) in FEATURES {
+                output!(self, "[{}] {}", if enabled { "x" } else { " " }, name);
+            }
+        } else if clean_str.starts_with("describe") {
+            self.describe();
+        } else if cfg!(feature = "debug_commands") && clean_str.starts_with("_state") {
+            // Hidden from `help`: prints the console's own state, for when
+            // the console itself seems wedged.
+            self.print_state();
+        } else if cfg!(feature = "debug_commands") && clean_str.starts_with("mpu") {
+            self.mpu(clean_str);
+        } else if cfg!(feature = "debug_commands") && clean_str.starts_with("memdump") {
+            self.memdump(clean_str);
+        } else if cfg!(feature = "debug_commands") && clean_str.starts_with("send") {
+            self.send(clean_str);
+        } else if cfg!(feature = "debug_commands") && clean_str.starts_with("injecterror") {
+            self.inject_error(clean_str);
+        } else if cfg!(feature = "debug_commands") && clean_str.starts_with("memlimit") {
+            self.mem_limit(clean_str);
+        } else if cfg!(feature = "debug_commands") && clean_str.starts_with("sched") {
+            self.sched(clean_str);
+        } else if cfg!(feature = "debug_commands") && clean_str.starts_with("erase") {
+            self.erase(clean_str);
+        } else if cfg!(feature = "debug_commands") && clean_str.starts_with("reg") {
+            self.reg(clean_str);
+        } else if clean_str.is_empty() {
+            self.print_valid_commands();
+        } else if !self
+            .unknown_command_handler
+            .map_or(false, |handler| handler.unknown_command(clean_str))
+        {
+            self.unknown_command(clean_str);
+        }
+    }
+
+    // Called once a command and all of its streamed output have finished.
+    fn command_complete(&self) {
+        self.finish_output();
+        if self.next_segment.get() == 0 {
+            self.finish_result();
+        }
+        match self.output_app.take() {
+            Some(appid) => {
+                let _ = self.apps.enter(appid, |app, _| {
+                    let written = app.output_len;
+                    app.callback.map(|mut cb| cb.schedule(written, 0, 0));
+                });
+            }
+            None => {
+                if self.next_segment.get() != 0 {
+                    // Run the rest of the command line.
+                    self.read_command();
+                    return;
+                }
+                if self.script_running.get() {
+                    // `run_script` carries on with the next line.
+                    return;
+                }
+                if !self.script.get().is_empty() {
+                    self.run_script();
+                    return;
+                }
+                if self.chain_running.get() {
+                    // `run_chain` carries on with the next step.
+                    return;
+                }
+                if self.chain.get().is_some() && self.input_mode.get() == InputMode::Busy {
+                    self.run_chain();
+                    return;
+                }
+                match self.input_mode.get() {
+                    // Waiting for input before carrying on.
+                    InputMode::Dormant | InputMode::Confirm(_) => {}
+                    InputMode::Command | InputMode::Busy => {
+                        self.input_mode.set(InputMode::Command);
+                        let dropped = self.input_dropped.get();
+                        if dropped > 0 {
+                            self.input_dropped.set(0);
+                            output!(self, "Input queue full, {} bytes dropped", dropped);
+                        }
+                        self.menu_prefill.take().map(|name| self.prefill(name));
+                        if self.debug_held.get() {
+                            self.debug_release.set(true);
+                        }
+                        self.redraw();
+                        self.release_debug_output();
+                        self.replay_input();
+                        self.release_held_input();
+                    }
+                }
+            }
+        }
+    }
+
+    // With `set coalesce on`, hold debug output back while a command runs,
+    // so it does not land in the middle of the command's output. The debug
+    // writer lets it go once the command's output has been transmitted, or
+    // once it fills half of its buffer.
+    fn hold_debug_output(&self) {
+        if self.coalesce.get() && !self.debug_held.get() {
+            self.debug_held.set(true);
+            self.debug_release.set(false);
+            debug::set_debug_output_held(true);
+        }
+    }
+
+    // Let held debug output go once everything the console queued before it
+    // has been transmitted.
+    fn release_debug_output(&self) {
+        if !self.debug_release.get() || self.tx_in_progress.get() {
+            return;
+        }
+        if self
+            .output_queue
+            .map_or(false, |queue| queue.has_elements())
+        {
+            return;
+        }
+        self.debug_release.set(false);
+        self.debug_held.set(false);
+        debug::set_debug_output_held(false);
+    }
+
+    // Handle input queued while the last command ran as if it were typed now,
+    // until it runs a command that does not complete straight away. That
+    // command's completion carries on from there.
+    fn replay_input(&self) {
+        if self.replaying.get() {
+            // A command run below completed; the loop carries on.
+            return;
+        }
+        self.replaying.set(true);
+        while self.input_mode.get() == InputMode::Command {
+            match self.input_queue.map_or(None, |queue| queue.dequeue()) {
+                Some(byte) => {
+                    if self.command_byte(byte) {
+                        self.read_command();
+                    }
+                }
+                None => break,
+            }
+        }
+        self.replaying.set(false);
+    }
+
+    // Whether the rest of a received chunk must wait for the command that a
+    // line terminator in it started. With an input queue the bytes wait
+    // there instead, and any key ends `watch`.
+    fn holding_input(&self) -> bool {
+        match (self.input_mode.get(), self.stream.get()) {
+            (InputMode::Busy, Stream::Watch { .. })
+            | (InputMode::Busy, Stream::Temperature { watch: true })
+            | (InputMode::Busy, Stream::TemperatureWatch) => false,
+            (InputMode::Busy, _) => self.input_queue.is_none(),
+            _ => false,
+        }
+    }
+
+    // Handle the bytes of a received chunk held back by `receive_complete`
+    // as if they had just arrived, then receive again.
+    fn release_held_input(&self) {
+        for (index, channel) in self.channels.iter().enumerate() {
+            let (start, end) = channel.rx_held.get();
+            if start == end {
+                continue;
+            }
+            channel.rx_held.set((0, 0));
+            self.active_channel.set(index);
+            channel.rx_buffer.map(|buffer| {
+                for position in start..end {
+                    if self.holding_input() {
+                        channel.rx_held.set((position, end));
+                        return;
+                    }
+                    self.receive_byte(buffer[position]);
+                }
+            });
+            channel.receive();
+        }
+    }
+
+    // With `set typeahead on`, start the next command with the key that
+    // ended a watch, so a command typed while watching is not lost, or queue
+    // it if the console is still busy, ringing the bell if it does not fit.
+    // Enter, Escape and `q` only end the watch.
+    fn type_ahead(&self, byte: u8) {
+        if self.input_mode.get() == InputMode::Command {
+            if self.command_byte(byte) {
+                self.read_command();
+            }
+        } else if !self.input_queue.map_or(false, |queue| queue.enqueue(byte)) {
+            self.echo(&[0x07]);
+        }
+    }
+
+    // Keep input that arrived while a command is running for `replay_input`.
+    fn queue_input(&self, byte: u8) {
+        let queued = self.input_queue.map_or(false, |queue| queue.enqueue(byte));
+        if !queued && self.input_queue.is_some() {
+            self.input_dropped.set(self.input_dropped.get() + 1);
+        }
+    }
+
+    // Handle one received byte according to the input mode. A command it
+    // completes runs straight away, so that the bytes after it are handled
+    // in the mode the command leaves the console in.
+    fn receive_byte(&self, byte: u8) {
+        if self.settling.get() && !self.line_settled() {
+            self.noise.set(self.noise.get() + 1);
+            return;
+        }
+        if self.detect_until.take().is_some() {
+            // Input this soon after starting comes from a host tool.
+            self.raw.set(true);
+        }
+        match self.input_mode.get() {
+            InputMode::Command => {
+                if self.command_byte(byte) {
+                    self.read_command();
+                }
+            }
+            InputMode::Busy => match self.stream.get() {
+                // Any key ends `watch` and `temp watch`.
+                Stream::Watch { .. }
+                | Stream::Temperature { watch: true }
+                | Stream::TemperatureWatch => {
+                    self.end_watch();
+                    let ends = byte == b'\r' || byte == b'\n' || byte == 0x1b || byte == b'q';
+                    if self.typeahead.get() && !ends {
+                        self.type_ahead(byte);
+                    }
+                }
+                _ => self.queue_input(byte),
+            },
+            // Whatever woke the console is not part of a command.
+            InputMode::Dormant => self.wake(),
+            InputMode::Confirm(action) => self.confirmed(action, byte),
+        }
+    }
+
+    // Whether the UART was quiet for the settle time before the byte just
+    // received. If not, the wait starts over from this byte. Once it was,
+    // input is no longer checked and the console reports the noise it
+    // discarded.
+    fn line_settled(&self) -> bool {
+        let (now, settle) = self.alarm.map_or((0, 0), |alarm| {
+            let ticks = self.settle_ms.get() as u64 * alarm.frequency() as u64 / 1000;
+            (alarm.now(), cmp::min(ticks, u32::max_value() as u64) as u32)
+        });
+        if now.wrapping_sub(self.quiet_since.get()) < settle {
+            self.quiet_since.set(now);
+            return false;
+        }
+        self.settling.set(false);
+        let noise = self.noise.get();
+        if noise > 0 {
+            self.enqueue(b"\r\n");
+            output!(self, "Discarded {} bytes of line noise", noise);
+            self.redraw();
+        }
+        true
+    }
+
+    // Start a new session after `exit`.
+    fn wake(&self) {
+        self.input_mode.set(InputMode::Command);
+        if !self.quiet.get() {
+            output!(self, "{}", BANNER);
+        }
+        self.redraw();
+    }
+
+    // Print one line of command output, either to the UART or into the output
+    // buffer of the app that issued the command.
+    // A line that does not fit is dropped whole, and room is always left for
+    // the truncation marker.
+    fn output_line(&self, args: fmt::Arguments) {
+        let (filter, filter_len) = self.filter.get();
+        let mut matcher = Contains::new(&filter[..filter_len]);
+        let _ = matcher.write_fmt(args);
+        if !matcher.found {
+            return;
+        }
+        let mut counter = LenCounter { len: 0 };
+        let _ = counter.write_fmt(args);
+        let width = self.width.get();
+        match self.output_app.map(|appid| *appid) {
+            Some(appid) => self.write_app_output(appid, |writer| {
+                if counter.len + 1 + TRUNCATED.len() + 1 > writer.remaining() {
+                    self.truncated.set(true);
+                    return;
+                }
+                let _ = writer.write_fmt(args);
+                let _ = writer.write_str("\n");
+            }),
+            None => {
+                // Besides the marker, leave room for the prompt and the
+                // command being typed.
+                let reserve = TRUNCATED.len() + 2 + PROMPT.len() + self.command_capacity;
+                if width > 0 {
+                    let mut wrapped = WrapWriter::new(LenCounter { len: 0 }, width);
+                    let _ = wrapped.write_fmt(args);
+                    counter = wrapped.finish();
+                }
+                self.output_queue.map(|queue| {
+                    if queue.len() + counter.len + 2 + reserve >= self.output_capacity {
+                        self.truncated.set(true);
+                        self.queue_dropped
+                            .set(self.queue_dropped.get() + counter.len + 2);
+                        return;
+                    }
+                    let mut writer = QueueWriter { queue: queue };
+                    if width > 0 {
+                        let mut wrapped = WrapWriter::new(writer, width);
+                        let _ = wrapped.write_fmt(args);
+                        writer = wrapped.finish();
+                    } else {
+                        let _ = writer.write_fmt(args);
+                    }
+                    let _ = writer.write_str("\r\n");
+                    self.queue_peak
+                        .set(cmp::max(self.queue_peak.get(), writer.queue.len()));
+                });
+                self.flush();
+            }
+        }
+    }
+
+    // Format into the output buffer of the app `appid`, after what the
+    // command has written into it so far.
+    fn write_app_output<F: FnOnce(&mut BufWriter)>(&self, appid: AppId, write: F) {
+        let _ = self.apps.enter(appid, |app, _| {
+            let start = app.output_len;
+            let end = app.output.as_mut().map(|slice| {
+                let buf = slice.as_mut();
+                let start = cmp::min(start, buf.len());
+                let mut writer = BufWriter::new(&mut buf[start..]);
+                write(&mut writer);
+                start + writer.len()
+            });
+            end.map(|end| app.output_len = end);
+        });
+    }
+
+    // Run a command and report how long it took. Commands that are still
+    // printing when `execute` returns are reported on by `command_complete`.
+    fn time(&self, command: &str) {
+        if command.is_empty() {
+            fail!(self, "Usage: time <command>");
+            return;
+        }
+        let now = match self.alarm.map(|alarm| alarm.now()) {
+            Some(now) => now,
+            None => {
+                fail!(self, "time: no alarm registered");
+                return;
+            }
+        };
+        self.timing.set(Some(now));
+        self.execute(command);
+        let waiting = match self.input_mode.get() {
+            InputMode::Confirm(_) => true,
+            _ => self.stream.get() != Stream::Idle,
+        };
+        if !waiting {
+            self.finish_output();
+        }
+    }
+
+    // Finish the output of a command: mark it if it was cut short, and say
+    // how long it took if it was timed.
+    fn finish_output(&self) {
+        self.filter.set(([0; FILTER_LEN], 0));
+        if self.truncated.get() {
+            self.truncated.set(false);
+            self.print_truncation_marker();
+        }
+        if let Some(start) = self.timing.get() {
+            self.timing.set(None);
+            self.alarm.map(|alarm| {
+                let ticks = alarm.now().wrapping_sub(start) as u64;
+                let frequency = cmp::max(alarm.frequency(), 1) as u64;
+                output!(self, "Elapsed: {}ms", ticks * 1000 / frequency);
+            });
+        }
+    }
+
+    // Start keeping track of the result of a command line.
+    fn start_result(&self) {
+        self.result_pending.set(true);
+        self.result_failed.set(false);
+        self.result_code.set(None);
+    }
+
+    // End the output of a command line with its result, if it owes one and
+    // `set resultline` is on: `#RESULT ok`, or `#RESULT error` followed by
+    // the error code, if a command on the line reported one with
+    // `fail_code!`. A line a break cut short fails with `ECANCEL`. The
+    // result comes after any output streamed once the command returned.
+    fn finish_result(&self) {
+        if !self.result_pending.get() {
+            return;
+        }
+        self.result_pending.set(false);
+        if !self.result_line.get() {
+            return;
+        }
+        match (self.result_failed.get(), self.result_code.get()) {
+            (false, _) => output!(self, "#RESULT ok"),
+            (true, Some(code)) => output!(self, "#RESULT error {:?}", code),
+            (true, None) => output!(self, "#RESULT error"),
+        }
+    }
+
+    // End the current command's output with a line saying it was cut short.
+    fn print_truncation_marker(&self) {
+        match self.output_app.map(|appid| *appid) {
+            Some(appid) => self.write_app_output(appid, |writer| {
+                let _ = writer.write_str(TRUNCATED);
+                let _ = writer.write_str("\n");
+            }),
+            None => {
+                self.enqueue(TRUNCATED.as_bytes());
+                self.enqueue(b"\r\n");
+                self.flush();
+            }
+        }
+    }
+
+    fn menu(&self) {
+        self.stream.set(Stream::Menu { number: 1 });
+        self.stream_next();
+    }
+
+    // Run the command numbered `selection` in the menu.
+    fn menu_select(&self, selection: &str) {
+        if selection.is_empty() {
+            return;
+        }
+        let names = CommandNames { prefix: "" };
+        let name = match parse_int(selection.as_bytes()) {
+            Ok(number) if number > 0 => names.names().nth(number - 1),
+            _ => None,
+        };
+        match name {
+            Some(name) if PROCESS_COMMANDS.contains(&name) => {
+                output!(self, "Type the process for {} and press Enter", name);
+                self.menu_prefill.set(Some(name));
+            }
+            Some(name) => self.execute(name),
+            None => {
+                output!(self, "Invalid selection '{}'", selection);
+                self.menu();
+            }
+        }
+    }
+
+    // Put `name` followed by a space on the command line, as if typed.
+    fn prefill(&self, name: &str) {
+        self.command_buffer.map(|command| {
+            if name.len() + 1 < command.len() {
+                command[..name.len()].copy_from_slice(name.as_bytes());
+                command[name.len()] = b' ';
+                command[name.len() + 1] = 0;
+                self.command_index.set(name.len() + 1);
+            }
+        });
+    }
+
+    fn print_valid_commands(&self) {
+        output!(self, "Valid commands are:{}", CommandNames { prefix: "" });
+    }
+
+    // Report an unrecognized command, suggesting the commands it is a prefix
+    // of in case it was mistyped or cut short.
+    fn unknown_command(&self, command: &str) {
+        self.parse_failed.set(true);
+        let verb = command.split_whitespace().next().unwrap_or("");
+        let candidates = CommandNames { prefix: verb };
+        match candidates.names().count() {
+            0 => {
+                fail!(self, "Unknown command '{}'.", verb);
+                self.print_valid_commands();
+            }
+            1 => fail!(
+                self,
+                "Unknown command '{}'. Did you mean '{}'?",
+                verb,
+                candidates.names().next().unwrap_or("")
+            ),
+            _ => fail!(
+                self,
+                "Unknown command '{}'. Did you mean one of:{}?",
+                verb,
+                candidates
+            ),
+        }
+    }
+
+    fn fault_policy(&self, command: &str) {
+        let mut args = command.split_whitespace().skip(1);
+        let target = match args.next() {
+            Some(target) => target,
+            None => {
+                fail!(self, "Usage: faultpolicy <process> [panic|stop|restart]");
+                return;
+            }
+        };
+        let policy = match args.next() {
+            None => None,
+            Some("panic") => Some(FaultResponse::Panic),
+            Some("stop") => Some(FaultResponse::Stop),
+            Some("restart") => Some(FaultResponse::Restart),
+            Some(other) => {
+                fail!(
+                    self,
+                    "Unknown fault policy '{}': expected panic, stop or restart",
+                    other
+                );
+                return;
+            }
+        };
+        let found = Cell::new(false);
+        self.kernel
+            .process_each_capability(&self.capability, |i, proc| {
+                if process_matches(i, proc.get_process_name(), target) {
+                    found.set(true);
+                    match policy {
+                        Some(policy) => {
+                            proc.set_fault_response(policy);
+                            output!(
+                                self,
+                                "Process {} fault policy set to {:?}",
+                                proc.get_process_name(),
+                                policy
+                            );
+                        }
+                        None => output!(
+                            self,
+                            "Process {} fault policy: {:?}",
+                            proc.get_process_name(),
+                            proc.get_fault_response()
+                        ),
+                    }
+                }
+            });
+        if !found.get() {
+            fail!(self, "No process {}", target);
+        }
+    }
+
+    // List the processes as a table, or with one field per line on terminals
+    // too narrow for the table.
+    fn list(&self) {
+        let width = self.width.get();
+        let table = width == 0 || width >= LIST_TABLE_WIDTH;
+        if table {
+            output!(
+                self,
+                "{}",
+                self.colorize(
+                    " PID    Name                Quanta  Syscalls  Dropped Callbacks    State",
+                    Style::Bold
+                )
+            );
+        }
+        self.kernel
+            .process_each_capability(&self.capability, |i, proc| {
+                let pname = proc.get_process_name();
+                if table {
+                    output!(
+                        self,
+                        "  {:02}\t{:<20}{:6}{:10}{:19}  {}",
+                        i,
+                        Truncated {
+                            s: pname,
+                            max: LIST_NAME_WIDTH,
+                        },
+                        proc.debug_timeslice_expiration_count(),
+                        proc.debug_syscall_count(),
+                        proc.debug_dropped_callback_count(),
+                        self.colorize(
+                            format_args!("{:?}", proc.get_state()),
+                            state_style(proc.get_state())
+                        )
+                    );
+                } else {
+                    output!(
+                        self,
+                        "{:02} {}",
+                        i,
+                        Truncated {
+                            s: pname,
+                            max: width - 3,
+                        }
+                    );
+                    output!(
+                        self,
+                        "  Quanta: {}",
+                        proc.debug_timeslice_expiration_count()
+                    );
+                    output!(self, "  Syscalls: {}", proc.debug_syscall_count());
+                    output!(self, "  Dropped: {}", proc.debug_dropped_callback_count());
+                    output!(
+                        self,
+                        "  State: {}",
+                        self.colorize(
+                            format_args!("{:?}", proc.get_state()),
+                            state_style(proc.get_state())
+                        )
+                    );
+                }
+            });
+    }
+
+    // Give `value` the style `style` if colors are on. Output to apps is never
+    // styled.
+    fn colorize<T: fmt::Display>(&self, value: T, style: Style) -> Styled<T> {
+        Styled {
+            value: value,
+            style: style,
+            enabled: self.color.get() && self.output_app.is_none(),
+        }
+    }
+
+    fn set(&self, command: &str) {
+        let mut args = command.split_whitespace().skip(1);
+        match (args.next(), args.next()) {
+            (None, _) => {
+                output!(self, "width {}", self.width.get());
+                match self.separator.get() {
+                    0 => output!(self, "separator none"),
+                    separator => output!(self, "separator {}", separator as char),
+                }
+                output!(self, "rxchunk {}", self.channels[0].rx_chunk.get());
+                output!(self, "color {}", if self.color.get() { "on" } else { "off" });
+                output!(self, "raw {}", if self.raw.get() { "on" } else { "off" });
+                output!(
+                    self,
+                    "echo {}",
+                    if self.echo_input.get() { "on" } else { "off" }
+                );
+                match self.mask.get() {
+                    0 => output!(self, "maskchar none"),
+                    mask => output!(self, "maskchar {}", mask as char),
+                }
+                match self.max_rate.get() {
+                    0 => output!(self, "maxrate unlimited"),
+                    rate => output!(self, "maxrate {}", rate),
+                }
+                output!(
+                    self,
+                    "emptyline {}",
+                    if self.empty_line_ignored.get() {
+                        "ignore"
+                    } else {
+                        "newline"
+                    }
+                );
+                output!(self, "confirmtimeout {}", self.confirm_timeout_ms.get());
+                output!(
+                    self,
+                    "statusline {}",
+                    if self.status_line.get() { "on" } else { "off" }
+                );
+                output!(
+                    self,
+                    "debugparse {}",
+                    if self.debug_parse.get() { "on" } else { "off" }
+                );
+                output!(
+                    self,
+                    "coalesce {}",
+                    if self.coalesce.get() { "on" } else { "off" }
+                );
+                output!(
+                    self,
+                    "keymode {}",
+                    if self.single_key.get() {
+                        "single"
+                    } else {
+                        "line"
+                    }
+                );
+                output!(
+                    self,
+                    "typeahead {}",
+                    if self.typeahead.get() { "on" } else { "off" }
+                );
+                output!(
+                    self,
+                    "resultline {}",
+                    if self.result_line.get() { "on" } else { "off" }
+                );
+                match self.prefix.get() {
+                    0 => output!(self, "prefix none"),
+                    prefix => output!(self, "prefix {}", prefix as char),
+                }
+            }
+            (Some("prefix"), Some("none")) => {
+                self.prefix.set(0);
+                output!(self, "prefix none");
+            }
+            (Some("prefix"), Some(value)) => match value.as_bytes() {
+                &[prefix]
+                    if prefix.is_ascii_punctuation()
+                        && prefix != self.separator.get()
+                        && prefix != b'|'
+                        && prefix != b'\\' =>
+                {
+                    self.prefix.set(prefix);
+                    output!(self, "prefix {}", prefix as char);
+                }
+                _ => fail!(
+                    self,
+                    "set: prefix must be a punctuation character other than the separator, or none"
+                ),
+            },
+            (Some("confirmtimeout"), Some(value)) => match parse_int(value.as_bytes()) {
+                Ok(ms) if ms <= u32::max_value() as usize => {
+                    self.confirm_timeout_ms.set(ms as u32);
+                    output!(self, "confirmtimeout {}", ms);
+                }
+                Ok(_) => fail!(self, "set: confirmtimeout is too long"),
+                Err(err) => fail!(self, "set: confirmtimeout {}", err),
+            },
+            (Some("maxrate"), Some(value)) => match parse_int(value.as_bytes()) {
+                Ok(0) => {
+                    self.max_rate.set(0);
+                    output!(self, "maxrate unlimited");
+                    // Anything held back can go straight away.
+                    self.tx_resume.set(None);
+                }
+                Ok(rate) => {
+                    if self.alarm.is_none() {
+                        fail!(self, "set: maxrate needs an alarm");
+                    } else {
+                        self.max_rate.set(rate);
+                        output!(self, "maxrate {}", rate);
+                    }
+                }
+                Err(err) => fail!(self, "set: maxrate {}", err),
+            },
+            (Some("echo"), Some("on")) => {
+                self.echo_input.set(true);
+                output!(self, "echo on");
+            }
+            (Some("echo"), Some("off")) => {
+                self.echo_input.set(false);
+                output!(self, "echo off");
+            }
+            (Some("emptyline"), Some("newline")) => {
+                self.empty_line_ignored.set(false);
+                output!(self, "emptyline newline");
+            }
+            (Some("emptyline"), Some("ignore")) => {
+                self.empty_line_ignored.set(true);
+                output!(self, "emptyline ignore");
+            }
+            (Some("statusline"), Some("on")) => {
+                self.status_line.set(true);
+                output!(self, "statusline on");
+            }
+            (Some("statusline"), Some("off")) => {
+                self.status_line.set(false);
+                output!(self, "statusline off");
+            }
+            (Some("debugparse"), Some("on")) => {
+                self.debug_parse.set(true);
+                output!(self, "debugparse on");
+            }
+            (Some("debugparse"), Some("off")) => {
+                self.debug_parse.set(false);
+                output!(self, "debugparse off");
+            }
+            (Some("coalesce"), Some("on")) => {
+                self.coalesce.set(true);
+                output!(self, "coalesce on");
+            }
+            (Some("coalesce"), Some("off")) => {
+                self.coalesce.set(false);
+                output!(self, "coalesce off");
+                // Once this command's output is out.
+                self.debug_release.set(self.debug_held.get());
+            }
+            (Some("keymode"), Some("single")) => {
+                self.single_key.set(true);
+                output!(self, "keymode single");
+            }
+            (Some("keymode"), Some("line")) => {
+                self.single_key.set(false);
+                output!(self, "keymode line");
+            }
+            (Some("typeahead"), Some("on")) => {
+                self.typeahead.set(true);
+                output!(self, "typeahead on");
+            }
+            (Some("typeahead"), Some("off")) => {
+                self.typeahead.set(false);
+                output!(self, "typeahead off");
+            }
+            (Some("resultline"), Some("on")) => {
+                self.result_line.set(true);
+                output!(self, "resultline on");
+            }
+            (Some("resultline"), Some("off")) => {
+                self.result_line.set(false);
+                output!(self, "resultline off");
+            }
+            (Some("maskchar"), Some("none")) => {
+                self.mask.set(0);
+                output!(self, "maskchar none");
+            }
+            (Some("maskchar"), Some(value)) => match value.as_bytes() {
+                &[mask] if mask.is_ascii_graphic() => {
+                    self.mask.set(mask);
+                    output!(self, "maskchar {}", mask as char);
+                }
+                _ => fail!(self, "set: maskchar must be a printable character or none"),
+            },
+            (Some("raw"), Some("on")) => {
+                self.raw.set(true);
+                output!(self, "raw on");
+            }
+            (Some("raw"), Some("off")) => {
+                self.raw.set(false);
+                output!(self, "raw off");
+            }
+            (Some("color"), Some("on")) => {
+                self.color.set(true);
+                output!(self, "color {}", self.colorize("on", Style::Green));
+            }
+            (Some("color"), Some("off")) => {
+                self.color.set(false);
+                output!(self, "color off");
+            }
+            (Some("rxchunk"), Some(value)) => {
+                // Every UART's receive buffer must hold a whole chunk.
+                let max = self
+                    .channels
+                    .iter()
+                    .filter(|channel| channel.uart.is_some())
+                    .map(|channel| channel.rx_capacity.get())
+                    .min()
+                    .unwrap_or(0);
+                match parse_int(value.as_bytes()) {
+                    Ok(chunk) if chunk >= 1 && chunk <= max => {
+                        // Receives already waiting keep their size; the new
+                        // one applies from the next receive.
+                        for channel in self.channels.iter() {
+                            channel.rx_chunk.set(chunk);
+                        }
+                        output!(self, "rxchunk {}", chunk);
+                    }
+                    _ => fail!(self, "set: rxchunk must be a number from 1 to {}", max),
+                }
+            }
+            (Some("separator"), Some("none")) => {
+                self.separator.set(0);
+                output!(self, "separator none");
+            }
+            (Some("separator"), Some(value)) => match value.as_bytes() {
+                // `|` starts a filter and `\` continues a line.
+                &[separator]
+                    if separator.is_ascii_punctuation()
+                        && separator != self.prefix.get()
+                        && separator != b'|'
+                        && separator != b'\\' =>
+                {
+                    self.separator.set(separator);
+                    output!(self, "separator {}", separator as char);
+                }
+                _ => fail!(
+                    self,
+                    "set: separator must be a punctuation character or none"
+                ),
+            },
+            (Some("width"), Some(value)) => match parse_int(value.as_bytes()) {
+                Ok(width) if width == 0 || width >= MIN_WIDTH => {
+                    self.width.set(width);
+                    output!(self, "width {}", width);
+                }
+                _ => fail!(
+                    self,
+                    "set: width must be 0 or a number of at least {}",
+                    MIN_WIDTH
+                ),
+            },
+            _ => fail!(
+                self,
+                "Usage: set [width <columns> | separator <character> | prefix <character> | rxchunk <bytes> | color on|off | raw on|off | echo on|off | maskchar <character> | emptyline newline|ignore | maxrate <bytes per second> | confirmtimeout <ms> | statusline on|off | debugparse on|off | coalesce on|off | keymode single|line | typeahead on|off | resultline on|off]"
+            ),
+        }
+    }
+
+    // Print the most recent output of a process, a line at a time.
+    fn tail(&self, command: &str) {
+        let target = match command.split_whitespace().nth(1) {
+            Some(target) => target,
+            None => {
+                fail!(self, "Usage: tail <process>");
+                return;
+            }
+        };
+        let found = Cell::new(false);
+        self.kernel
+            .process_each_capability(&self.capability, |i, proc| {
+                if !process_matches(i, proc.get_process_name(), target) {
+                    return;
+                }
+                found.set(true);
+                let mut buffer = [0; TAIL_LEN];
+                let len = self
+                    .output_capture
+                    .map_or(None, |capture| {
+                        capture.recent_output(proc.appid(), &mut buffer)
+                    })
+                    .unwrap_or(0);
+                if len == 0 {
+                    output!(self, "No captured output");
+                    return;
+                }
+                // A final newline does not start another line.
+                let text = match buffer[len - 1] {
+                    b'\n' => &buffer[..len - 1],
+                    _ => &buffer[..len],
+                };
+                for line in text.split(|byte| *byte == b'\n') {
+                    let line = match line.last() {
+                        Some(&b'\r') => &line[..line.len() - 1],
+                        _ => line,
+                    };
+                    output!(self, "{}", Escaped(line));
+                }
+            });
+        if !found.get() {
+            fail!(self, "No process {}", target);
+        }
+    }
+
+    // List the armed alarms by how long until they fire. Alarms that are
+    // already due but have not been handled yet show a negative time.
+    fn timers(&self) {
+        match self.alarm_list.map(|alarms| *alarms) {
+            None => fail!(self, "timers: no alarm list registered"),
+            Some(alarms) => {
+                let now = alarms.now();
+                let frequency = cmp::max(alarms.frequency(), 1) as i64;
+                let mut count = 0;
+                alarms.each_armed(&mut |when, owner| {
+                    count += 1;
+                    let ticks = when.wrapping_sub(now) as i32 as i64;
+                    output!(self, "{:+}ms  {:#010x}", ticks * 1000 / frequency, owner);
+                });
+                if count == 0 {
+                    output!(self, "No armed alarms");
+                }
+            }
+        }
+    }
+
+    fn idle(&self, command: &str) {
+        let info = KernelInfo::new(self.kernel);
+        match command.split_whitespace().nth(1) {
+            None => match info.idle_time(&self.capability) {
+                None => fail!(self, "idle: the board does not measure idle time"),
+                Some((_, 0)) => output!(self, "Idle: 0.0%"),
+                Some((idle, total)) => {
+                    let permille = idle as u64 * 1000 / total as u64;
+                    output!(self, "Idle: {}.{}%", permille / 10, permille % 10);
+                }
+            },
+            Some("clear") => {
+                info.clear_idle_time(&self.capability);
+                output!(self, "Idle time cleared");
+            }
+            Some(_) => fail!(self, "Usage: idle [clear]"),
+        }
+    }
+
+    fn gpio(&self, command: &str) {
+        let pins = self.gpio_pins.get();
+        let mut args = command.split_whitespace().skip(1);
+        let (index, operation) = match (args.next(), args.next()) {
+            (None, _) => {
+                if pins.is_empty() {
+                    output!(self, "No GPIO pins registered");
+                } else {
+                    output!(self, "{} GPIO pins, 0 to {}", pins.len(), pins.len() - 1);
+                }
+                return;
+            }
+            (Some(index), Some(operation)) => (index, operation),
+            (Some(_), None) => {
+                fail!(self, "Usage: gpio <pin> read|high|low|toggle");
+                return;
+            }
+        };
+        let pin = match parse_int(index.as_bytes()) {
+            Ok(index) if index < pins.len() => pins[index],
+            Ok(_) => {
+                output!(self, "gpio: no pin {}, there are {}", index, pins.len());
+                return;
+            }
+            Err(err) => {
+                output!(self, "gpio: pin {} {}", index, err);
+                return;
+            }
+        };
+        match operation {
+            "read" => {}
+            "high" => {
+                pin.make_output();
+                pin.set();
+            }
+            "low" => {
+                pin.make_output();
+                pin.clear();
+            }
+            "toggle" => {
+                pin.make_output();
+                pin.toggle();
+            }
+            _ => {
+                fail!(self, "Usage: gpio <pin> read|high|low|toggle");
+                return;
+            }
+        }
+        output!(
+            self,
+            "Pin {}: {}",
+            index,
+            if pin.read() { "high" } else { "low" }
+        );
+    }
+
+    // Add a command to the command log, if there is one.
+    fn log_command(&self, line: &str, source: CommandSource) {
+        let time = self.alarm.map(|alarm| {
+            (alarm.now() as u64 * 1000 / cmp::max(alarm.frequency(), 1) as u64) as u32
+        });
+        let count = self.command_log_count.get();
+        self.command_log.map(|log| {
+            if log.is_empty() {
+                return;
+            }
+            let entry = &mut log[count % log.len()];
+            let len = cmp::min(line.len(), CMDLOG_TEXT_LEN);
+            entry.time = time;
+            entry.source = source;
+            entry.text[..len].copy_from_slice(&line.as_bytes()[..len]);
+            entry.len = len;
+            self.command_log_count.set(count + 1);
+        });
+    }
+
+    fn cmdlog(&self, command: &str) {
+        let len = match self.command_log.map(|log| log.len()) {
+            Some(len) => len,
+            None => {
+                output!(self, "No command log on this board");
+                return;
+            }
+        };
+        match command.split_whitespace().nth(1) {
+            None => {
+                let end = self.command_log_count.get();
+                self.stream.set(Stream::CommandLog {
+                    position: end.saturating_sub(len),
+                    end: end,
+                });
+                self.stream_next();
+            }
+            Some("clear") => {
+                self.command_log_count.set(0);
+                output!(self, "Command log cleared");
+            }
+            Some(_) => fail!(self, "Usage: cmdlog [clear]"),
+        }
+    }
+
+    fn flash_info(&self) {
+        let layout = match self.flash_layout.map(|layout| *layout) {
+            Some(layout) => layout,
+            None => {
+                output!(self, "Flash layout is not available on this board");
+                return;
+            }
+        };
+        let (kernel_start, kernel_len) = layout.kernel;
+        let (apps_start, apps_len) = layout.apps;
+        // Apps are placed one after another from the start of their region,
+        // so new ones go after the last.
+        let apps_used = Cell::new(0);
+        let apps_end = Cell::new(apps_start);
+        let count = Cell::new(0);
+        self.kernel
+            .process_each_capability(&self.capability, |_i, proc| {
+                let (start, end) = (proc.flash_start() as usize, proc.flash_end() as usize);
+                apps_used.set(apps_used.get() + end - start);
+                apps_end.set(cmp::max(apps_end.get(), end));
+                count.set(count.get() + 1);
+            });
+        let apps_free = (apps_start + apps_len).saturating_sub(apps_end.get());
+        output!(
+            self,
+            "Kernel: {} used of {} at {:#010x}",
+            Kb(layout.kernel_used),
+            Kb(kernel_len),
+            kernel_start
+        );
+        output!(
+            self,
+            "Apps: {} used of {} at {:#010x} by {} processes, {} free for new apps",
+            Kb(apps_used.get()),
+            Kb(apps_len),
+            apps_start,
+            count.get(),
+            Kb(apps_free)
+        );
+        let total = kernel_len + apps_len;
+        let used = layout.kernel_used + apps_used.get();
+        output!(
+            self,
+            "Total: {} of flash, {} used, {} free",
+            Kb(total),
+            Kb(used),
+            Kb(total.saturating_sub(used))
+        );
+    }
+
+    fn dbggpio(&self, command: &str) {
+        let mut args = command.split_whitespace().skip(1);
+        match (args.next(), args.next()) {
+            (None, _) => {
+                for n in 0..debug::DEBUG_GPIO_COUNT {
+                    if !debug::debug_gpio_assigned(n) {
+                        output!(self, "Debug GPIO {}: no pin", n);
+                    } else if debug::debug_gpio_enabled(n) {
+                        output!(self, "Debug GPIO {}: on", n);
+                    } else {
+                        output!(self, "Debug GPIO {}: off", n);
+                    }
+                }
+            }
+            (Some(n), Some(state)) if state == "on" || state == "off" => {
+                match parse_int(n.as_bytes()) {
+                    Ok(n) if n < debug::DEBUG_GPIO_COUNT => {
+                        debug::set_debug_gpio_enabled(n, state == "on");
+                        output!(self, "Debug GPIO {}: {}", n, state);
+                    }
+                    _ => fail!(
+                        self,
+                        "dbggpio: there are {} debug GPIOs",
+                        debug::DEBUG_GPIO_COUNT
+                    ),
+                }
+            }
+            _ => fail!(self, "Usage: dbggpio [<n> on|off]"),
+        }
+    }
+
+    fn strace(&self, command: &str) {
+        let mut args = command.split_whitespace().skip(1);
+        let count = match args.next().map(|arg| parse_int(arg.as_bytes())) {
+            None => usize::max_value(),
+            Some(Ok(count)) if count > 0 => count,
+            Some(_) => {
+                fail!(self, "Usage: strace [count] [process]");
+                return;
+            }
+        };
+        let process = match args.next() {
+            None => None,
+            Some(target) => {
+                let found = Cell::new(None);
+                self.kernel
+                    .process_each_capability(&self.capability, |i, proc| {
+                        if found.get().is_none()
+                            && process_matches(i, proc.get_process_name(), target)
+                        {
+                            found.set(Some(i));
+                        }
+                    });
+                if found.get().is_none() {
+                    fail!(self, "No process {}", target);
+                    return;
+                }
+                found.get()
+            }
+        };
+        let info = KernelInfo::new(self.kernel);
+        let (oldest, next) = info.syscall_trace_range(&self.capability);
+        // Walk back from the newest call to where the listing starts.
+        let mut start = next;
+        let mut found = 0;
+        while start > oldest && found < count {
+            start -= 1;
+            let matches = info
+                .syscall_trace_entry(start, &self.capability)
+                .map_or(false, |record| {
+                    process.map_or(true, |p| record.process == p)
+                });
+            if matches {
+                found += 1;
+            }
+        }
+        if found == 0 {
+            output!(self, "No system calls recorded");
+            return;
+        }
+        self.stream.set(Stream::Strace {
+            position: start,
+            end: next,
+            process: process,
+        });
+        self.stream_next();
+    }
+
+    fn defcalls(&self, command: &str) {
+        match command.split_whitespace().nth(1) {
+            None => {
+                let pending = deferred_call::pending_tasks();
+                output!(self, "Pending: {:#010x}", pending);
+                let mut used = false;
+                for task in 0..32 {
+                    let (set, run) = deferred_call::task_counts(task);
+                    let task_pending = pending & (1 << task) != 0;
+                    if set == 0 && run == 0 && !task_pending {
+                        continue;
+                    }
+                    used = true;
+                    output!(
+                        self,
+                        "Task {:2}: set {}, run {}{}",
+                        task,
+                        set,
+                        run,
+                        if task_pending { ", pending" } else { "" }
+                    );
+                }
+                if !used {
+                    output!(self, "No deferred calls have been set");
+                }
+            }
+            Some("clear") => {
+                deferred_call::clear_counts();
+                output!(self, "Deferred call counts cleared");
+            }
+            Some(_) => fail!(self, "Usage: defcalls [clear]"),
+        }
+    }
+
+    fn quantum(&self, command: &str) {
+        match command.split_whitespace().nth(1) {
+            None => {
+                let us = self.kernel.timeslice_us();
+                output!(self, "Quantum: {}.{:03}ms", us / 1000, us % 1000);
+            }
+            Some(arg) => match parse_int(arg.as_bytes()) {
+                Ok(ms) if ms >= QUANTUM_MIN_MS as usize && ms <= QUANTUM_MAX_MS as usize => {
+                    let ms = ms as u32;
+                    match self.kernel.set_timeslice_us(ms * 1000, &self.capability) {
+                        ReturnCode::SUCCESS => output!(self, "Quantum set to {}ms", ms),
+                        code => fail_code!(self, code, "Not supported by current scheduler"),
+                    }
+                }
+                _ => fail!(
+                    self,
+                    "quantum: expected {}-{} milliseconds",
+                    QUANTUM_MIN_MS,
+                    QUANTUM_MAX_MS
+                ),
+            },
+        }
+    }
+
+    fn args(&self, command: &str) {
+        let target = match command.split_whitespace().nth(1) {
+            Some(target) => target,
+            None => {
+                fail!(self, "Usage: args <process>");
+                return;
+            }
+        };
+        let found = Cell::new(false);
+        self.kernel
+            .process_each_capability(&self.capability, |i, proc| {
+                if process_matches(i, proc.get_process_name(), target) {
+                    found.set(true);
+                    let call = proc.init_call();
+                    output!(self, "{} entry {:#x}", proc.get_process_name(), call.pc);
+                    output!(self, "  flash start {:#x}", call.argument0);
+                    output!(self, "  memory start {:#x}", call.argument1);
+                    output!(self, "  memory length {}", call.argument2);
+                    output!(self, "  memory break {:#x}", call.argument3);
+                }
+            });
+        if !found.get() {
+            fail!(self, "No process {}", target);
+        }
+    }
+
+    fn reset_reason(&self, command: &str) {
+        let reset_cause = match self.reset_cause.map(|reset_cause| *reset_cause) {
+            Some(reset_cause) => reset_cause,
+            None => {
+                output!(self, "Reset cause is not available on this board");
+                return;
+            }
+        };
+        match command.split_whitespace().nth(1) {
+            None => output!(
+                self,
+                "Reset reason: {:?} (raw {:#010x})",
+                reset_cause.reason(),
+                reset_cause.raw()
+            ),
+            Some("clear") => match reset_cause.clear() {
+                ReturnCode::SUCCESS => output!(self, "Reset cause cleared"),
+                ReturnCode::ENOSUPPORT => {
+                    output!(
+                        self,
+                        "Reset cause is cleared by the next reset on this chip"
+                    )
+                }
+                error => fail_code!(self, error, "resetreason: clearing failed: {:?}", error),
+            },
+            Some(_) => fail!(self, "Usage: resetreason [clear]"),
+        }
+    }
+
+    fn adc(&self, command: &str) {
+        let adc = match self.adc.map(|adc| *adc) {
+            Some(adc) => adc,
+            None => {
+                fail!(self, "adc: not supported, no ADC registered");
+                return;
+            }
+        };
+        let channel = match command
+            .split_whitespace()
+            .nth(1)
+            .map(|arg| parse_int(arg.as_bytes()))
+        {
+            Some(Ok(channel)) if channel < adc.channel_count() => channel,
+            Some(Ok(channel)) => {
+                output!(
+                    self,
+                    "adc: no channel {}, there are {}",
+                    channel,
+                    adc.channel_count()
+                );
+                return;
+            }
+            Some(Err(err)) => {
+                output!(self, "adc: channel {}", err);
+                return;
+            }
+            None => {
+                fail!(self, "Usage: adc <channel>");
+                return;
+            }
+        };
+        let result = adc.sample(channel);
+        if result == ReturnCode::SUCCESS {
+            // `sample_ready` prints the sample.
+            self.stream.set(Stream::Adc { channel: channel });
+        } else {
+            fail_code!(self, result, "adc: sampling failed: {:?}", result);
+        }
+    }
+
+    fn temp(&self, command: &str) {
+        let watch = match command.split_whitespace().nth(1) {
+            None => false,
+            Some("watch") => true,
+            Some(_) => {
+                fail!(self, "Usage: temp [watch]");
+                return;
+            }
+        };
+        if self.temperature.is_none() {
+            fail!(self, "temp: unsupported, no temperature sensor registered");
+            return;
+        }
+        if watch && self.alarm.is_none() {
+            fail!(self, "temp: no alarm registered");
+            return;
+        }
+        if watch {
+            output!(
+                self,
+                "Reading the temperature every second; press any key to stop"
+            );
+        }
+        self.temp_read(watch);
+    }
+
+    // Ask the sensor for a reading, which `callback` prints, and for
+    // another after it if `watch`.
+    fn temp_read(&self, watch: bool) {
+        let result = self
+            .temperature
+            .map_or(ReturnCode::ENODEVICE, |sensor| sensor.read_temperature());
+        if result == ReturnCode::SUCCESS {
+            self.stream.set(Stream::Temperature { watch: watch });
+        } else {
+            fail_code!(self, result, "temp: reading failed: {:?}", result);
+            if self.stream.get() == Stream::TemperatureWatch {
+                self.end_watch();
+            }
+        }
+    }
+
+    fn date(&self, command: &str) {
+        let clock = match self.date_time.map(|clock| *clock) {
+            Some(clock) => clock,
+            None => {
+                fail!(self, "No RTC configured");
+                return;
+            }
+        };
+        let mut args = command.split_whitespace().skip(1);
+        let value = match (args.next(), args.next(), args.next(), args.next()) {
+            (None, _, _, _) => None,
+            (Some("set"), Some(date), Some(time), None) => match parse_date_time(date, time) {
+                Some(value) => Some(value),
+                None => {
+                    fail!(self, "date: {} {} is not a valid date and time", date, time);
+                    return;
+                }
+            },
+            _ => {
+                fail!(self, "Usage: date [set YYYY-MM-DD HH:MM:SS]");
+                return;
+            }
+        };
+        let (result, stream) = match value {
+            Some(value) => (clock.set_date_time(value), Stream::DateSet(value)),
+            None => (clock.get_date_time(), Stream::DateRead { expected: None }),
+        };
+        if result == ReturnCode::SUCCESS {
+            self.stream.set(stream);
+        } else {
+            fail_code!(self, result, "date: failed: {:?}", result);
+        }
+    }
+
+    fn clock(&self, command: &str) {
+        let clock = match self.clock_scaling.map(|clock| *clock) {
+            Some(clock) => clock,
+            None => {
+                fail!(self, "Clock scaling not supported");
+                return;
+            }
+        };
+        let mut args = command.split_whitespace().skip(1);
+        let frequency = match (args.next(), args.next()) {
+            (None, _) => {
+                output!(self, "Clock: {} Hz", clock.frequency());
+                return;
+            }
+            (Some(frequency), None) => match parse_int(frequency.as_bytes()) {
+                Ok(frequency) => frequency,
+                Err(err) => {
+                    fail!(self, "clock: frequency {}", err);
+                    return;
+                }
+            },
+            _ => {
+                fail!(self, "Usage: clock [freq]");
+                return;
+            }
+        };
+        let frequencies = clock.frequencies();
+        if !frequencies
+            .iter()
+            .any(|&supported| supported as usize == frequency)
+        {
+            fail!(
+                self,
+                "clock: {} Hz is not supported, valid frequencies:{}",
+                frequency,
+                Frequencies(frequencies)
+            );
+            return;
+        }
+        // Sending this also makes sure the queue drains, and with it that
+        // `stream_next` switches the clock.
+        output!(self, "Switching clock to {} Hz", frequency);
+        self.stream.set(Stream::Clock {
+            frequency: frequency as u32,
+        });
+    }
+
+    // Switch the CPU clock for `clock`, now that nothing is being sent, and
+    // configure the UARTs for the same baud rate at the new frequency.
+    fn clock_switch(&self, frequency: u32) {
+        self.stream.set(Stream::Idle);
+        let result = self.clock_scaling.map_or(ReturnCode::ENOSUPPORT, |clock| {
+            clock.set_frequency(frequency)
+        });
+        if result == ReturnCode::SUCCESS {
+            match self.initialize() {
+                ReturnCode::SUCCESS => output!(self, "Clock: {} Hz", frequency),
+                error => fail_code!(
+                    self,
+                    error,
+                    "clock: switched to {} Hz, but a UART rejected {} baud: {:?}",
+                    frequency,
+                    self.baud_rate,
+                    error
+                ),
+            }
+        } else {
+            fail_code!(self, result, "clock: failed: {:?}", result);
+        }
+        self.command_complete();
+    }
+
+    fn log_storage(&self, rotate: bool) {
+        let command = if rotate { "logrotate" } else { "logflush" };
+        let log = match self.log_storage.map(|log| *log) {
+            Some(log) => log,
+            None => {
+                fail!(self, "{}: unsupported, no log storage registered", command);
+                return;
+            }
+        };
+        let result = if rotate { log.rotate() } else { log.flush() };
+        if result == ReturnCode::SUCCESS {
+            // `log_done` reports how it went.
+            self.stream.set(Stream::LogStorage { rotate: rotate });
+        } else {
+            fail_code!(self, result, "{} failed: {:?}", command, result);
+        }
+    }
+
+    fn perf(&self, command: &str) {
+        let target = match command.split_whitespace().nth(1) {
+            Some(target) => target,
+            None => {
+                fail!(self, "Usage: perf <process>");
+                return;
+            }
+        };
+        if !KernelInfo::new(self.kernel).counts_cycles(&self.capability) {
+            fail!(self, "Performance counters unavailable");
+            return;
+        }
+        let found = Cell::new(false);
+        self.kernel
+            .process_each_capability(&self.capability, |i, proc| {
+                if !found.get() && process_matches(i, proc.get_process_name(), target) {
+                    found.set(true);
+                    output!(
+                        self,
+                        "{}: {} cycles",
+                        proc.get_process_name(),
+                        Thousands(proc.debug_cycle_count())
+                    );
+                }
+            });
+        if !found.get() {
+            fail!(self, "No process {}", target);
+        }
+    }
+
+    fn run_queue(&self) {
+        let current = KernelInfo::new(self.kernel).last_scheduled(&self.capability);
+        let position = Cell::new(0);
+        output!(self, "Run queue (round-robin):");
+        self.kernel
+            .process_each_capability(&self.capability, |i, proc| {
+                position.set(position.get() + 1);
+                output!(
+                    self,
+                    "{} {:>2}. [{}] {} {}",
+                    if current == Some(i) { '*' } else { ' ' },
+                    position.get(),
+                    i,
+                    proc.get_process_name(),
+                    self.colorize(
+                        format_args!("{:?}", proc.get_state()),
+                        state_style(proc.get_state())
+                    )
+                );
+            });
+        if position.get() == 0 {
+            output!(self, "No processes");
+        }
+    }
+
+    fn probe(&self, command: &str) {
+        match command.split_whitespace().nth(1) {
+            Some("i2c") => match self.i2c.map(|device| *device) {
+                Some(device) => {
+                    device.enable();
+                    self.i2c_probe(I2C_PROBE_FIRST, 0);
+                }
+                None => fail!(self, "No I2C configured"),
+            },
+            Some("spi") => {
+                let device = match self.spi.map(|device| *device) {
+                    Some(device) => device,
+                    None => {
+                        fail!(self, "No SPI configured");
+                        return;
+                    }
+                };
+                match (self.spi_write_buffer.take(), self.spi_read_buffer.take()) {
+                    (Some(write), Some(read)) => {
+                        for byte in write.iter_mut() {
+                            *byte = 0;
+                        }
+                        write[0] = SPI_READ_ID;
+                        let len = cmp::min(write.len(), read.len());
+                        let result = device.read_write_bytes(write, Some(read), len);
+                        if result == ReturnCode::SUCCESS {
+                            // `read_write_done` prints the ID.
+                            self.stream.set(Stream::SpiProbe);
+                        } else {
+                            fail_code!(self, result, "probe: SPI transfer failed: {:?}", result);
+                        }
+                    }
+                    (write, read) => {
+                        write.map(|buffer| self.spi_write_buffer.replace(buffer));
+                        read.map(|buffer| self.spi_read_buffer.replace(buffer));
+                        fail!(self, "probe: SPI is busy");
+                    }
+                }
+            }
+            _ => fail!(self, "Usage: probe i2c|spi"),
+        }
+    }
+
+    // Ask whether a device answers at `addr` on the I2C bus by reading a byte
+    // from it.
+    fn i2c_probe(&self, addr: u8, found: usize) {
+        match (self.i2c.map(|device| *device), self.i2c_buffer.take()) {
+            (Some(device), Some(buffer)) => {
+                self.stream.set(Stream::I2cProbe {
+                    addr: addr,
+                    found: found,
+                });
+                device.set_address(addr);
+                device.read(buffer, 1);
+            }
+            (device, buffer) => {
+                buffer.map(|buffer| self.i2c_buffer.replace(buffer));
+                device.map(|device| device.disable());
+                fail!(self, "probe: I2C is busy");
+                self.stream.set(Stream::Idle);
+            }
+        }
+    }
+
+    fn irqtest(&self, command: &str) {
+        let samples = match command
+            .split_whitespace()
+            .nth(1)
+            .map(|arg| parse_int(arg.as_bytes()))
+        {
+            None => IRQTEST_DEFAULT_SAMPLES,
+            Some(Ok(n)) if n > 0 && n <= IRQTEST_MAX_SAMPLES => n,
+            Some(_) => {
+                fail!(self, "irqtest: expected 1-{} samples", IRQTEST_MAX_SAMPLES);
+                return;
+            }
+        };
+        if !self.alarm.is_some() {
+            fail!(self, "irqtest: no alarm registered");
+            return;
+        }
+        self.stream.set(Stream::IrqTest {
+            samples: samples,
+            remaining: samples,
+            due: 0,
+            min: u32::max_value(),
+            max: 0,
+            total: 0,
+        });
+        self.irqtest_arm();
+    }
+
+    // Set the next `irqtest` alarm, 1ms from now.
+    fn irqtest_arm(&self) {
+        self.alarm.map(|alarm| {
+            if let Stream::IrqTest {
+                samples,
+                remaining,
+                min,
+                max,
+                total,
+                ..
+            } = self.stream.get()
+            {
+                let due = alarm
+                    .now()
+                    .wrapping_add(cmp::max(alarm.frequency() / 1000, 1));
+                self.stream.set(Stream::IrqTest {
+                    samples: samples,
+                    remaining: remaining,
+                    due: due,
+                    min: min,
+                    max: max,
+                    total: total,
+                });
+                alarm.set_alarm(due);
+            }
+        });
+    }
+
+    // Record when an `irqtest` alarm's callback ran, and set the next one or
+    // report the results.
+    fn irqtest_fired(&self) {
+        let now = self.alarm.map_or(0, |alarm| alarm.now());
+        let frequency = self.alarm.map_or(1, |alarm| alarm.frequency());
+        if let Stream::IrqTest {
+            samples,
+            remaining,
+            due,
+            min,
+            max,
+            total,
+        } = self.stream.get()
+        {
+            let latency = now.wrapping_sub(due);
+            let (min, max) = (cmp::min(min, latency), cmp::max(max, latency));
+            let total = total.saturating_add(latency);
+            if remaining > 1 {
+                self.stream.set(Stream::IrqTest {
+                    samples: samples,
+                    remaining: remaining - 1,
+                    due: due,
+                    min: min,
+                    max: max,
+                    total: total,
+                });
+                self.irqtest_arm();
+                return;
+            }
+            let us = |ticks: u32| (ticks as u64 * 1_000_000 / frequency as u64) as u32;
+            output!(
+                self,
+                "irqtest: {} samples, latency min {}us max {}us avg {}us",
+                samples,
+                us(min),
+                us(max),
+                us(total / samples as u32)
+            );
+            self.stream.set(Stream::Idle);
+            self.command_complete();
+        }
+    }
+
+    fn watch(&self, command: &str) {
+        let target = match command.split_whitespace().nth(1) {
+            Some(target) => target,
+            None => {
+                fail!(self, "Usage: watch <process>");
+                return;
+            }
+        };
+        if !self.alarm.is_some() {
+            fail!(self, "watch: no alarm registered");
+            return;
+        }
+        let found = Cell::new(None);
+        self.kernel
+            .process_each_capability(&self.capability, |i, proc| {
+                if found.get().is_none() && process_matches(i, proc.get_process_name(), target) {
+                    output!(
+                        self,
+                        "Watching {}: {:?}; press any key to stop",
+                        proc.get_process_name(),
+                        proc.get_state()
+                    );
+                    found.set(Some((i, proc.get_state())));
+                }
+            });
+        match found.get() {
+            Some((index, state)) => {
+                self.stream.set(Stream::Watch {
+                    index: index,
+                    state: state,
+                });
+                self.watch_arm();
+            }
+            None => fail!(self, "No process {}", target),
+        }
+    }
+
+    fn watch_arm(&self) {
+        self.alarm.map(|alarm| {
+            let period = cmp::max(alarm.frequency() / (1000 / WATCH_PERIOD_MS), 1);
+            alarm.set_alarm(alarm.now().wrapping_add(period));
+        });
+    }
+
+    // Print a line if the watched process changed state since it was last
+    // checked.
+    fn watch_poll(&self) {
+        if let Stream::Watch { index, state } = self.stream.get() {
+            let current = Cell::new(None);
+            self.kernel
+                .process_each_capability(&self.capability, |i, proc| {
+                    if i == index {
+                        current.set(Some(proc.get_state()));
+                        if proc.get_state() != state {
+                            output!(
+                                self,
+                                "{}: {:?} -> {:?}",
+                                proc.get_process_name(),
+                                state,
+                                proc.get_state()
+                            );
+                        }
+                    }
+                });
+            match current.get() {
+                Some(current) => {
+                    self.stream.set(Stream::Watch {
+                        index: index,
+                        state: current,
+                    });
+                    self.watch_arm();
+                }
+                None => {
+                    output!(self, "Process {} is gone", index);
+                    self.end_watch();
+                }
+            }
+        }
+    }
+
+    fn end_watch(&self) {
+        self.stream.set(Stream::Idle);
+        self.command_complete();
+    }
+
+    fn take_snapshot(&self) {
+        let snapshot = Cell::new([None; SNAPSHOT_LEN]);
+        self.kernel
+            .process_each_capability(&self.capability, |i, proc| {
+                if i < SNAPSHOT_LEN {
+                    let mut processes = snapshot.get();
+                    processes[i] = Some(ProcessSnapshot {
+                        name: proc.get_process_name(),
+                        state: proc.get_state(),
+                        restarts: proc.debug_restart_count(),
+                        syscalls: proc.debug_syscall_count(),
+                        dropped_callbacks: proc.debug_dropped_callback_count(),
+                        heap_end: proc.app_memory_break() as usize,
+                    });
+                    snapshot.set(processes);
+                }
+            });
+        self.snapshot.set(snapshot.get());
+        self.snapshot_taken.set(true);
+        output!(self, "Snapshot taken");
+    }
+
+    // Print, one line per process, what changed since the last snapshot.
+    // Counters are reset when a process restarts, so changes to them are
+    // only meaningful for processes that did not restart.
+    fn diff(&self) {
+        if !self.snapshot_taken.get() {
+            fail!(self, "No snapshot; run snapshot first");
+            return;
+        }
+        let snapshot = self.snapshot.get();
+        let changed = Cell::new(false);
+        self.kernel
+            .process_each_capability(&self.capability, |i, proc| {
+                let before = match snapshot.get(i).and_then(|before| *before) {
+                    Some(before) => before,
+                    None => {
+                        changed.set(true);
+                        output!(self, "{:02} {}: new", i, proc.get_process_name());
+                        return;
+                    }
+                };
+                // Restarts counted since `restarts clear`, if that was since
+                // the snapshot.
+                let count = proc.debug_restart_count();
+                let restarts = if count < before.restarts {
+                    count
+                } else {
+                    count - before.restarts
+                };
+                let state = proc.get_state();
+                let heap = proc.app_memory_break() as isize - before.heap_end as isize;
+                if restarts > 0 {
+                    changed.set(true);
+                    output!(
+                        self,
+                        "{:02} {}: restarted {}x, {:?}->{:?}",
+                        i,
+                        before.name,
+                        restarts,
+                        before.state,
+                        state
+                    );
+                    return;
+                }
+                let syscalls = proc.debug_syscall_count().wrapping_sub(before.syscalls);
+                let dropped = proc
+                    .debug_dropped_callback_count()
+                    .wrapping_sub(before.dropped_callbacks);
+                if syscalls == 0 && dropped == 0 && heap == 0 && state == before.state {
+                    return;
+                }
+                changed.set(true);
+                if state == before.state {
+                    output!(
+                        self,
+                        "{:02} {}: syscalls +{}, dropped +{}, heap {:+}",
+                        i,
+                        before.name,
+                        syscalls,
+                        dropped,
+                        heap
+                    );
+                } else {
+                    output!(
+                        self,
+                        "{:02} {}: syscalls +{}, dropped +{}, heap {:+}, {:?}->{:?}",
+                        i,
+                        before.name,
+                        syscalls,
+                        dropped,
+                        heap,
+                        before.state,
+                        state
+                    );
+                }
+            });
+        if !changed.get() {
+            output!(self, "No changes");
+        }
+    }
+
+    fn ipc(&self, command: &str) {
+        let target = match command.split_whitespace().nth(1) {
+            Some(target) => target,
+            None => {
+                fail!(self, "Usage: ipc <process>");
+                return;
+            }
+        };
+        let ipc = match self.ipc.map(|ipc| *ipc) {
+            Some(ipc) => ipc,
+            None => {
+                fail!(self, "IPC is not available on this board");
+                return;
+            }
+        };
+        let found = Cell::new(false);
+        self.kernel
+            .process_each_capability(&self.capability, |i, proc| {
+                if !process_matches(i, proc.get_process_name(), target) {
+                    return;
+                }
+                found.set(true);
+                if ipc.is_service(proc.appid(), &self.capability) {
+                    output!(self, "Service: {}", proc.get_process_name());
+                } else {
+                    output!(
+                        self,
+                        "{} does not provide a service",
+                        proc.get_process_name()
+                    );
+                }
+                let clients = Cell::new(0);
+                ipc.each_client(
+                    proc.appid(),
+                    &|client| {
+                        clients.set(clients.get() + 1);
+                        self.kernel
+                            .process_each_capability(&self.capability, |j, other| {
+                                if j == client {
+                                    output!(self, "  client {:02} {}", j, other.get_process_name());
+                                }
+                            });
+                    },
+                    &self.capability,
+                );
+                if clients.get() == 0 {
+                    output!(self, "  no clients");
+                }
+            });
+        if !found.get() {
+            fail!(self, "No process {}", target);
+        }
+    }
+
+    // Report the grant region of a process. Grant memory is never freed, so
+    // its free space is the single block between the heap and the grants.
+    fn compact(&self, command: &str) {
+        let target = match command.split_whitespace().nth(1) {
+            Some(target) => target,
+            None => {
+                fail!(self, "Usage: compact <process>");
+                return;
+            }
+        };
+        let found = Cell::new(false);
+        self.kernel
+            .process_each_capability(&self.capability, |i, proc| {
+                if process_matches(i, proc.get_process_name(), target) {
+                    found.set(true);
+                    let grant_start = proc.kernel_memory_break() as usize;
+                    let used = proc.mem_end() as usize - grant_start;
+                    let free = grant_start - proc.app_memory_break() as usize;
+                    output!(
+                        self,
+                        "Process {} grant region: {} bytes used, {} bytes free, largest free block {} bytes",
+                        proc.get_process_name(),
+                        used,
+                        free,
+                        free
+                    );
+                    output!(self, "Grant memory is never freed, so there is nothing to compact.");
+                }
+            });
+        if !found.get() {
+            fail!(self, "No process {}", target);
+        }
+    }
+
+    // Print a line for each process with how many grants it has allocated,
+    // then stream a line for each grant.
+    fn grant_audit(&self) {
+        let info = KernelInfo::new(self.kernel);
+        let grants = info.number_grants(&self.capability);
+        self.kernel
+            .process_each_capability(&self.capability, |_i, proc| {
+                let allocated = (0..grants)
+                    .filter(|&grant| {
+                        info.app_grant_allocated(proc.appid(), grant, &self.capability)
+                    })
+                    .count();
+                output!(
+                    self,
+                    "{}: {} of {} grants allocated, grant region {} bytes",
+                    proc.get_process_name(),
+                    allocated,
+                    grants,
+                    proc.mem_end() as usize - proc.kernel_memory_break() as usize
+                );
+            });
+        self.stream.set(Stream::GrantAudit { grant: 0 });
+        self.stream_next();
+    }
+
+    // Print the next line of `grantmap`, for the process at index `process`
+    // or the next one after it, starting at address `after`.
+    fn grant_map_next(&self, process: usize, after: usize) {
+        let info = KernelInfo::new(self.kernel);
+        let grants = info.number_grants(&self.capability);
+        let shown = Cell::new(None);
+        self.kernel
+            .process_each_capability(&self.capability, |i, proc| {
+                if i < process || shown.get().is_some() {
+                    return;
+                }
+                let start = proc.kernel_memory_break() as usize;
+                let structures = proc.kernel_structures_start() as usize;
+                let end = proc.mem_end() as usize;
+                if after == 0 {
+                    output!(
+                        self,
+                        "{}: grant region {:#010x}-{:#010x}, {} bytes",
+                        proc.get_process_name(),
+                        start,
+                        end,
+                        end - start
+                    );
+                    shown.set(Some((i, start)));
+                    return;
+                }
+                // The lowest grant at or above `after`, and the allocation
+                // above it.
+                let mut lowest: Option<(usize, usize)> = None;
+                let mut above = structures;
+                for grant in 0..grants {
+                    let address =
+                        match info.app_grant_address(proc.appid(), grant, &self.capability) {
+                            Some(address) if address >= after => address,
+                            _ => continue,
+                        };
+                    match lowest {
+                        Some((_, lowest_address)) if lowest_address < address => {
+                            above = cmp::min(above, address);
+                        }
+                        Some((_, lowest_address)) => {
+                            above = cmp::min(above, lowest_address);
+                            lowest = Some((grant, address));
+                        }
+                        None => lowest = Some((grant, address)),
+                    }
+                }
+                let next = match lowest {
+                    Some((grant, address)) if address == after => {
+                        output!(
+                            self,
+                            "  {:#010x} +{:<6} grant {}, {} bytes",
+                            address,
+                            address - start,
+                            grant,
+                            above - address
+                        );
+                        above
+                    }
+                    Some((_, address)) => {
+                        output!(
+                            self,
+                            "  {:#010x} +{:<6} other allocations, {} bytes",
+                            after,
+                            after - start,
+                            address - after
+                        );
+                        address
+                    }
+                    None if after < structures => {
+                        output!(
+                            self,
+                            "  {:#010x} +{:<6} other allocations, {} bytes",
+                            after,
+                            after - start,
+                            structures - after
+                        );
+                        structures
+                    }
+                    None => {
+                        output!(
+                            self,
+                            "  {:#010x} +{:<6} kernel data, {} bytes",
+                            structures,
+                            structures - start,
+                            end - structures
+                        );
+                        shown.set(Some((i + 1, 0)));
+                        return;
+                    }
+                };
+                shown.set(Some((i, next)));
+            });
+        match shown.get() {
+            Some((process, after)) => self.stream.set(Stream::GrantMap {
+                process: process,
+                after: after,
+            }),
+            None => {
+                self.stream.set(Stream::Idle);
+                self.command_complete();
+            }
+        }
+    }
+
+    // Print one line of the `dumpstate` report and move on to the next.
+    fn dump_state(&self, section: DumpSection, line: usize) {
+        let info = KernelInfo::new(self.kernel);
+        let next = match (section, line) {
+            (DumpSection::Kernel, 0) => {
+                output!(self, "=== Kernel ===");
+                Stream::DumpState {
+                    section: section,
+                    line: 1,
+                }
+            }
+            (DumpSection::Kernel, _) => {
+                output!(
+                    self,
+                    "Processes: {} loaded, {} active",
+                    info.number_loaded_processes(&self.capability),
+                    info.number_active_processes(&self.capability)
+                );
+                output!(
+                    self,
+                    "Timeslice expirations: {}",
+                    info.timeslice_expirations(&self.capability)
+                );
+                match self.reset_cause.map(|reset_cause| *reset_cause) {
+                    Some(reset_cause) => output!(
+                        self,
+                        "Reset reason: {:?} (raw {:#010x})",
+                        reset_cause.reason(),
+                        reset_cause.raw()
+                    ),
+                    None => output!(self, "Reset reason: not available"),
+                }
+                Stream::DumpState {
+                    section: DumpSection::Processes,
+                    line: 0,
+                }
+            }
+            (DumpSection::Processes, 0) => {
+                output!(self, "=== Processes ===");
+                Stream::DumpState {
+                    section: section,
+                    line: 1,
+                }
+            }
+            (DumpSection::Memory, 0) => {
+                output!(self, "=== Memory ===");
+                Stream::DumpState {
+                    section: section,
+                    line: 1,
+                }
+            }
+            (_, _) => {
+                // The next process from index `line - 1` on.
+                let printed = Cell::new(None);
+                self.kernel
+                    .process_each_capability(&self.capability, |i, proc| {
+                        if i < line - 1 || printed.get().is_some() {
+                            return;
+                        }
+                        printed.set(Some(i));
+                        let name = proc.get_process_name();
+                        if section == DumpSection::Processes {
+                            output!(
+                                self,
+                                "{:02} {}: {:?}, fault policy {:?}, {} restarts, {} syscalls, {} dropped callbacks",
+                                i,
+                                name,
+                                proc.get_state(),
+                                proc.get_fault_response(),
+                                proc.debug_restart_count(),
+                                proc.debug_syscall_count(),
+                                proc.debug_dropped_callback_count()
+                            );
+                        } else {
+                            let start = proc.mem_start() as usize;
+                            let app_break = proc.app_memory_break() as usize;
+                            let kernel_break = proc.kernel_memory_break() as usize;
+                            output!(
+                                self,
+                                "{:02} {}: {} bytes at {:#010x}, {} used by the process, {} free, {} of grants",
+                                i,
+                                name,
+                                proc.mem_end() as usize - start,
+                                start,
+                                app_break - start,
+                                kernel_break - app_break,
+                                proc.mem_end() as usize - kernel_break
+                            );
+                        }
+                    });
+                match (printed.get(), section) {
+                    (Some(i), _) => Stream::DumpState {
+                        section: section,
+                        line: i + 2,
+                    },
+                    (None, DumpSection::Processes) => Stream::DumpState {
+                        section: DumpSection::Memory,
+                        line: 0,
+                    },
+                    (None, _) => Stream::Idle,
+                }
+            }
+        };
+        self.stream.set(next);
+        if next == Stream::Idle {
+            self.command_complete();
+        }
+    }
+
+    // Print a single line of JSON describing the console. The JSON is built in
+    // full before printing so that it is never split by other output.
+    // A line that does not fit in the output is dropped whole, so truncated
+    // JSON is never printed.
+    fn describe(&self) {
+        output!(
+            self,
+            "{}",
+            Description {
+                command_capacity: self.command_capacity,
+                output_capacity: self.output_capacity,
+            }
+        );
+    }
+
+    fn mpu(&self, command: &str) {
+        match command.split_whitespace().nth(1) {
+            None => output!(
+                self,
+                "MPU enforcement: {}",
+                if self.kernel.mpu_enforced() {
+                    "on"
+                } else {
+                    "off"
+                }
+            ),
+            Some("on") => {
+                self.kernel.set_mpu_enforced(true, &self.capability);
+                output!(self, "MPU enforcement on");
+            }
+            Some("off") => {
+                output!(
+                    self,
+                    "WARNING: processes will be able to read and write all memory, including the kernel's"
+                );
+                self.confirm(Action::MpuOff);
+            }
+            Some(_) => fail!(self, "Usage: mpu [on|off]"),
+        }
+    }
+
+    fn sched(&self, command: &str) {
+        match command.split_whitespace().nth(1) {
+            None => output!(
+                self,
+                "Scheduler: {}",
+                if self.kernel.scheduler_paused() {
+                    "paused"
+                } else {
+                    "running"
+                }
+            ),
+            Some("pause") => {
+                output!(self, "WARNING: no process will run until 'sched resume'");
+                self.confirm(Action::SchedPause);
+            }
+            Some("resume") => {
+                self.kernel.set_scheduler_paused(false, &self.capability);
+                output!(self, "Scheduler running");
+            }
+            Some(_) => fail!(self, "Usage: sched [pause|resume]"),
+        }
+    }
+
+    fn erase(&self, command: &str) {
+        let target = match command.split_whitespace().nth(1) {
+            Some(target) => target,
+            None => {
+                fail!(self, "Usage: erase <process>");
+                return;
+            }
+        };
+        let page_size = match self.app_flash.map(|flash| flash.page_size()) {
+            Some(page_size) => page_size,
+            None => {
+                fail!(self, "erase: unsupported, no app flash registered");
+                return;
+            }
+        };
+        let found = Cell::new(None);
+        self.kernel
+            .process_each_capability(&self.capability, |i, proc| {
+                if found.get().is_some() || !process_matches(i, proc.get_process_name(), target) {
+                    return;
+                }
+                found.set(Some(i));
+                let start = proc.flash_start() as usize;
+                let end = proc.flash_end() as usize;
+                match proc.get_state() {
+                    State::StoppedRunning | State::StoppedYielded => {}
+                    _ => {
+                        fail!(self, "erase: stop {} first", proc.get_process_name());
+                        return;
+                    }
+                }
+                if page_size == 0 || start % page_size != 0 || end % page_size != 0 {
+                    fail!(
+                        self,
+                        "erase: {:#010x}-{:#010x} is not made of whole {} byte pages",
+                        start,
+                        end,
+                        page_size
+                    );
+                    return;
+                }
+                output!(
+                    self,
+                    "WARNING: this erases {} ({} bytes at {:#010x}), and apps after it will not be loaded",
+                    proc.get_process_name(),
+                    end - start,
+                    start
+                );
+                self.confirm(Action::Erase(i));
+            });
+        if found.get().is_none() {
+            fail!(self, "No process {}", target);
+        }
+    }
+
+    fn reg(&self, command: &str) {
+        let mut args = command.split_whitespace().skip(1);
+        let (name, offset) = match (args.next(), args.next(), args.next()) {
+            (Some(name), Some(offset), None) => (name, offset),
+            _ => {
+                fail!(self, "Usage: reg <peripheral> <offset>");
+                return;
+            }
+        };
+        let offset = match parse_int(offset.as_bytes()) {
+            Ok(offset) => offset,
+            Err(err) => {
+                fail!(self, "reg: offset {}", err);
+                return;
+            }
+        };
+        let info = KernelInfo::new(self.kernel);
+        let peripherals = info.peripherals(&self.capability);
+        let index = match peripherals.iter().position(|p| p.name == name) {
+            Some(index) => index,
+            None => {
+                fail!(self, "reg: no peripheral {} registered", name);
+                return;
+            }
+        };
+        let peripheral = &peripherals[index];
+        let addr = peripheral.base.saturating_add(offset);
+        match info.read_register(index, offset, &self.capability) {
+            Ok(value) => {
+                output!(
+                    self,
+                    "{}+{:#x} ({:#010x}): {:#010x}",
+                    peripheral.name,
+                    offset,
+                    addr,
+                    value
+                );
+                for field in peripheral.fields.iter().filter(|f| f.offset == offset) {
+                    let mask = if field.width >= 32 {
+                        u32::max_value()
+                    } else {
+                        (1 << field.width) - 1
+                    };
+                    output!(
+                        self,
+                        "  {} = {:#x}",
+                        field.name,
+                        value.checked_shr(field.shift).unwrap_or(0) & mask
+                    );
+                }
+            }
+            Err(_) => fail!(
+                self,
+                "reg: offset {:#x} is not a word-aligned register of {} (0-{:#x})",
+                offset,
+                peripheral.name,
+                peripheral.size.saturating_sub(4)
+            ),
+        }
+    }
+
+    // Start erasing the flash of the process at `index`, which must still be
+    // stopped. The process is faulted first, with a response that leaves it
+    // faulted, so it can never run from the erased flash. Returns whether the
+    // erase started.
+    fn erase_start(&self, index: usize) -> bool {
+        let page_size = self.app_flash.map_or(0, |flash| flash.page_size());
+        let range = Cell::new(None);
+        self.kernel
+            .process_each_capability(&self.capability, |i, proc| {
+                if i != index {
+                    return;
+                }
+                match proc.get_state() {
+                    State::StoppedRunning | State::StoppedYielded if page_size > 0 => {
+                        proc.set_fault_response(FaultResponse::Stop);
+                        proc.set_fault_state();
+                        range.set(Some((
+                            proc.flash_start() as usize,
+                            proc.flash_end() as usize,
+                        )))
+                    }
+                    State::StoppedRunning | State::StoppedYielded => {}
+                    _ => fail!(
+                        self,
+                        "erase: {} is no longer stopped",
+                        proc.get_process_name()
+                    ),
+                }
+            });
+        let (start, end) = match range.get() {
+            Some(range) => range,
+            None => return false,
+        };
+        self.stream.set(Stream::Erase {
+            start: start / page_size,
+            page: start / page_size,
+            end: end / page_size,
+        });
+        self.erase_next()
+    }
+
+    // Whether the flash `proc` was loaded from has been erased, so that it
+    // must not be restarted.
+    fn flash_erased(&self, proc: &ProcessType) -> bool {
+        let mut header = [0; 4];
+        let result = KernelInfo::new(self.kernel).read_memory(
+            proc.flash_start() as usize,
+            &mut header,
+            &self.capability,
+        );
+        result == ReturnCode::SUCCESS && header == [0xff; 4]
+    }
+
+    // Erase the page `erase` is up to. Returns whether the erase started.
+    fn erase_next(&self) -> bool {
+        let page = match self.stream.get() {
+            Stream::Erase { page, .. } => page,
+            _ => return false,
+        };
+        let result = self
+            .app_flash
+            .map_or(ReturnCode::ENODEVICE, |flash| flash.erase_page(page));
+        if result != ReturnCode::SUCCESS {
+            self.stream.set(Stream::Idle);
+            fail!(self, "erase: failed at page {}: {:?}", page, result);
+            return false;
+        }
+        true
+    }
+
+    // Ask whether to carry out `action`. The answer is the next byte received.
+    fn confirm(&self, action: Action) {
+        self.enqueue(b"Are you sure? [y/N] ");
+        self.flush();
+        self.input_mode.set(InputMode::Confirm(action));
+        let timeout = self.confirm_timeout_ms.get();
+        self.alarm.map(|alarm| {
+            if timeout > 0 {
+                let ticks = timeout as u64 * alarm.frequency() as u64 / 1000;
+                let ticks = cmp::min(ticks, u32::max_value() as u64 / 2) as u32;
+                self.confirm_deadline.set(alarm.now().wrapping_add(ticks));
+                // Output held back by the rate limit has the alarm; the
+                // timeout is checked whenever it fires.
+                if self.tx_resume.get().is_none() {
+                    alarm.set_alarm(self.confirm_deadline.get());
+                }
+            }
+        });
+    }
+
+    // Start waiting to see whether input arrives from a host tool, if
+    // `set_host_detection` asked for it. Returns whether the console is
+    // waiting.
+    fn start_host_detection(&self) -> bool {
+        let detect_ms = self.detect_ms.get();
+        if detect_ms == 0 || !self.script.get().is_empty() {
+            return false;
+        }
+        match self.alarm.map(|alarm| *alarm) {
+            Some(alarm) => {
+                let ticks = detect_ms as u64 * alarm.frequency() as u64 / 1000;
+                let ticks = cmp::min(ticks, u32::max_value() as u64 / 2) as u32;
+                let until = alarm.now().wrapping_add(ticks);
+                self.detect_until.set(Some(until));
+                alarm.set_alarm(until);
+                true
+            }
+            None => {
+                debug!("ProcessConsole: host detection needs an alarm, ignoring it");
+                false
+            }
+        }
+    }
+
+    // Once the time `set_host_detection` waits for input from a host tool is
+    // up without any arriving, print the banner and the prompt for a person.
+    // Otherwise make sure the alarm goes off when it is up.
+    fn check_host_detection(&self) {
+        let until = match self.detect_until.get() {
+            Some(until) => until,
+            None => return,
+        };
+        let up = self.alarm.map_or(true, |alarm| {
+            if alarm.now().wrapping_sub(until) < u32::max_value() / 2 {
+                return true;
+            }
+            if self.tx_resume.get().is_none() {
+                alarm.set_alarm(until);
+            }
+            false
+        });
+        if up {
+            self.detect_until.set(None);
+            if !self.quiet.get() {
+                debug!("{}", BANNER);
+            }
+            self.redraw();
+        }
+    }
+
+    // Give up on the question `confirm` asked if it has waited too long, as
+    // if it had been answered no. Otherwise make sure the alarm goes off when
+    // it times out.
+    fn check_confirm_timeout(&self) {
+        if self.confirm_timeout_ms.get() == 0 {
+            return;
+        }
+        let deadline = self.confirm_deadline.get();
+        let timed_out = self.alarm.map_or(false, |alarm| {
+            let waited = alarm.now().wrapping_sub(deadline);
+            if waited < u32::max_value() / 2 {
+                return true;
+            }
+            if self.tx_resume.get().is_none() {
+                alarm.set_alarm(deadline);
+            }
+            false
+        });
+        if timed_out {
+            self.input_mode.set(InputMode::Busy);
+            self.enqueue(b"\r\n");
+            output!(self, "Timed out, aborted");
+            self.command_complete();
+        }
+    }
+
+    // Handle the answer to `confirm`, then finish the command that asked.
+    fn confirmed(&self, action: Action, byte: u8) {
+        self.input_mode.set(InputMode::Busy);
+        if byte.is_ascii_graphic() {
+            self.echo(&[byte]);
+        }
+        self.enqueue(b"\r\n");
+        if byte == b'y' || byte == b'Y' {
+            match action {
+                Action::MpuOff => {
+                    self.kernel.set_mpu_enforced(false, &self.capability);
+                    output!(self, "MPU enforcement off");
+                }
+                Action::SchedPause => {
+                    self.kernel.set_scheduler_paused(true, &self.capability);
+                    output!(self, "Scheduler paused");
+                }
+                Action::Erase(index) => {
+                    if self.erase_start(index) {
+                        // `erase_complete` finishes the command.
+                        return;
+                    }
+                }
+                Action::ClearCallbacks(index) => {
+                    self.kernel
+                        .process_each_capability(&self.capability, |i, proc| {
+                            if i == index {
+                                output!(
+                                    self,
+                                    "Dropped {} queued tasks of {}",
+                                    proc.clear_tasks(),
+                                    proc.get_process_name()
+                                );
+                            }
+                        });
+                }
+            }
+        } else {
+            output!(self, "Aborted");
+        }
+        self.command_complete();
+    }
+
+    fn restarts(&self, command: &str) {
+        let target = command.split_whitespace().nth(1);
+        let found = Cell::new(false);
+        self.kernel
+            .process_each_capability(&self.capability, |i, proc| match target {
+                Some("clear") => proc.debug_clear_restart_count(),
+                Some(target) if !process_matches(i, proc.get_process_name(), target) => {}
+                _ => {
+                    found.set(true);
+                    let count = proc.debug_restart_count();
+                    output!(
+                        self,
+                        "{}: {} restart{}",
+                        proc.get_process_name(),
+                        count,
+                        if count == 1 { "" } else { "s" }
+                    );
+                }
+            });
+        match target {
+            Some("clear") => output!(self, "Restart counts cleared"),
+            Some(target) if !found.get() => fail!(self, "No process {}", target),
+            _ => {}
+        }
+    }
+
+    fn callbacks(&self, command: &str) {
+        let mut args = command.split_whitespace().skip(1);
+        let (target, clear) = match (args.next(), args.next()) {
+            (Some(target), None) => (target, false),
+            (Some(target), Some("clear")) => (target, true),
+            _ => {
+                fail!(self, "Usage: callbacks <process> [clear]");
+                return;
+            }
+        };
+        let found = Cell::new(None);
+        self.kernel
+            .process_each_capability(&self.capability, |i, proc| {
+                if found.get().is_none() && process_matches(i, proc.get_process_name(), target) {
+                    found.set(Some(i));
+                    if !clear {
+                        self.print_callbacks(proc);
+                    }
+                }
+            });
+        match found.get() {
+            None => fail!(self, "No process {}", target),
+            Some(index) if clear => self.confirm(Action::ClearCallbacks(index)),
+            Some(_) => {}
+        }
+    }
+
+    // Print how many tasks of each kind are queued for a process.
+    fn print_callbacks(&self, proc: &ProcessType) {
+        let mut kinds: [Option<(PendingTask, usize)>; CALLBACK_KINDS] = [None; CALLBACK_KINDS];
+        let mut others = 0;
+        let mut index = 0;
+        while let Some(task) = proc.peek_task(index) {
+            index += 1;
+            let kind = match task {
+                Task::FunctionCall(call) => PendingTask::Callback(call.pc),
+                Task::IPC((appid, _)) => PendingTask::Ipc(appid.idx()),
+            };
+            match kinds
+                .iter()
+                .position(|slot| slot.map_or(true, |(seen, _)| seen == kind))
+            {
+                Some(slot) => {
+                    let count = kinds[slot].map_or(0, |(_, count)| count);
+                    kinds[slot] = Some((kind, count + 1));
+                }
+                None => others += 1,
+            }
+        }
+        if index == 0 {
+            output!(self, "No callbacks queued for {}", proc.get_process_name());
+            return;
+        }
+        for &(kind, count) in kinds.iter().filter_map(|slot| slot.as_ref()) {
+            match kind {
+                PendingTask::Callback(pc) => output!(self, "Callback to {:#010x}: {}", pc, count),
+                PendingTask::Ipc(index) => output!(self, "IPC with process {}: {}", index, count),
+            }
+        }
+        if others > 0 {
+            output!(self, "{} more of other kinds", others);
+        }
+    }
+
+    fn brown_out(&self, command: &str) {
+        let detector = match self.brown_out.map(|detector| *detector) {
+            Some(detector) => detector,
+            None => {
+                fail!(self, "bod: unsupported, no brown-out detector registered");
+                return;
+            }
+        };
+        let (min, max) = detector.threshold_range();
+        match command.split_whitespace().nth(1) {
+            None => {
+                output!(
+                    self,
+                    "Brown-out threshold: {} (range {}-{}), control register {:#010x}",
+                    detector.threshold(),
+                    min,
+                    max,
+                    detector.raw()
+                );
+                output!(
+                    self,
+                    "Brown-out detected since reset: {}",
+                    if detector.detected() { "yes" } else { "no" }
+                );
+            }
+            Some(value) => match parse_int(value.as_bytes()) {
+                Ok(threshold) if threshold >= min as usize && threshold <= max as usize => {
+                    match detector.set_threshold(threshold as u32) {
+                        ReturnCode::SUCCESS => {
+                            output!(self, "Brown-out threshold set to {}", threshold)
+                        }
+                        ReturnCode::EOFF => {
+                            fail!(self, "bod: failed: the detector's configuration is locked")
+                        }
+                        error => fail_code!(self, error, "bod: failed: {:?}", error),
+                    }
+                }
+                Ok(_) => fail!(self, "bod: threshold must be from {} to {}", min, max),
+                Err(err) => fail!(self, "bod: threshold {}", err),
+            },
+        }
+    }
+
+    fn check_driver(&self, command: &str) {
+        let mut args = command.split_whitespace().skip(1);
+        let (driver, command_num) = match (args.next(), args.next(), args.next()) {
+            (Some(driver), command_num, None) => (driver, command_num),
+            _ => {
+                fail!(self, "Usage: checkdriver <driver> [command]");
+                return;
+            }
+        };
+        let driver = match parse_int(driver.as_bytes()) {
+            Ok(driver) => driver,
+            Err(err) => {
+                fail!(self, "checkdriver: driver {}", err);
+                return;
+            }
+        };
+        let command_num = match command_num.map(|num| parse_int(num.as_bytes())) {
+            None => None,
+            Some(Ok(num)) => Some(num),
+            Some(Err(err)) => {
+                fail!(self, "checkdriver: command {}", err);
+                return;
+            }
+        };
+        if self.drivers.get().is_empty() {
+            fail!(self, "checkdriver: unsupported, no driver list registered");
+            return;
+        }
+        match self.drivers.get().iter().find(|&&(num, _)| num == driver) {
+            Some(&(_, name)) => match command_num {
+                Some(num) => output!(
+                    self,
+                    "Driver {:#x} ({}): available, command {}: allowed",
+                    driver,
+                    name,
+                    num
+                ),
+                None => output!(self, "Driver {:#x} ({}): available", driver, name),
+            },
+            None => fail!(
+                self,
+                "Driver {:#x}: not registered, apps get ENODEVICE",
+                driver
+            ),
+        }
+    }
+
+    fn uart_stat(&self) {
+        for (i, channel) in self.channels.iter().enumerate() {
+            if channel.uart.is_none() {
+                continue;
+            }
+            output!(self, "UART {}: {} baud 8N1", i, self.baud_rate);
+            let transmitting = self.tx_in_progress.get() && self.tx_channel.get() == i;
+            let (held_start, held_end) = channel.rx_held.get();
+            output!(
+                self,
+                "  Console: tx {}, rx {}",
+                if channel.tx_stalled.get() {
+                    "stalled"
+                } else if transmitting {
+                    "in progress"
+                } else {
+                    "idle"
+                },
+                if held_start < held_end {
+                    "held"
+                } else if channel.rx_in_progress.get() {
+                    "in progress"
+                } else if !channel.rx_healthy.get() {
+                    "failing"
+                } else {
+                    "idle"
+                }
+            );
+            output!(
+                self,
+                "  Bytes: {} sent, {} received",
+                Thousands(channel.tx_bytes.get() as u64),
+                Thousands(channel.rx_bytes.get() as u64)
+            );
+            match channel.last_error.get() {
+                Some(error) => {
+                    output!(self, "  Errors: {}, last {:?}", channel.errors.get(), error)
+                }
+                None => output!(self, "  Errors: 0"),
+            }
+            if i == 0 {
+                self.uart_status.map(|uart| {
+                    let state = uart.status();
+                    output!(
+                        self,
+                        "  Driver: tx {}, rx {}",
+                        if state.transmitting { "busy" } else { "idle" },
+                        if state.receiving { "busy" } else { "idle" }
+                    );
+                    if state.transmitting || state.receiving {
+                        output!(
+                            self,
+                            "  Driver lengths: tx {}, rx {}",
+                            state.tx_len,
+                            state.rx_len
+                        );
+                    }
+                });
+            }
+        }
+    }
+
+    fn print_state(&self) {
+        output!(
+            self,
+            "running {} mode {:?} command_index {} line_start {}",
+            self.running.get(),
+            self.input_mode.get(),
+            self.command_index.get(),
+            self.line_start.get()
+        );
+        output!(
+            self,
+            "tx_in_progress {} on UART {} redraw_pending {} mid_line {}",
+            self.tx_in_progress.get(),
+            self.tx_channel.get(),
+            self.redraw_pending.get(),
+            self.mid_line.get()
+        );
+        for (i, channel) in self.channels.iter().enumerate() {
+            if channel.uart.is_some() {
+                output!(
+                    self,
+                    "UART {}: rx_in_progress {}",
+                    i,
+                    channel.rx_in_progress.get()
+                );
+            }
+        }
+        output!(
+            self,
+            "output queue {}/{} input queue {}",
+            self.output_queue.map_or(0, |queue| queue.len()),
+            self.output_capacity,
+            self.input_queue.map_or(0, |queue| queue.len())
+        );
+    }
+
+    fn flashdump(&self, command: &str) {
+        let mut args = command.split_whitespace().skip(1);
+        let target = match args.next() {
+            Some(target) => target,
+            None => {
+                fail!(self, "Usage: flashdump <process> [length]");
+                return;
+            }
+        };
+        let len = match args.next().map(|len| parse_int(len.as_bytes())) {
+            None => Some(MEMDUMP_MAX_LEN),
+            Some(Ok(len)) if len > 0 => Some(len),
+            Some(_) => None,
+        };
+        let flash = Cell::new(None);
+        self.kernel
+            .process_each_capability(&self.capability, |i, proc| {
+                if flash.get().is_none() && process_matches(i, proc.get_process_name(), target) {
+                    let start = proc.flash_start() as usize;
+                    flash.set(Some((start, proc.flash_end() as usize - start)));
+                }
+            });
+        match (flash.get(), len) {
+            (None, _) => fail!(self, "No process {}", target),
+            (Some(_), None) => fail!(self, "flashdump: length must be a positive number"),
+            (Some((start, size)), Some(len)) => {
+                let len = cmp::min(len, size);
+                if !KernelInfo::new(self.kernel).memory_readable(start, len, &self.capability) {
+                    fail!(
+                        self,
+                        "flashdump: {:#x}-{:#x} is not readable",
+                        start,
+                        start + len
+                    );
+                    return;
+                }
+                self.stream.set(Stream::MemDump {
+                    addr: start,
+                    remaining: len,
+                });
+                self.stream_next();
+            }
+        }
+    }
+
+    // Transmit raw bytes given as numbers. All of them are checked before any
+    // is sent, so a typo does not send half of a sequence.
+    fn send(&self, command: &str) {
+        let args = command.split_whitespace().skip(1);
+        for arg in args.clone() {
+            match parse_int(arg.as_bytes()) {
+                Ok(byte) if byte <= 0xff => {}
+                Ok(_) => {
+                    fail!(self, "send: '{}' does not fit in a byte", arg);
+                    return;
+                }
+                Err(error) => {
+                    fail!(self, "send: '{}' {}", arg, error);
+                    return;
+                }
+            }
+        }
+        for arg in args {
+            let _ = parse_int(arg.as_bytes()).map(|byte| self.enqueue(&[byte as u8]));
+        }
+        self.flush();
+    }
+
+    fn inject_error(&self, command: &str) {
+        let error = match command.split_whitespace().nth(1) {
+            Some("framing") => uart::Error::FramingError,
+            Some("overrun") => uart::Error::OverrunError,
+            Some("parity") => uart::Error::ParityError,
+            Some("break") => uart::Error::BreakError,
+            _ => {
+                fail!(self, "Usage: injecterror framing|overrun|parity|break");
+                return;
+            }
+        };
+        self.injected_error.set(Some(error));
+        output!(self, "The next receive completes with {:?}", error);
+    }
+
+    fn mem_limit(&self, command: &str) {
+        let mut args = command.split_whitespace().skip(1);
+        let target = match args.next() {
+            Some(target) => target,
+            None => {
+                fail!(self, "Usage: memlimit <process> [bytes|none]");
+                return;
+            }
+        };
+        let limit = match args.next() {
+            None => None,
+            Some("none") => Some(None),
+            Some(value) => match parse_int(value.as_bytes()) {
+                Ok(limit) => Some(Some(limit)),
+                Err(err) => {
+                    fail!(self, "memlimit: limit {}", err);
+                    return;
+                }
+            },
+        };
+        let found = Cell::new(false);
+        self.kernel
+            .process_each_capability(&self.capability, |i, proc| {
+                if found.get() || !process_matches(i, proc.get_process_name(), target) {
+                    return;
+                }
+                found.set(true);
+                let name = proc.get_process_name();
+                let used = (proc.app_memory_break() as usize - proc.mem_start() as usize)
+                    + (proc.mem_end() as usize - proc.kernel_memory_break() as usize);
+                match limit {
+                    Some(Some(limit)) if limit < used => {
+                        fail!(
+                            self,
+                            "memlimit: {} bytes is less than the {} bytes {} already uses",
+                            limit,
+                            used,
+                            name
+                        );
+                    }
+                    Some(limit) => {
+                        proc.debug_set_memory_limit(limit);
+                        match limit {
+                            Some(limit) => output!(self, "{}: limited to {} bytes", name, limit),
+                            None => output!(self, "{}: no memory limit", name),
+                        }
+                    }
+                    None => match proc.debug_memory_limit() {
+                        Some(limit) => {
+                            output!(self, "{}: {} of {} bytes used", name, used, limit)
+                        }
+                        None => output!(self, "{}: {} bytes used, no memory limit", name, used),
+                    },
+                }
+            });
+        if !found.get() {
+            fail!(self, "No process {}", target);
+        }
+    }
+
+    fn memdump(&self, command: &str) {
+        let mut args = command.split_whitespace().skip(1);
+        let addr = args.next().and_then(|a| parse_int(a.as_bytes()).ok());
+        let len = args.next().and_then(|l| parse_int(l.as_bytes()).ok());
+        match (addr, len) {
+            (Some(addr), Some(len)) => {
+                if addr % 4 != 0 {
+                    fail!(self, "memdump: address {:#x} is not word aligned", addr);
+                } else if len == 0 || len > MEMDUMP_MAX_LEN {
+                    fail!(self, "memdump: length must be 1-{} bytes", MEMDUMP_MAX_LEN);
+                } else if !KernelInfo::new(self.kernel).memory_readable(addr, len, &self.capability)
+                {
+                    fail!(
+                        self,
+                        "memdump: {:#x}-{:#x} is not readable",
+                        addr,
+                        addr + len
+                    );
+                } else {
+                    self.stream.set(Stream::MemDump {
+                        addr: addr,
+                        remaining: len,
+                    });
+                    self.stream_next();
+                }
+            }
+            _ => fail!(self, "Usage: memdump <address> <length>"),
+        }
+    }
+
+    // Print the next line of the active stream, or finish the stream and
+    // reprint the prompt if there is nothing left to print.
+    fn stream_next(&self) {
+        match self.stream.get() {
+            // Carried on by `fired`.
+            Stream::Idle
+            | Stream::IrqTest { .. }
+            | Stream::Watch { .. }
+            | Stream::TemperatureWatch => {}
+            // Carried on by the temperature sensor's `callback`.
+            Stream::Temperature { .. } => {}
+            // Carried on by `sample_ready`.
+            Stream::Adc { .. } => {}
+            // Carried on by the real-time clock's client callbacks.
+            Stream::DateRead { .. } | Stream::DateSet(_) => {}
+            Stream::Clock { frequency } => self.clock_switch(frequency),
+            // Carried on by `log_done`.
+            Stream::LogStorage { .. } => {}
+            // Carried on by `erase_complete`.
+            Stream::Erase { .. } => {}
+            // Carried on by the I2C and SPI client callbacks.
+            Stream::I2cProbe { .. } | Stream::SpiProbe => {}
+            Stream::Dmesg { position, end } => {
+                // Skip whatever newer output overwrote while streaming.
+                let position = cmp::max(position, debug::debug_log_range().0);
+                let mut line = [0; DMESG_LINE_LEN];
+                let len = if position < end {
+                    let want = cmp::min(DMESG_LINE_LEN, end - position);
+                    debug::debug_log_read(position, &mut line[..want])
+                } else {
+                    0
+                };
+                if len == 0 {
+                    self.stream.set(Stream::Idle);
+                    self.command_complete();
+                    return;
+                }
+                let (text, consumed) = match line[..len].iter().position(|&byte| byte == b'\n') {
+                    Some(newline) => (&line[..newline], newline + 1),
+                    None => (&line[..len], len),
+                };
+                let text = match text.last() {
+                    Some(&b'\r') => &text[..text.len() - 1],
+                    _ => text,
+                };
+                output!(self, "{}", Escaped(text));
+                self.stream.set(Stream::Dmesg {
+                    position: position + consumed,
+                    end: end,
+                });
+            }
+            Stream::Strace {
+                mut position,
+                end,
+                process,
+            } => {
+                let info = KernelInfo::new(self.kernel);
+                while position < end {
+                    // Calls overwritten while streaming are skipped.
+                    let record = info.syscall_trace_entry(position, &self.capability);
+                    position += 1;
+                    match record {
+                        Some(record) if process.map_or(true, |p| record.process == p) => {
+                            output!(
+                                self,
+                                "{:10}  {:2}  {}",
+                                record.time,
+                                record.process,
+                                TracedSyscall(record.syscall)
+                            );
+                            self.stream.set(Stream::Strace {
+                                position: position,
+                                end: end,
+                                process: process,
+                            });
+                            return;
+                        }
+                        _ => {}
+                    }
+                }
+                self.stream.set(Stream::Idle);
+                self.command_complete();
+            }
+            Stream::DumpState { section, line } => self.dump_state(section, line),
+            Stream::Menu { number } => {
+                match (CommandNames { prefix: "" }).names().nth(number - 1) {
+                    Some(name) => {
+                        output!(self, "{:>3}. {}", number, name);
+                        self.stream.set(Stream::Menu { number: number + 1 });
+                    }
+                    None => {
+                        output!(self, "Enter a number, or an empty line to leave the menu");
+                        // Input from apps and scripts does not come from someone
+                        // choosing from the menu.
+                        if !self.script_running.get() {
+                            self.menu_selecting.set(true);
+                        }
+                        self.stream.set(Stream::Idle);
+                        self.command_complete();
+                    }
+                }
+            }
+            Stream::CommandLog { position, end } => {
+                // `cmdlog clear` may have emptied the log since.
+                let entry = if position < cmp::min(end, self.command_log_count.get()) {
+                    self.command_log.map(|log| log[position % log.len()])
+                } else {
+                    None
+                };
+                match entry {
+                    Some(entry) => {
+                        // Only whole characters are kept.
+                        let mut len = entry.len;
+                        while str::from_utf8(&entry.text[..len]).is_err() {
+                            len -= 1;
+                        }
+                        let text = str::from_utf8(&entry.text[..len]).unwrap_or("");
+                        match entry.time {
+                            Some(time) => {
+                                output!(self, "{:10}ms  {:<8}  {}", time, entry.source, text)
+                            }
+                            None => output!(self, "{:>12}  {:<8}  {}", "-", entry.source, text),
+                        }
+                        self.stream.set(Stream::CommandLog {
+                            position: position + 1,
+                            end: end,
+                        });
+                    }
+                    None => {
+                        self.stream.set(Stream::Idle);
+                        self.command_complete();
+                    }
+                }
+            }
+            Stream::GrantMap { process, after } => self.grant_map_next(process, after),
+            Stream::GrantAudit { grant } => {
+                let info = KernelInfo::new(self.kernel);
+                let grants = info.number_grants(&self.capability);
+                let processes = Cell::new(0);
+                let allocated = Cell::new(0);
+                let region = Cell::new(0);
+                self.kernel
+                    .process_each_capability(&self.capability, |_i, proc| {
+                        processes.set(processes.get() + 1);
+                        if grant < grants {
+                            if info.app_grant_allocated(proc.appid(), grant, &self.capability) {
+                                allocated.set(allocated.get() + 1);
+                            }
+                        } else {
+                            for grant in 0..grants {
+                                if info.app_grant_allocated(proc.appid(), grant, &self.capability) {
+                                    allocated.set(allocated.get() + 1);
+                                }
+                            }
+                            region.set(
+                                region.get() + proc.mem_end() as usize
+                                    - proc.kernel_memory_break() as usize,
+                            );
+                        }
+                    });
+                if grant < grants {
+                    output!(
+                        self,
+                        "Grant {}: allocated in {} of {} processes",
+                        grant,
+                        allocated.get(),
+                        processes.get()
+                    );
+                    self.stream.set(Stream::GrantAudit { grant: grant + 1 });
+                } else {
+                    output!(
+                        self,
+                        "Total: {} grants allocated, {} bytes of grant regions",
+                        allocated.get(),
+                        region.get()
+                    );
+                    self.stream.set(Stream::Idle);
+                    self.command_complete();
+                }
+            }
+            Stream::MemDump { addr, remaining } => {
+                if remaining == 0 {
+                    self.stream.set(Stream::Idle);
+                    self.command_complete();
+                    return;
+                }
+                let count = cmp::min(remaining, MEMDUMP_LINE_LEN);
+                self.print_memdump_line(addr, count);
+                self.stream.set(Stream::MemDump {
+                    addr: addr + count,
+                    remaining: remaining - count,
+                });
+            }
+        }
+    }
+
+    // Print `count` (at most 16) bytes starting at `addr` as
+    // `aaaaaaaa: xx xx ... |ascii|`.
+    fn print_memdump_line(&self, addr: usize, count: usize) {
+        const HEX: &'static [u8] = b"0123456789abcdef";
+        let mut bytes = [0; MEMDUMP_LINE_LEN];
+        let result =
+            KernelInfo::new(self.kernel).read_memory(addr, &mut bytes[..count], &self.capability);
+        if result != ReturnCode::SUCCESS {
+            output!(self, "{:08x}: unreadable", addr);
+            return;
+        }
+        let mut line = [b' '; 3 * MEMDUMP_LINE_LEN + 2 + MEMDUMP_LINE_LEN + 1];
+        line[3 * MEMDUMP_LINE_LEN + 1] = b'|';
+        for (i, &byte) in bytes[..count].iter().enumerate() {
+            line[3 * i] = HEX[(byte >> 4) as usize];
+            line[3 * i + 1] = HEX[(byte & 0xf) as usize];
+            line[3 * MEMDUMP_LINE_LEN + 2 + i] = if byte >= 0x20 && byte < 0x7f {
+                byte
+            } else {
+                b'.'
+            };
+        }
+        let end = 3 * MEMDUMP_LINE_LEN + 2 + count;
+        line[end] = b'|';
+        // Every byte written above is ASCII.
+        let text = str::from_utf8(&line[..end + 1]).unwrap_or("");
+        output!(self, "{:08x}: {}", addr, text);
+    }
+
+    // Stop waiting for a transmission that has taken too long, so the UART it
+    // is on does not hold up output to the other one.
+    fn check_tx_stalled(&self) {
+        if !self.tx_in_progress.get() {
+            return;
+        }
+        let stalled = self.alarm.map_or(false, |alarm| {
+            let limit = alarm.frequency() / 1000 * TX_STALL_MS;
+            alarm.now().wrapping_sub(self.tx_started.get()) > limit
+        });
+        if stalled {
+            let index = self.tx_channel.get();
+            self.channels[index].tx_stalled.set(true);
+            self.tx_in_progress.set(false);
+            self.tx_continues.set(false);
+            output!(self, "UART {} unavailable", index);
+        }
+    }
+
+    // Print the prompt followed by the command typed so far. If the debug
+    // writer is using the UART, defer until it is done.
+    fn redraw(&self) {
+        match self.input_mode.get() {
+            // There is no command line to show.
+            InputMode::Dormant | InputMode::Confirm(_) => return,
+            InputMode::Command | InputMode::Busy => {}
+        }
+        if self.raw.get() {
+            // Raw input has no prompt and is not echoed.
+            return;
+        }
+        if self.stream.get() != Stream::Idle {
+            // The prompt is printed once the stream finishes.
+            return;
+        }
+        if debug::debug_writer_busy() {
+            self.redraw_pending.set(true);
+            return;
+        }
+        self.redraw_pending.set(false);
+        let start = self.line_start.get();
+        let prompt = if start > 0 {
+            CONTINUATION_PROMPT
+        } else {
+            if self.status_line.get() {
+                self.enqueue_status_line();
+            }
+            PROMPT
+        };
+        self.enqueue(prompt);
+        self.command_buffer.map(|command| {
+            self.enqueue_typed(&command[start..self.command_index.get()]);
+        });
+        self.flush();
+    }
+
+    // Queue the line `set statusline` prints before the prompt, such as
+    // `#STATUS procs=4 running=3 faulted=1 freeram=8192`: how many processes
+    // there are, how many are neither stopped nor faulted, how many have
+    // faulted, and how many bytes are free between the processes' heaps and
+    // grants in total.
+    fn enqueue_status_line(&self) {
+        let procs = Cell::new(0);
+        let running = Cell::new(0);
+        let faulted = Cell::new(0);
+        let free = Cell::new(0);
+        self.kernel
+            .process_each_capability(&self.capability, |_, proc| {
+                procs.set(procs.get() + 1);
+                match proc.get_state() {
+                    State::Running | State::Yielded => running.set(running.get() + 1),
+                    State::Fault => faulted.set(faulted.get() + 1),
+                    State::StoppedRunning | State::StoppedYielded => {}
+                }
+                let app_break = proc.app_memory_break() as usize;
+                let kernel_break = proc.kernel_memory_break() as usize;
+                free.set(free.get() + kernel_break.saturating_sub(app_break));
+            });
+        self.output_queue.map(|queue| {
+            let mut writer = QueueWriter { queue: queue };
+            let _ = write!(
+                writer,
+                "#STATUS procs={} running={} faulted={} freeram={}\r\n",
+                procs.get(),
+                running.get(),
+                faulted.get(),
+                free.get()
+            );
+        });
+    }
+
+    // Queue what was typed to be shown, masked or left out while echo is
+    // off.
+    fn enqueue_typed(&self, bytes: &[u8]) {
+        if self.echo_input.get() {
+            self.enqueue(bytes);
+        } else if self.mask.get() != 0 {
+            for _ in bytes {
+                self.enqueue(&[self.mask.get()]);
+            }
+        }
+    }
+
+    // Echo what was typed, masked or left out while echo is off.
+    fn echo_typed(&self, bytes: &[u8]) {
+        if self.echo_input.get() {
+            self.echo(bytes);
+        } else if self.mask.get() != 0 {
+            for _ in bytes {
+                self.echo(&[self.mask.get()]);
+            }
+        }
+    }
+
+    // Echo typed bytes unless doing so would interleave with debug output,
+    // in which case the whole line is redrawn once the debug writer drains.
+    fn echo(&self, bytes: &[u8]) {
+        if self.redraw_pending.get() || debug::debug_writer_busy() {
+            self.redraw_pending.set(true);
+        } else {
+            self.enqueue(bytes);
+            self.flush();
+        }
+    }
+
+    fn enqueue(&self, bytes: &[u8]) {
+        self.output_queue.map(|queue| {
+            for (i, byte) in bytes.iter().enumerate() {
+                if !queue.enqueue(*byte) {
+                    self.queue_dropped
+                        .set(self.queue_dropped.get() + bytes.len() - i);
+                    break;
+                }
+            }
+            self.queue_peak
+                .set(cmp::max(self.queue_peak.get(), queue.len()));
+        });
+    }
+
+    // Transmit as much of the output queue as fits in the write buffer of the
+    // active channel, unless a transmission is already underway or the debug
+    // writer has output pending.
+    fn flush(&self) {
+        if self.tx_in_progress.get() || debug::debug_writer_busy() || self.tx_held() {
+            return;
+        }
+        // With a rate limit, transmit a tenth of a second's worth at a time.
+        let rate = self.max_rate.get();
+        let max = if rate > 0 {
+            cmp::max(rate / 10, 1)
+        } else {
+            usize::max_value()
+        };
+        let index = if self.tx_continues.get() {
+            self.tx_channel.get()
+        } else {
+            self.active_channel.get()
+        };
+        let channel = &self.channels[index];
+        channel.uart.map(|uart| {
+            channel.tx_buffer.take().map(|buffer| {
+                // Send as much as fits in the write buffer, in order, and
+                // leave the rest queued for the next transmission.
+                let len = self.output_queue.map_or(0, |queue| {
+                    let mut len = 0;
+                    while len < cmp::min(buffer.len(), max) {
+                        match queue.dequeue() {
+                            Some(byte) => buffer[len] = byte,
+                            None => break,
+                        }
+                        len += 1;
+                    }
+                    len
+                });
+                self.tx_continues.set(
+                    self.output_queue
+                        .map_or(false, |queue| queue.has_elements()),
+                );
+                if len == 0 {
+                    channel.tx_buffer.replace(buffer);
+                } else {
+                    self.mid_line.set(buffer[len - 1] != ('\n' as u8));
+                    self.tx_in_progress.set(true);
+                    self.tx_channel.set(index);
+                    channel.tx_bytes.set(channel.tx_bytes.get() + len);
+                    self.alarm.map(|alarm| {
+                        let now = alarm.now();
+                        self.tx_started.set(now);
+                        if rate > 0 {
+                            let ticks = len as u64 * alarm.frequency() as u64 / rate as u64;
+                            let ticks = cmp::min(ticks, u32::max_value() as u64 / 2) as u32;
+                            self.tx_resume.set(Some(now.wrapping_add(ticks)));
+                        }
+                    });
+                    uart.transmit(buffer, len);
+                }
+            });
+        });
+    }
+
+    // Whether the rate limit holds back output until the time set by the last
+    // transmission. If so, the alarm is set for then, unless `irqtest` is
+    // using it; its alarms fire often and carry on with the output too.
+    fn tx_held(&self) -> bool {
+        let resume = match self.tx_resume.get() {
+            Some(resume) => resume,
+            None => return false,
+        };
+        let held = self.alarm.map_or(false, |alarm| {
+            let wait = resume.wrapping_sub(alarm.now());
+            if wait == 0 || wait > u32::max_value() / 2 {
+                return false;
+            }
+            match self.stream.get() {
+                Stream::IrqTest { .. } => {}
+                _ => alarm.set_alarm(resume),
+            }
+            true
+        });
+        if !held {
+            self.tx_resume.set(None);
+        }
+        held
+    }
+
+    // Keep output moving once the UART is free: transmit what is queued, then
+    // the next line of an active stream, then any deferred redraw.
+    fn pump(&self) {
+        for channel in self.channels.iter() {
+            if !channel.rx_healthy.get() && self.running.get() {
+                // Give a receive path that stopped after repeated errors
+                // another chance.
+                channel.rx_failures.set(0);
+                channel.receive();
+            }
+        }
+        self.release_debug_output();
+        if self.tx_in_progress.get() || debug::debug_writer_busy() {
+            return;
+        }
+        if self
+            .output_queue
+            .map_or(false, |queue| queue.has_elements())
+        {
+            self.flush();
+        } else if self.stream.get() != Stream::Idle {
+            self.stream_next();
+        } else if self.redraw_pending.get() {
+            self.redraw();
+        }
+    }
+
+    // Throw away the line being typed and any output still streaming, and
+    // start over at a new prompt.
+    fn reset_input(&self) {
+        // Also abandons a question asked by `confirm`.
+        self.input_mode.set(InputMode::Busy);
+        self.next_segment.set(0);
+        self.command_buffer.map(|command| command[0] = 0);
+        self.input_queue
+            .map(|queue| while queue.dequeue().is_some() {});
+        self.input_dropped.set(0);
+        self.timing.set(None);
+        self.command_index.set(0);
+        self.line_start.set(0);
+        if self.stream.get() != Stream::Idle {
+            self.suspend(self.stream.get());
+            self.stream.set(Stream::Idle);
+            self.print_truncation_marker();
+        } else {
+            // End the line that was being typed.
+            self.enqueue(b"\r\n");
+        }
+        self.script.set(&[]);
+        self.chain.set(None);
+        self.menu_selecting.set(false);
+        self.menu_prefill.set(None);
+        self.key_line.set(false);
+        output!(self, "Console reset");
+        // A command the break cut short did not succeed.
+        self.result_failed.set(true);
+        self.result_code.set(Some(ReturnCode::ECANCEL));
+        self.command_complete();
+    }
+
+    // Keep a listing a break is cutting short for `resume`.
+    fn suspend(&self, stream: Stream) {
+        let resumable = match stream {
+            Stream::Dmesg { .. }
+            | Stream::Strace { .. }
+            | Stream::CommandLog { .. }
+            | Stream::MemDump { .. }
+            | Stream::DumpState { .. }
+            | Stream::GrantAudit { .. }
+            | Stream::GrantMap { .. } => true,
+            _ => false,
+        };
+        self.suspended
+            .set(if resumable { Some(stream) } else { None });
+        self.suspended_at
+            .set(self.alarm.map_or(0, |alarm| alarm.now()));
+    }
+
+    // Carry on with the listing a break cut short.
+    fn resume(&self) {
+        let stream = match self.suspended.take() {
+            Some(stream) => stream,
+            None => {
+                fail!(self, "resume: no interrupted listing to continue");
+                return;
+            }
+        };
+        let expired = self.alarm.map_or(false, |alarm| {
+            let keep = alarm.frequency() as u64 * RESUME_KEEP_MS as u64 / 1000;
+            let keep = cmp::min(keep, u32::max_value() as u64 / 2) as u32;
+            alarm.now().wrapping_sub(self.suspended_at.get()) > keep
+        });
+        if expired {
+            fail!(
+                self,
+                "resume: the interrupted listing was kept for {} seconds",
+                RESUME_KEEP_MS / 1000
+            );
+            return;
+        }
+        self.stream.set(stream);
+        self.stream_next();
+    }
+
+    // Complete the word being typed: a command name if it is the first word
+    // of a command, or a process name if it follows a command that takes one.
+    // A word only one name starts with is completed and followed by a space,
+    // several names are completed as far as they agree and listed if that
+    // adds nothing, and the bell rings if no name fits.
+    fn complete(&self) {
+        let listed = self.command_buffer.map_or(false, |command| {
+            let index = self.command_index.get() as usize;
+            let separator = self.separator.get();
+            // The word is completed into the free part of the buffer.
+            let (typed, free) = command.split_at_mut(index);
+            let start = typed
+                .iter()
+                .rposition(|byte| separator != 0 && *byte == separator)
+                .map_or(0, |i| i + 1);
+            let segment = str::from_utf8(&typed[start..]).unwrap_or("|");
+            if segment.contains('|') {
+                self.echo(&[0x07]);
+                return false;
+            }
+            let word_start = segment.rfind(' ').map_or(0, |i| i + 1);
+            let mut word = &segment[word_start..];
+            let prefix = self.prefix.get();
+            if prefix != 0 && segment[..word_start].trim().is_empty() {
+                // The command prefix is not part of the command's name.
+                word = word.trim_left_matches(prefix as char);
+            }
+            let mut words = segment[..word_start].split_whitespace();
+            let completion = Cell::new(Completion::new());
+            let commands = CommandNames { prefix: word };
+            let processes = match (words.next(), words.next()) {
+                (None, _) => {
+                    for name in commands.names() {
+                        let mut c = completion.get();
+                        c.add(name);
+                        completion.set(c);
+                    }
+                    false
+                }
+                (Some(verb), None) if PROCESS_COMMANDS.contains(&verb) => {
+                    self.kernel
+                        .process_each_capability(&self.capability, |_i, proc| {
+                            let name = proc.get_process_name();
+                            if name.starts_with(word) {
+                                let mut c = completion.get();
+                                c.add(name);
+                                completion.set(c);
+                            }
+                        });
+                    true
+                }
+                _ => {
+                    self.echo(&[0x07]);
+                    return false;
+                }
+            };
+            let completion = completion.get();
+            if completion.count == 0 {
+                self.echo(&[0x07]);
+                return false;
+            }
+            if completion.common > word.len() || completion.count == 1 {
+                let rest = completion.first[word.len()..completion.common].as_bytes();
+                let mut len = rest.len();
+                if completion.count == 1 {
+                    len += 1;
+                }
+                if len >= free.len() {
+                    // The completed word does not fit.
+                    self.echo(&[0x07]);
+                    return false;
+                }
+                free[..rest.len()].copy_from_slice(rest);
+                if completion.count == 1 {
+                    free[rest.len()] = ' ' as u8;
+                }
+                free[len] = 0;
+                self.command_index.set(index + len);
+                self.echo_typed(&free[..len]);
+                return false;
+            }
+            // The names agree no further than what is typed: list them under
+            // the line, which is then shown again.
+            self.enqueue(b"\r\n");
+            self.filter.set(([0; FILTER_LEN], 0));
+            if processes {
+                self.kernel
+                    .process_each_capability(&self.capability, |_i, proc| {
+                        let name = proc.get_process_name();
+                        if name.starts_with(word) {
+                            output!(self, "{}", name);
+                        }
+                    });
+            } else {
+                for name in commands.names() {
+                    output!(self, "{}", name);
+                }
+            }
+            true
+        });
+        if listed {
+            self.redraw();
+        }
+    }
+
+    // Run the command for a key typed at an empty prompt with `set keymode
+    // single`, for slow links where every byte counts. Keys that take an
+    // argument in `SINGLE_KEYS` put their command on the line instead, `:`
+    // opens the line for a command typed in full, and any other key rings
+    // the bell. Returns whether it should be run now, as for `command_byte`.
+    fn single_key_byte(&self, byte: u8) -> bool {
+        if byte == b':' {
+            // The next line is typed as in line mode.
+            self.key_line.set(true);
+            return false;
+        }
+        match SINGLE_KEYS.iter().find(|&&(key, _, _)| key == byte) {
+            Some(&(_, name, true)) => {
+                // Enter runs it once the argument has been typed.
+                self.prefill(name);
+                self.echo_typed(name.as_bytes());
+                self.echo_typed(b" ");
+                false
+            }
+            Some(&(_, name, false)) => {
+                self.command_buffer.map(|command| {
+                    if name.len() < command.len() {
+                        command[..name.len()].copy_from_slice(name.as_bytes());
+                        command[name.len()] = 0;
+                        self.command_index.set(name.len());
+                    }
+                });
+                self.echo_typed(name.as_bytes());
+                self.command_byte(b'\r')
+            }
+            None => {
+                self.echo(&[0x07]);
+                false
+            }
+        }
+    }
+
+    // Edit the command line with a byte typed in command mode. Returns whether
+    // the byte ended the command, which should then be run.
+    fn command_byte(&self, byte: u8) -> bool {
+        if self.raw.get() {
+            return self.raw_byte(byte);
+        }
+        if self.single_key.get()
+            && !self.key_line.get()
+            && !self.menu_selecting.get()
+            && self.command_index.get() == 0
+            && byte.is_ascii_graphic()
+        {
+            return self.single_key_byte(byte);
+        }
+        if byte == ('\t' as u8) {
+            self.complete();
+            return false;
+        }
+        if byte == 0x0c || byte == 0x12 {
+            // Ctrl-L or Ctrl-R: show the line being typed again.
+            self.enqueue(b"\r\n");
+            self.redraw();
+            return false;
+        }
+        let mut execute = false;
+        self.command_buffer.map(|command| {
+            let index = self.command_index.get() as usize;
+            let terminator = byte == ('\n' as u8) || byte == ('\r' as u8);
+            if terminator && continues_line(&command[..index]) {
+                // Drop the backslash and keep accumulating.
+                self.echo(&['\r' as u8, '\n' as u8]);
+                command[index - 1] = 0;
+                if index == command.len() - 1 {
+                    // There is no room left to continue into.
+                    output!(self, "Command too long");
+                    command[0] = 0;
+                    self.command_index.set(0);
+                    self.line_start.set(0);
+                } else {
+                    self.command_index.set(index - 1);
+                    self.line_start.set(index - 1);
+                }
+                self.redraw();
+            } else if terminator
+                && index == 0
+                && self.empty_line_ignored.get()
+                && !self.menu_selecting.get()
+            {
+                // Nothing was typed, and nothing is shown for it.
+            } else if terminator {
+                execute = true;
+                self.key_line.set(false);
+                // The command's output always starts on a line of its own.
+                // If the echo of this line was held back for debug output,
+                // show it first rather than leaving it for a redraw.
+                if self.redraw_pending.get() {
+                    self.redraw_pending.set(false);
+                    let start = self.line_start.get();
+                    self.enqueue(if start > 0 {
+                        CONTINUATION_PROMPT
+                    } else {
+                        PROMPT
+                    });
+                    self.enqueue_typed(&command[start..index]);
+                }
+                self.enqueue(b"\r\n");
+                self.flush();
+            } else {
+                let (new_index, echo) = edit_line(command, index, self.line_start.get(), byte);
+                if new_index > index {
+                    self.echo_typed(echo);
+                } else if new_index < index {
+                    // Erasing is shown even when typed bytes are masked.
+                    if self.echo_input.get() || self.mask.get() != 0 {
+                        self.echo(echo);
+                    }
+                } else if !echo.is_empty() {
+                    self.echo(echo);
+                }
+                self.command_index.set(new_index);
+            }
+        });
+        execute
+    }
+
+    // Handle a byte of raw input: store it without echoing it, until a line
+    // terminator ends the command. Bytes that do not fit are dropped, as are
+    // NUL bytes, which would end the command early. Returns whether there is
+    // a command to run.
+    fn raw_byte(&self, byte: u8) -> bool {
+        if byte == ('\n' as u8) || byte == ('\r' as u8) {
+            return true;
+        }
+        if byte == 0 {
+            return false;
+        }
+        self.command_buffer.map(|command| {
+            let index = self.command_index.get();
+            if index < command.len() - 1 {
+                command[index] = byte;
+                command[index + 1] = 0;
+                self.command_index.set(index + 1);
+            }
+        });
+        false
+    }
+}
+
+impl<U: UART, C: ProcessManagementCapability> Driver for ProcessConsole<'a, U, C> {
+    /// Setup shared buffers.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `1`: Buffer holding the text of the command to run
+    /// - `2`: Buffer the command's output is written into
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            1 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.command = slice;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            2 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.output = slice;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `1`: Command completed callback, passed the number of bytes of
+    ///        output written into the output buffer
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            1 => self
+                .apps
+                .enter(app_id, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Run console commands.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Run the command in the command buffer, which is `arg1` bytes
+    ///        long, writing its output into the output buffer. The callback
+    ///        is scheduled once the command has finished. Returns
+    ///        `ENOSUPPORT` for commands apps may not run, which are those
+    ///        that change the system, and `EBUSY` while a command typed on
+    ///        the UART has not finished.
+    fn command(&self, cmd_num: usize, arg1: usize, _: usize, appid: AppId) -> ReturnCode {
+        match cmd_num {
+            0 /* check if present */ => ReturnCode::SUCCESS,
+            1 /* run command */ => {
+                // A command typed on the UART that is still running, waiting
+                // for confirmation or for a choice from the menu would take
+                // the app's command as its own input.
+                let idle = match self.input_mode.get() {
+                    InputMode::Command | InputMode::Dormant => !self.menu_selecting.get(),
+                    InputMode::Busy | InputMode::Confirm(_) => false,
+                };
+                if !idle || self.output_app.is_some() || self.stream.get() != Stream::Idle {
+                    return ReturnCode::EBUSY;
+                }
+                let mut command = [0; APP_COMMAND_LEN];
+                let len = self
+                    .apps
+                    .enter(appid, |app, _| {
+                        app.output_len = 0;
+                        app.command.as_ref().map_or(0, |slice| {
+                            let len = cmp::min(cmp::min(arg1, slice.len()), command.len());
+                            command[..len].copy_from_slice(&slice.as_ref()[..len]);
+                            len
+                        })
+                    }).unwrap_or(0);
+                match str::from_utf8(&command[..len]) {
+                    Ok(s) if !app_command_allowed(s) => ReturnCode::ENOSUPPORT,
+                    Ok(s) => {
+                        self.output_app.set(appid);
+                        self.start_result();
+                        self.execute(s.trim());
+                        // There is no UART transmission to wait for, so print
+                        // any streamed output right away.
+                        while self.stream.get() != Stream::Idle {
+                            self.stream_next();
+                        }
+                        if self.output_app.is_some() {
+                            self.command_complete();
+                        }
+                        ReturnCode::SUCCESS
+                    }
+                    Err(_) => ReturnCode::EINVAL,
+                }
+            }
+            _ => ReturnCode::ENOSUPPORT
+        }
+    }
+}
+
+impl<U: UART, C: ProcessManagementCapability> Client for ProcessConsole<'a, U, C> {
+    fn transmit_complete(&self, buffer: &'static mut [u8], error: uart::Error) {
+        let address = buffer.as_ptr() as usize;
+        let index = self
+            .channels
+            .iter()
+            .position(|channel| channel.tx_address.get() == address)
+            .unwrap_or(self.tx_channel.get());
+        let channel = &self.channels[index];
+        channel.record_error(error);
+        channel.tx_buffer.replace(buffer);
+        if !channel.tx_stalled.get() {
+            self.tx_in_progress.set(false);
+        }
+        // A stalled UART is usable again, but the console may have moved on
+        // to transmitting on the other one.
+        channel.tx_stalled.set(false);
+        self.pump();
+    }
+
+    fn receive_complete(&self, read_buf: &'static mut [u8], rx_len: usize, error: uart::Error) {
+        // A real error takes precedence over one injected for testing.
+        let error = if error == uart::Error::CommandComplete {
+            self.injected_error.take().unwrap_or(error)
+        } else {
+            error
+        };
+        // Answer on whichever channel this input arrived on.
+        let address = read_buf.as_ptr() as usize;
+        let index = self
+            .channels
+            .iter()
+            .position(|channel| channel.rx_address.get() == address)
+            .unwrap_or(0);
+        self.active_channel.set(index);
+        if error == uart::Error::BreakError {
+            if self.input_mode.get() == InputMode::Dormant {
+                self.wake();
+            } else {
+                self.reset_input();
+            }
+        } else if error == uart::Error::CommandComplete {
+            if rx_len == 0 {
+                debug!("ProcessConsole had read of 0 bytes");
+            }
+            // A receive asks for `set rxchunk` bytes, one unless changed. A
+            // line terminator in the middle of a chunk runs its command
+            // straight away, and the bytes after it start the next line;
+            // they are held back if that command does not complete straight
+            // away. Input that arrives while a command is running is still
+            // discarded as usual.
+            let len = cmp::min(rx_len, read_buf.len());
+            let busy = self.input_mode.get() == InputMode::Busy;
+            for position in 0..len {
+                if !busy && self.holding_input() {
+                    self.channels[index].rx_held.set((position, len));
+                    break;
+                }
+                self.receive_byte(read_buf[position]);
+            }
+        }
+        let channel = &self.channels[index];
+        if error == uart::Error::CommandComplete {
+            channel
+                .rx_bytes
+                .set(channel.rx_bytes.get() + cmp::min(rx_len, read_buf.len()));
+        }
+        channel.record_error(error);
+        channel.rx_buffer.replace(read_buf);
+        channel.rx_in_progress.set(false);
+        if error == uart::Error::CommandComplete || error == uart::Error::BreakError {
+            channel.rx_failures.set(0);
+            channel.rx_healthy.set(true);
+        } else {
+            channel.rx_failures.set(channel.rx_failures.get() + 1);
+        }
+        if channel.rx_failures.get() < RX_MAX_FAILURES {
+            channel.receive();
+        } else if channel.rx_healthy.get() {
+            // Stop re-arming so a receive that keeps failing does not
+            // monopolize the kernel; `pump` retries later.
+            channel.rx_healthy.set(false);
+            debug!(
+                "ProcessConsole: {} receive errors in a row, last {:?}; retrying later",
+                RX_MAX_FAILURES, error
+            );
+        }
+    }
+}
+
+impl<U: UART, C: ProcessManagementCapability> adc::Client for ProcessConsole<'a, U, C> {
+    fn sample_ready(&self, sample: u16) {
+        // A break may have ended the command before the sample arrived.
+        if let Stream::Adc { channel } = self.stream.get() {
+            let (bits, reference) = self.adc.map_or((16, None), |adc| {
+                (cmp::min(adc.resolution_bits(), 16), adc.reference_mv())
+            });
+            // Samples are left-justified.
+            let raw = (sample as usize) >> (16 - bits);
+            match reference {
+                Some(reference) if bits > 0 => output!(
+                    self,
+                    "Channel {}: {} ({}mV)",
+                    channel,
+                    raw,
+                    raw * reference / ((1 << bits) - 1)
+                ),
+                _ => output!(self, "Channel {}: {}", channel, raw),
+            }
+            self.stream.set(Stream::Idle);
+            self.command_complete();
+        }
+    }
+}
+
+impl<U: UART, C: ProcessManagementCapability> TemperatureClient for ProcessConsole<'a, U, C> {
+    fn callback(&self, value: usize) {
+        // A key may have ended the command before the reading arrived.
+        if let Stream::Temperature { watch } = self.stream.get() {
+            // Readings are in hundredths of a degree.
+            output!(self, "Temperature: {}.{:02} C", value / 100, value % 100);
+            if watch {
+                self.stream.set(Stream::TemperatureWatch);
+                self.alarm.map(|alarm| {
+                    let period = alarm.frequency() as u64 * TEMP_WATCH_PERIOD_MS as u64 / 1000;
+                    let period = cmp::max(period, 1) as u32;
+                    alarm.set_alarm(alarm.now().wrapping_add(period));
+                });
+            } else {
+                self.stream.set(Stream::Idle);
+                self.command_complete();
+            }
+        }
+    }
+}
+
+impl<U: UART, C: ProcessManagementCapability> DateTimeClient for ProcessConsole<'a, U, C> {
+    fn get_date_time_done(&self, result: Result<DateTimeValues, ReturnCode>) {
+        // A break may have ended the command before the clock answered.
+        let expected = match self.stream.get() {
+            Stream::DateRead { expected } => expected,
+            _ => return,
+        };
+        match (result, expected) {
+            (Ok(value), None) => output!(self, "{}", Date(value)),
+            (Ok(value), Some(expected)) if value == expected => {
+                output!(self, "Date set to {}", Date(value))
+            }
+            (Ok(value), Some(expected)) => fail!(
+                self,
+                "date: set {}, but the clock reads {}",
+                Date(expected),
+                Date(value)
+            ),
+            (Err(error), _) => fail_code!(self, error, "date: failed: {:?}", error),
+        }
+        self.stream.set(Stream::Idle);
+        self.command_complete();
+    }
+
+    fn set_date_time_done(&self, result: ReturnCode) {
+        let expected = match self.stream.get() {
+            Stream::DateSet(expected) => expected,
+            _ => return,
+        };
+        let result = if result == ReturnCode::SUCCESS {
+            // Read the clock back to check.
+            let result = self
+                .date_time
+                .map_or(ReturnCode::ENODEVICE, |clock| clock.get_date_time());
+            if result == ReturnCode::SUCCESS {
+                self.stream.set(Stream::DateRead {
+                    expected: Some(expected),
+                });
+                return;
+            }
+            result
+        } else {
+            result
+        };
+        fail_code!(self, result, "date: failed: {:?}", result);
+        self.stream.set(Stream::Idle);
+        self.command_complete();
+    }
+}
+
+impl<U: UART, C: ProcessManagementCapability, F: flash::Flash> flash::Client<F>
+    for ProcessConsole<'a, U, C>
+{
+    fn read_complete(&self, _buffer: &'static mut F::Page, _error: flash::Error) {}
+
+    fn write_complete(&self, _buffer: &'static mut F::Page, _error: flash::Error) {}
+
+    fn erase_complete(&self, error: flash::Error) {
+        // A break may have ended the command before the page was erased.
+        let (start, page, end) = match self.stream.get() {
+            Stream::Erase { start, page, end } => (start, page, end),
+            _ => return,
+        };
+        if error != flash::Error::CommandComplete {
+            fail!(self, "erase: failed at page {}: {:?}", page, error);
+        } else if page + 1 < end {
+            self.stream.set(Stream::Erase {
+                start: start,
+                page: page + 1,
+                end: end,
+            });
+            if self.erase_next() {
+                return;
+            }
+        } else {
+            let page_size = self.app_flash.map_or(0, |flash| flash.page_size());
+            output!(
+                self,
+                "Erased {} bytes at {:#010x}",
+                (end - start) * page_size,
+                start * page_size
+            );
+        }
+        self.stream.set(Stream::Idle);
+        self.command_complete();
+    }
+}
+
+impl<U: UART, C: ProcessManagementCapability> LogStorageClient for ProcessConsole<'a, U, C> {
+    fn log_done(&self, result: ReturnCode) {
+        // A break may have ended the command before the log finished.
+        if let Stream::LogStorage { rotate } = self.stream.get() {
+            match (rotate, result) {
+                (false, ReturnCode::SUCCESS) => output!(self, "Log flushed"),
+                (true, ReturnCode::SUCCESS) => output!(self, "Log rotated"),
+                (false, result) => fail_code!(self, result, "logflush failed: {:?}", result),
+                (true, result) => fail_code!(self, result, "logrotate failed: {:?}", result),
+            }
+            self.stream.set(Stream::Idle);
+            self.command_complete();
+        }
+    }
+}
+
+impl<U: UART, C: ProcessManagementCapability> I2CClient for ProcessConsole<'a, U, C> {
+    fn command_complete(&self, buffer: &'static mut [u8], error: i2c::Error) {
+        self.i2c_buffer.replace(buffer);
+        // A break may have ended the command before the bus answered.
+        let (addr, mut found) = match self.stream.get() {
+            Stream::I2cProbe { addr, found } => (addr, found),
+            _ => {
+                self.i2c.map(|device| device.disable());
+                return;
+            }
+        };
+        if error == i2c::Error::CommandComplete {
+            found += 1;
+            output!(self, "Device at {:#04x}", addr);
+        }
+        if addr < I2C_PROBE_LAST {
+            self.i2c_probe(addr + 1, found);
+            if self.stream.get() != Stream::Idle {
+                return;
+            }
+        } else {
+            self.i2c.map(|device| device.disable());
+            output!(self, "{} I2C devices found", found);
+            self.stream.set(Stream::Idle);
+        }
+        self.command_complete();
+    }
+}
+
+impl<U: UART, C: ProcessManagementCapability> SpiMasterClient for ProcessConsole<'a, U, C> {
+    fn read_write_done(
+        &self,
+        write_buffer: &'static mut [u8],
+        read_buffer: Option<&'static mut [u8]>,
+        len: usize,
+    ) {
+        self.spi_write_buffer.replace(write_buffer);
+        let read_buffer = match read_buffer {
+            Some(buffer) => buffer,
+            None => return,
+        };
+        // The ID follows the byte the command was sent in.
+        let mut id = [0; 3];
+        let id_len = cmp::min(cmp::min(len, read_buffer.len()).saturating_sub(1), id.len());
+        id[..id_len].copy_from_slice(&read_buffer[1..1 + id_len]);
+        self.spi_read_buffer.replace(read_buffer);
+        if self.stream.get() == Stream::SpiProbe {
+            let id = &id[..id_len];
+            if id.iter().all(|&byte| byte == 0x00) || id.iter().all(|&byte| byte == 0xff) {
+                output!(self, "No SPI device answered");
+            } else {
+                output!(self, "SPI ID:{}", HexBytes(id));
+            }
+            self.stream.set(Stream::Idle);
+            self.command_complete();
+        }
+    }
+}
+
+impl<U: UART, C: ProcessManagementCapability> time::Client for ProcessConsole<'a, U, C> {
+    fn fired(&self) {
+        // Output held back by the rate limit may be due.
+        if self.tx_resume.get().is_some() {
+            self.pump();
+        }
+        if let InputMode::Confirm(_) = self.input_mode.get() {
+            self.check_confirm_timeout();
+        }
+        self.check_host_detection();
+        // A break may have ended the command while this alarm was pending.
+        match self.stream.get() {
+            Stream::IrqTest { .. } => self.irqtest_fired(),
+            Stream::Watch { .. } => self.watch_poll(),
+            Stream::TemperatureWatch => self.temp_read(true),
+            Stream::Idle
+            | Stream::Temperature { .. }
+            | Stream::MemDump { .. }
+            | Stream::Dmesg { .. }
+            | Stream::Adc { .. }
+            | Stream::LogStorage { .. }
+            | Stream::Erase { .. }
+            | Stream::DateRead { .. }
+            | Stream::DateSet(_)
+            | Stream::Clock { .. }
+            | Stream::I2cProbe { .. }
+            | Stream::SpiProbe
+            | Stream::Strace { .. }
+            | Stream::CommandLog { .. }
+            | Stream::Menu { .. }
+            | Stream::DumpState { .. }
+            | Stream::GrantAudit { .. }
+            | Stream::GrantMap { .. } => {}
+        }
+    }
+}
+
+impl<U: UART, C: ProcessManagementCapability> debug::DebugWriterClient
+    for ProcessConsole<'a, U, C>
+{
+    fn write_drained(&self) {
+        let queued = self
+            .output_queue
+            .map_or(false, |queue| queue.has_elements());
+        if self.running.get() && self.mid_line.get() && !queued {
+            // Debug output was printed after the prompt or the command being
+            // typed, so reprint both.
+            self.mid_line.set(false);
+            self.redraw_pending.set(true);
+        }
+        self.pump();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Format `text` through a `WrapWriter` of `width` columns into `buf`,
+    // returning what came out.
+    fn wrap(text: &str, width: usize, buf: &'b mut [u8]) -> &'b str {
+        let len = {
+            let mut writer = WrapWriter::new(BufWriter::new(buf), width);
+            writer.write_str(text).unwrap();
+            writer.finish().len()
+        };
+        str::from_utf8(&buf[..len]).unwrap()
+    }
+
+    fn contains(text: &str, pattern: &str) -> bool {
+        let mut filter = Contains::new(pattern.as_bytes());
+        filter.write_str(text).unwrap();
+        filter.found
+    }
+
+    #[test]
+    fn parse_int_reads_decimal_and_hex() {
+        assert_eq!(parse_int(b"0"), Ok(0));
+        assert_eq!(parse_int(b"1234"), Ok(1234));
+        assert_eq!(parse_int(b"0x1f"), Ok(0x1f));
+        assert_eq!(parse_int(b"0xABCD"), Ok(0xabcd));
+    }
+
+    #[test]
+    fn parse_int_rejects_bad_numbers() {
+        assert_eq!(parse_int(b""), Err(ParseError::Empty));
+        assert_eq!(parse_int(b"0x"), Err(ParseError::Empty));
+        assert_eq!(parse_int(b"12a"), Err(ParseError::InvalidDigit));
+        assert_eq!(parse_int(b"0x1g"), Err(ParseError::InvalidDigit));
+        assert_eq!(parse_int(b"-1"), Err(ParseError::InvalidDigit));
+        assert_eq!(
+            parse_int(b"0xffffffffffffffffff"),
+            Err(ParseError::Overflow)
+        );
+    }
+
+    #[test]
+    fn parse_date_time_reads_valid_dates() {
+        assert_eq!(
+            parse_date_time("2024-02-29", "23:59:59"),
+            Some(DateTimeValues {
+                year: 2024,
+                month: 2,
+                day: 29,
+                hour: 23,
+                minute: 59,
+                second: 59,
+            })
+        );
+        assert!(parse_date_time("2000-02-29", "00:00:00").is_some());
+        assert!(parse_date_time("2018-12-31", "12:00:00").is_some());
+    }
+
+    #[test]
+    fn parse_date_time_rejects_invalid_dates() {
+        assert!(parse_date_time("1900-02-29", "00:00:00").is_none());
+        assert!(parse_date_time("2023-02-29", "00:00:00").is_none());
+        assert!(parse_date_time("2018-04-31", "00:00:00").is_none());
+        assert!(parse_date_time("2018-13-01", "00:00:00").is_none());
+        assert!(parse_date_time("2018-00-01", "00:00:00").is_none());
+        assert!(parse_date_time("2018-01-00", "00:00:00").is_none());
+        assert!(parse_date_time("2018-01-01", "24:00:00").is_none());
+        assert!(parse_date_time("2018-01-01", "00:60:00").is_none());
+        assert!(parse_date_time("2018-1-01", "00:00:00").is_none());
+        assert!(parse_date_time("2018-01-01-01", "00:00:00").is_none());
+        assert!(parse_date_time("2018-01-01", "00:00").is_none());
+        assert!(parse_date_time("2018-01-+1", "00:00:00").is_none());
+    }
+
+    #[test]
+    fn wrap_writer_breaks_between_fields() {
+        let mut buf = [0; 64];
+        assert_eq!(wrap("one two three", 9, &mut buf), "one two\r\n    three");
+        let mut buf = [0; 64];
+        assert_eq!(wrap("short line", 80, &mut buf), "short line");
+    }
+
+    #[test]
+    fn wrap_writer_ignores_escape_sequences() {
+        let mut buf = [0; 64];
+        assert_eq!(
+            wrap("\x1b[1mab\x1b[0m cd", 5, &mut buf),
+            "\x1b[1mab\x1b[0m cd"
+        );
+    }
+
+    #[test]
+    fn wrap_writer_does_not_break_before_long_fields_continue() {
+        let mut buf = [0; 128];
+        let long = "0123456789012345678901234567890123456789";
+        let text = wrap(long, 10, &mut buf);
+        assert_eq!(text, long);
+    }
+
+    #[test]
+    fn filter_finds_pattern_across_writes() {
+        assert!(contains("running process", "proc"));
+        assert!(contains("anything", ""));
+        assert!(!contains("running", "faulted"));
+        assert!(!contains("ab", "abc"));
+        let mut filter = Contains::new(b"abc");
+        filter.write_str("xxa").unwrap();
+        filter.write_str("bcx").unwrap();
+        assert!(filter.found);
+    }
+
+    #[test]
+    fn filter_window_holds_longest_pattern() {
+        let pattern = "0123456789abcdef";
+        assert_eq!(pattern.len(), FILTER_LEN);
+        assert!(contains("xx0123456789abcdefxx", pattern));
+        assert!(!contains("xx0123456789abcdexx", pattern));
+    }
+
+    #[test]
+    fn backslash_continues_line() {
+        assert!(continues_line(b"list \\"));
+        assert!(!continues_line(b"list"));
+        assert!(!continues_line(b""));
+    }
+
+    #[test]
+    fn delete_erases_the_last_byte() {
+        let mut command = [0; 4];
+        assert_eq!(edit_line(&mut command, 0, 0, b'l'), (1, &b"l"[..]));
+        assert_eq!(edit_line(&mut command, 1, 0, 0x7f), (0, &b"\x08 \x08"[..]));
+        assert_eq!(command, [0; 4]);
+        // Nothing is erased before the start of the line.
+        assert_eq!(edit_line(&mut command, 0, 0, 0x7f), (0, &b""[..]));
+        assert_eq!(edit_line(&mut command, 2, 2, 0x08), (2, &b""[..]));
+    }
+
+    #[test]
+    fn recent_output_keeps_newest_bytes_in_order() {
+        let mut recent = RecentOutput::default();
+        let mut buf = [0; RECENT_OUTPUT_LEN];
+        assert_eq!(recent.copy_into(&mut buf), 0);
+        for &byte in b"hello" {
+            recent.record(byte);
+        }
+        let len = recent.copy_into(&mut buf);
+        assert_eq!(&buf[..len], b"hello");
+        for byte in 0..(RECENT_OUTPUT_LEN + 3) as u8 {
+            recent.record(byte);
+        }
+        assert_eq!(recent.copy_into(&mut buf), RECENT_OUTPUT_LEN);
+        assert_eq!(buf[0], 3);
+        assert_eq!(buf[RECENT_OUTPUT_LEN - 1], RECENT_OUTPUT_LEN as u8 + 2);
+        let mut short = [0; 4];
+        assert_eq!(recent.copy_into(&mut short), 4);
+        assert_eq!(short[3], RECENT_OUTPUT_LEN as u8 + 2);
+    }
+
+    #[test]
+    fn apps_may_only_run_read_only_commands() {
+        assert!(app_command_allowed("list"));
+        assert!(app_command_allowed("help list"));
+        assert!(app_command_allowed("list | proc"));
+        assert!(app_command_allowed("checkdriver 1 2"));
+        assert!(app_command_allowed("restarts blink"));
+        assert!(!app_command_allowed("restarts clear"));
+        assert!(!app_command_allowed("checkdriver 1 2 3"));
+        assert!(!app_command_allowed("status now"));
+        assert!(!app_command_allowed("stop blink"));
+        assert!(!app_command_allowed("reboot"));
+        assert!(!app_command_allowed(""));
+        assert!(!app_command_allowed("| list"));
     }
 }
@@ -4,7 +4,8 @@
 use core::cell::Cell;
 use kernel::common::cells::OptionalCell;
 use kernel::common::{List, ListLink, ListNode};
-use kernel::hil::time::{self, Alarm, Time};
+use kernel::hil::time::{self, Alarm, Frequency, Time};
+use process_console::AlarmList;
 
 pub struct VirtualMuxAlarm<'a, Alrm: Alarm> {
     mux: &'a MuxAlarm<'a, Alrm>,
@@ -170,3 +171,25 @@ impl<Alrm: Alarm> time::Client for MuxAlarm<'a, Alrm> {
         }
     }
 }
+
+impl<Alrm: Alarm> AlarmList for MuxAlarm<'a, Alrm> {
+    fn now(&self) -> u32 {
+        self.alarm.now()
+    }
+
+    fn frequency(&self) -> u32 {
+        Alrm::Frequency::frequency()
+    }
+
+    fn each_armed(&self, f: &mut FnMut(u32, usize)) {
+        self.virtual_alarms
+            .iter()
+            .filter(|cur| cur.armed.get())
+            .for_each(|cur| {
+                let owner = cur.client.map_or(0, |client| {
+                    *client as *const time::Client as *const u8 as usize
+                });
+                f(cur.when.get(), owner);
+            });
+    }
+}
@@ -59,10 +59,10 @@ impl MuxI2C<'a> {
             mnode.map(|node| {
                 node.buffer.take().map(|buf| {
                     match node.operation.get() {
-                        Op::Write(len) => self.i2c.write(node.addr, buf, len),
-                        Op::Read(len) => self.i2c.read(node.addr, buf, len),
+                        Op::Write(len) => self.i2c.write(node.addr.get(), buf, len),
+                        Op::Read(len) => self.i2c.read(node.addr.get(), buf, len),
                         Op::WriteRead(wlen, rlen) => {
-                            self.i2c.write_read(node.addr, buf, wlen, rlen)
+                            self.i2c.write_read(node.addr.get(), buf, wlen, rlen)
                         }
                         Op::Idle => {} // Can't get here...
                     }
@@ -84,7 +84,7 @@ enum Op {
 
 pub struct I2CDevice<'a> {
     mux: &'a MuxI2C<'a>,
-    addr: u8,
+    addr: Cell<u8>,
     enabled: Cell<bool>,
     buffer: TakeCell<'static, [u8]>,
     operation: Cell<Op>,
@@ -96,7 +96,7 @@ impl I2CDevice<'a> {
     pub const fn new(mux: &'a MuxI2C<'a>, addr: u8) -> I2CDevice<'a> {
         I2CDevice {
             mux: mux,
-            addr: addr,
+            addr: Cell::new(addr),
             enabled: Cell::new(false),
             buffer: TakeCell::empty(),
             operation: Cell::new(Op::Idle),
@@ -109,6 +109,12 @@ impl I2CDevice<'a> {
         self.mux.devices.push_head(self);
         self.client.set(client);
     }
+
+    /// Talk to the device at `addr` from the next operation on, for clients
+    /// such as bus scanners that are not tied to one device.
+    pub fn set_address(&self, addr: u8) {
+        self.addr.set(addr);
+    }
 }
 
 impl I2CClient for I2CDevice<'a> {
@@ -2,6 +2,8 @@
 
 use kernel::common::registers::{ReadOnly, ReadWrite, WriteOnly};
 use kernel::common::StaticRef;
+use kernel::hil;
+use kernel::ReturnCode;
 
 #[repr(C)]
 struct BscifRegisters {
@@ -368,6 +370,64 @@ pub fn setup_rc_1mhz() {
     while !BSCIF.rc1mcr.is_set(RC1MClockConfig::CLKOEN) {}
 }
 
+/// The brown-out detector on the 3.3V supply, BOD33.
+pub struct Bod33(());
+
+pub static BOD33: Bod33 = Bod33(());
+
+// Offsets of the BOD33 registers, to unlock them.
+const BOD33CTRL_OFFSET: u32 = 0x2C;
+const BOD33LEVEL_OFFSET: u32 = 0x30;
+
+impl hil::brown_out::BrownOutDetector for Bod33 {
+    fn threshold_range(&self) -> (u32, u32) {
+        (0, 63)
+    }
+
+    fn threshold(&self) -> u32 {
+        BSCIF.bod33level.read(BodLevel::VAL)
+    }
+
+    fn set_threshold(&self, threshold: u32) -> ReturnCode {
+        if threshold > 63 {
+            return ReturnCode::EINVAL;
+        }
+        if BSCIF.bod33ctrl.is_set(BodControl::SFV) {
+            return ReturnCode::EOFF;
+        }
+        // The detector must be disabled while its level changes, or the
+        // change can trigger it.
+        let ctrl = BSCIF.bod33ctrl.extract();
+        BSCIF
+            .unlock
+            .write(Unlock::KEY.val(0xAA) + Unlock::ADDR.val(BOD33CTRL_OFFSET));
+        BSCIF
+            .bod33ctrl
+            .modify_no_read(ctrl, BodControl::EN::Disabled);
+        let level = BSCIF.bod33level.extract();
+        BSCIF
+            .unlock
+            .write(Unlock::KEY.val(0xAA) + Unlock::ADDR.val(BOD33LEVEL_OFFSET));
+        BSCIF
+            .bod33level
+            .modify_no_read(level, BodLevel::VAL.val(threshold));
+        BSCIF
+            .unlock
+            .write(Unlock::KEY.val(0xAA) + Unlock::ADDR.val(BOD33CTRL_OFFSET));
+        BSCIF.bod33ctrl.set(ctrl.get());
+        while !BSCIF.pclksr.is_set(PowerClocksStatus::BOD33SYNRDY) {}
+        ReturnCode::SUCCESS
+    }
+
+    fn detected(&self) -> bool {
+        BSCIF.isr.is_set(Interrupt::BOD33DET)
+    }
+
+    fn raw(&self) -> u32 {
+        BSCIF.bod33ctrl.get()
+    }
+}
+
 pub unsafe fn disable_rc_1mhz() {
     let rc1mcr = BSCIF.rc1mcr.extract();
     // Unlock the BSCIF::RC32KCR register
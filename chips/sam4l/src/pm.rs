@@ -8,7 +8,10 @@ use flashcalw;
 use gpio;
 use kernel::common::registers::{FieldValue, ReadOnly, ReadWrite, WriteOnly};
 use kernel::common::StaticRef;
+use kernel::hil;
+use kernel::hil::reset::ResetReason;
 use kernel::ClockInterface;
+use kernel::ReturnCode;
 use scif;
 
 /// §10.7 PM::UserInterface from SAM4L Datasheet.
@@ -856,6 +859,78 @@ impl PowerManager {
     }
 }
 
+impl hil::reset::ResetCause for PowerManager {
+    fn raw(&self) -> u32 {
+        PM_REGS.rcause.get()
+    }
+
+    fn reason(&self) -> ResetReason {
+        let rcause = &PM_REGS.rcause;
+        if rcause.is_set(ResetCause::WDT) {
+            ResetReason::Watchdog
+        } else if rcause.is_set(ResetCause::OCDRST) {
+            ResetReason::SoftReset
+        } else if rcause.is_set(ResetCause::EXT) {
+            ResetReason::PinReset
+        } else if rcause.is_set(ResetCause::BOD) || rcause.is_set(ResetCause::BOD33) {
+            ResetReason::BrownOut
+        } else if rcause.is_set(ResetCause::POR) || rcause.is_set(ResetCause::POR33) {
+            ResetReason::PowerOn
+        } else if rcause.is_set(ResetCause::BKUP) {
+            ResetReason::Backup
+        } else {
+            ResetReason::Unknown
+        }
+    }
+
+    fn clear(&self) -> ReturnCode {
+        // RCAUSE is read-only, and rewritten by the next reset.
+        ReturnCode::ENOSUPPORT
+    }
+}
+
+/// The system clock frequencies `set_frequency` switches between. None of
+/// them needs an external oscillator, whose frequency depends on the board.
+const SCALING_FREQUENCIES: &'static [u32] =
+    &[48000000, 40000000, 12000000, 8200000, 4300000, 1000000];
+
+impl hil::clock::ClockScaling for PowerManager {
+    fn frequency(&self) -> u32 {
+        get_system_frequency()
+    }
+
+    fn frequencies(&self) -> &'static [u32] {
+        SCALING_FREQUENCIES
+    }
+
+    fn set_frequency(&self, frequency: u32) -> ReturnCode {
+        // Already running at this frequency, perhaps from a clock that is
+        // not in the list, such as the PLL.
+        if frequency == get_system_frequency() {
+            return ReturnCode::SUCCESS;
+        }
+        let clock_source = match frequency {
+            48000000 => SystemClockSource::DfllRc32kAt48MHz,
+            40000000 => SystemClockSource::RC80M,
+            12000000 => SystemClockSource::RCFAST {
+                frequency: RcfastFrequency::Frequency12MHz,
+            },
+            8200000 => SystemClockSource::RCFAST {
+                frequency: RcfastFrequency::Frequency8MHz,
+            },
+            4300000 => SystemClockSource::RCFAST {
+                frequency: RcfastFrequency::Frequency4MHz,
+            },
+            1000000 => SystemClockSource::RC1M,
+            _ => return ReturnCode::EINVAL,
+        };
+        unsafe {
+            self.change_system_clock(clock_source);
+        }
+        ReturnCode::SUCCESS
+    }
+}
+
 fn unlock(register_offset: u32) {
     PM_REGS.unlock.set(0xAA000000 | register_offset);
 }
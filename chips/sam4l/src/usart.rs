@@ -323,6 +323,7 @@ impl Drop for USARTRegManager<'a> {
                 + Interrupt::PARE::SET
                 + Interrupt::FRAME::SET
                 + Interrupt::OVRE::SET
+                + Interrupt::RXBRK::SET
                 + Interrupt::TXRDY::SET
                 + Interrupt::RXRDY::SET,
         );
@@ -557,10 +558,12 @@ impl USART {
     }
 
     fn enable_rx_error_interrupts(&self, usart: &USARTRegManager) {
-        usart
-            .registers
-            .ier
-            .write(Interrupt::PARE::SET + Interrupt::FRAME::SET + Interrupt::OVRE::SET);
+        usart.registers.ier.write(
+            Interrupt::PARE::SET
+                + Interrupt::FRAME::SET
+                + Interrupt::OVRE::SET
+                + Interrupt::RXBRK::SET,
+        );
     }
 
     fn disable_rx_interrupts(&self, usart: &USARTRegManager) {
@@ -570,6 +573,7 @@ impl USART {
                 + Interrupt::PARE::SET
                 + Interrupt::FRAME::SET
                 + Interrupt::OVRE::SET
+                + Interrupt::RXBRK::SET
                 + Interrupt::RXRDY::SET,
         );
     }
@@ -660,6 +664,9 @@ impl USART {
                     }
                 });
             });
+        } else if status.is_set(ChannelStatus::RXBRK) {
+            // A break also looks like a framing error, so check for it first.
+            self.abort_rx(usart, hil::uart::Error::BreakError);
         } else if status.is_set(ChannelStatus::PARE) {
             self.abort_rx(usart, hil::uart::Error::ParityError);
         } else if status.is_set(ChannelStatus::FRAME) {
@@ -908,6 +915,17 @@ impl hil::uart::UART for USART {
     }
 }
 
+impl hil::uart::UARTStatus for USART {
+    fn status(&self) -> hil::uart::UARTState {
+        hil::uart::UARTState {
+            transmitting: self.usart_tx_state.get() != USARTStateTX::Idle,
+            receiving: self.usart_rx_state.get() != USARTStateRX::Idle,
+            tx_len: self.tx_len.get(),
+            rx_len: self.rx_len.get(),
+        }
+    }
+}
+
 impl hil::uart::UARTReceiveAdvanced for USART {
     fn receive_automatic(&self, rx_buffer: &'static mut [u8], interbyte_timeout: u8) {
         let usart = &USARTRegManager::new(&self);
@@ -44,13 +44,68 @@ impl AtomicUsize {
 
 unsafe impl Sync for AtomicUsize {}
 
+/// A count of how often something has happened to each of the 32 tasks.
+struct TaskCounts {
+    v: UnsafeCell<[usize; 32]>,
+}
+
+impl TaskCounts {
+    crate const fn new() -> TaskCounts {
+        TaskCounts {
+            v: UnsafeCell::new([0; 32]),
+        }
+    }
+
+    crate fn increment(&self, task: usize) {
+        unsafe {
+            let counts = &mut *self.v.get();
+            counts[task] = counts[task].wrapping_add(1);
+        }
+    }
+
+    crate fn get(&self, task: usize) -> usize {
+        unsafe { (*self.v.get())[task] }
+    }
+
+    crate fn clear(&self) {
+        unsafe { *self.v.get() = [0; 32] }
+    }
+}
+
+unsafe impl Sync for TaskCounts {}
+
 static DEFERRED_CALL: AtomicUsize = AtomicUsize::new(0);
+// How many times each task has been set, and handed out by `next_pending`.
+static SET_COUNTS: TaskCounts = TaskCounts::new();
+static RUN_COUNTS: TaskCounts = TaskCounts::new();
 
 /// Are there any pending `DeferredCall`s?
 pub fn has_tasks() -> bool {
     DEFERRED_CALL.load_relaxed() != 0
 }
 
+/// Returns the pending tasks, one bit per task.
+pub fn pending_tasks() -> usize {
+    DEFERRED_CALL.load_relaxed()
+}
+
+/// Returns how many times the task with number `task` (0-31) has been set,
+/// and how many times it has been handed to the chip to run, since boot or
+/// the last call to `clear_counts`. Setting a task that is already pending
+/// counts as being set again, so it can be set more often than it runs.
+pub fn task_counts(task: usize) -> (usize, usize) {
+    if task >= 32 {
+        return (0, 0);
+    }
+    (SET_COUNTS.get(task), RUN_COUNTS.get(task))
+}
+
+/// Reset the counts `task_counts` returns to zero.
+pub fn clear_counts() {
+    SET_COUNTS.clear();
+    RUN_COUNTS.clear();
+}
+
 /// Represents a way to generate an asynchronous call without a hardware
 /// interrupt. Supports up to 32 possible deferrable tasks.
 pub struct DeferredCall<T>(T);
@@ -66,7 +121,9 @@ impl<T: Into<usize> + TryFrom<usize> + Copy> DeferredCall<T> {
 
     /// Set the `DeferredCall` as pending
     pub fn set(&self) {
-        DEFERRED_CALL.fetch_or_relaxed(1 << self.0.into() as usize);
+        let task = self.0.into() as usize;
+        SET_COUNTS.increment(task);
+        DEFERRED_CALL.fetch_or_relaxed(1 << task);
     }
 
     /// Gets and clears the next pending `DeferredCall`
@@ -78,6 +135,7 @@ impl<T: Into<usize> + TryFrom<usize> + Copy> DeferredCall<T> {
             let bit = val.trailing_zeros() as usize;
             let new_val = val & !(1 << bit);
             DEFERRED_CALL.store_relaxed(new_val);
+            RUN_COUNTS.increment(bit);
             bit.try_into().ok()
         }
     }
@@ -46,7 +46,7 @@ use core::slice;
 use core::str;
 
 use common::cells::NumericCellExt;
-use common::cells::{MapCell, TakeCell};
+use common::cells::{MapCell, OptionalCell, TakeCell};
 use hil;
 use process::ProcessType;
 
@@ -181,13 +181,49 @@ pub unsafe fn assign_gpios(
     DEBUG_GPIOS.2 = gpio2;
 }
 
-/// In-kernel gpio debugging, accepts any GPIO HIL method
+/// How many debug GPIOs there are.
+pub const DEBUG_GPIO_COUNT: usize = 3;
+
+/// Whether `debug_gpio!` drives each debug GPIO, so that traces can be turned
+/// on and off at runtime. All of them are on until changed.
+pub static mut DEBUG_GPIOS_ENABLED: [bool; DEBUG_GPIO_COUNT] = [true; DEBUG_GPIO_COUNT];
+
+/// Whether the board assigned a pin to debug GPIO `n`.
+pub fn debug_gpio_assigned(n: usize) -> bool {
+    unsafe {
+        match n {
+            0 => DEBUG_GPIOS.0.is_some(),
+            1 => DEBUG_GPIOS.1.is_some(),
+            2 => DEBUG_GPIOS.2.is_some(),
+            _ => false,
+        }
+    }
+}
+
+/// Whether `debug_gpio!` drives debug GPIO `n`.
+pub fn debug_gpio_enabled(n: usize) -> bool {
+    unsafe { n < DEBUG_GPIO_COUNT && DEBUG_GPIOS_ENABLED[n] }
+}
+
+/// Turn driving debug GPIO `n` with `debug_gpio!` on or off.
+pub fn set_debug_gpio_enabled(n: usize, enabled: bool) {
+    if n < DEBUG_GPIO_COUNT {
+        unsafe {
+            DEBUG_GPIOS_ENABLED[n] = enabled;
+        }
+    }
+}
+
+/// In-kernel gpio debugging, accepts any GPIO HIL method. Does nothing while
+/// the GPIO is turned off with `set_debug_gpio_enabled`.
 #[macro_export]
 macro_rules! debug_gpio {
     ($i:tt, $method:ident) => {{
         #[allow(unused_unsafe)]
         unsafe {
-            $crate::debug::DEBUG_GPIOS.$i.map(|g| g.$method());
+            if $crate::debug::DEBUG_GPIOS_ENABLED[$i] {
+                $crate::debug::DEBUG_GPIOS.$i.map(|g| g.$method());
+            }
         }
     }};
 }
@@ -216,6 +252,28 @@ pub struct DebugWriter {
     active_len: Cell<usize>,
     // Number of debug!() calls.
     count: Cell<usize>,
+    // Optional client to notify when the internal buffer has been drained.
+    client: OptionalCell<&'static DebugWriterClient>,
+    // Whether new output is kept in the internal buffer rather than
+    // transmitted.
+    held: Cell<bool>,
+    // Optional copy of the most recent debug output, kept after it has been
+    // transmitted. Byte `n` of all output written since the log was set is
+    // at `n % log.len()`.
+    log: TakeCell<'static, [u8]>,
+    // Bytes written to the log since it was set.
+    log_written: Cell<usize>,
+    // The first byte that has not been cleared.
+    log_start: Cell<usize>,
+}
+
+/// Interface for capsules that share a UART with the debug writer and need to
+/// know when kernel debug output has finished transmitting, for example to
+/// redraw a prompt that the debug output may have landed in the middle of.
+pub trait DebugWriterClient {
+    /// Called when everything in the debug writer's internal buffer has been
+    /// transmitted.
+    fn write_drained(&self);
 }
 
 /// Static variable that holds the kernel's reference to the debug tool. This is
@@ -224,6 +282,7 @@ static mut DEBUG_WRITER: Option<&'static mut DebugWriterWrapper> = None;
 
 pub static mut OUTPUT_BUF: [u8; 64] = [0; 64];
 pub static mut INTERNAL_BUF: [u8; 1024] = [0; 1024];
+pub static mut LOG_BUF: [u8; 512] = [0; 512];
 
 pub unsafe fn get_debug_writer() -> &'static mut DebugWriterWrapper {
     match ptr::read(&DEBUG_WRITER) {
@@ -237,6 +296,86 @@ pub unsafe fn set_debug_writer_wrapper(debug_writer: &'static mut DebugWriterWra
     DEBUG_WRITER = Some(debug_writer);
 }
 
+/// Function used by board main.rs to register a client that is notified when
+/// the current debug writer drains. Must be called after
+/// `set_debug_writer_wrapper`.
+pub unsafe fn set_debug_writer_client(client: &'static DebugWriterClient) {
+    get_debug_writer().set_client(client);
+}
+
+/// Function used by board main.rs to keep the most recent debug output in
+/// `buffer`, for example `LOG_BUF`, so that it can be read back after it has
+/// been transmitted. Must be called after `set_debug_writer_wrapper`.
+pub unsafe fn set_debug_log_buffer(buffer: &'static mut [u8]) {
+    get_debug_writer()
+        .dw
+        .map(move |dw| dw.set_log_buffer(buffer));
+}
+
+/// The positions of the oldest byte of debug output still in the log and of
+/// the next byte to be written, counted from when the log was set. Returns
+/// `(0, 0)` if there is no log.
+pub fn debug_log_range() -> (usize, usize) {
+    unsafe {
+        match ptr::read(&DEBUG_WRITER) {
+            Some(x) => x.dw.map_or((0, 0), |dw| dw.log_range()),
+            None => (0, 0),
+        }
+    }
+}
+
+/// Copy debug output from the log into `buf`, starting at `position` (see
+/// `debug_log_range`). Returns how many bytes were copied, which is 0 if
+/// `position` is no longer or not yet in the log.
+pub fn debug_log_read(position: usize, buf: &mut [u8]) -> usize {
+    unsafe {
+        match ptr::read(&DEBUG_WRITER) {
+            Some(x) => x.dw.map_or(0, |dw| dw.log_read(position, buf)),
+            None => 0,
+        }
+    }
+}
+
+/// Forget everything in the debug log so far.
+pub fn debug_log_clear() {
+    unsafe {
+        match ptr::read(&DEBUG_WRITER) {
+            Some(x) => {
+                x.dw.map(|dw| dw.log_start.set(dw.log_written.get()));
+            }
+            None => {}
+        }
+    }
+}
+
+/// Keep debug output in the debug writer's buffer instead of transmitting
+/// it, or transmit what has been kept and go back to transmitting output
+/// as it is written. Output is only kept while it fills less than half of
+/// the buffer; past that the writer stops holding it, so that it cannot run
+/// out of room.
+pub fn set_debug_output_held(held: bool) {
+    unsafe {
+        match ptr::read(&DEBUG_WRITER) {
+            Some(x) => {
+                x.dw.map(|dw| dw.set_held(held));
+            }
+            None => {}
+        }
+    }
+}
+
+/// Returns true if the debug writer has output that has not been transmitted
+/// yet, not counting output it is holding. Returns false if no debug writer
+/// has been set.
+pub fn debug_writer_busy() -> bool {
+    unsafe {
+        match ptr::read(&DEBUG_WRITER) {
+            Some(x) => x.is_busy(),
+            None => false,
+        }
+    }
+}
+
 impl DebugWriterWrapper {
     pub fn new(dw: &'static DebugWriter) -> DebugWriterWrapper {
         DebugWriterWrapper {
@@ -259,6 +398,70 @@ impl DebugWriter {
             tail: Cell::new(0),       // one past last valid index (wraps to 0)
             active_len: Cell::new(0), // how big is the current transaction?
             count: Cell::new(0),      // how many debug! calls
+            client: OptionalCell::empty(),
+            held: Cell::new(false),
+            log: TakeCell::empty(),
+            log_written: Cell::new(0),
+            log_start: Cell::new(0),
+        }
+    }
+
+    fn set_log_buffer(&self, buffer: &'static mut [u8]) {
+        self.log.replace(buffer);
+        self.log_written.set(0);
+        self.log_start.set(0);
+    }
+
+    fn log_bytes(&self, bytes: &[u8]) {
+        self.log.map(|log| {
+            if log.is_empty() {
+                return;
+            }
+            for &byte in bytes {
+                let written = self.log_written.get();
+                log[written % log.len()] = byte;
+                self.log_written.set(written.wrapping_add(1));
+            }
+        });
+    }
+
+    fn log_range(&self) -> (usize, usize) {
+        let written = self.log_written.get();
+        let kept = self.log.map_or(0, |log| log.len());
+        (
+            cmp::max(self.log_start.get(), written.saturating_sub(kept)),
+            written,
+        )
+    }
+
+    fn log_read(&self, position: usize, buf: &mut [u8]) -> usize {
+        let (oldest, next) = self.log_range();
+        if position < oldest || position >= next {
+            return 0;
+        }
+        let len = cmp::min(buf.len(), next - position);
+        self.log.map(|log| {
+            for (i, byte) in buf[..len].iter_mut().enumerate() {
+                *byte = log[(position + i) % log.len()];
+            }
+        });
+        len
+    }
+
+    pub fn set_client(&self, client: &'static DebugWriterClient) {
+        self.client.set(client);
+    }
+
+    /// Returns true if there is debug output that has not been transmitted
+    /// yet, not counting output being held.
+    pub fn is_busy(&self) -> bool {
+        self.active_len.get() != 0 || (self.head.get() != self.tail.get() && !self.held.get())
+    }
+
+    fn set_held(&self, held: bool) {
+        self.held.set(held);
+        if !held && self.active_len.get() == 0 && self.head.get() != self.tail.get() {
+            self.publish_str();
         }
     }
 
@@ -286,6 +489,10 @@ impl DebugWriter {
     /// Write as many of the bytes from the internal_buffer to the output
     /// mechanism as possible.
     fn publish_str(&self) {
+        if self.held.get() {
+            // Published once the output is no longer held.
+            return;
+        }
         // Can only publish if we have the output_buffer. If we don't that is
         // fine, we will do it when the transmit done callback happens.
         self.output_buffer.take().map(|out_buffer| {
@@ -367,6 +574,7 @@ impl hil::uart::Client for DebugWriter {
             // to maximize the buffer length available before fragmentation
             self.head.set(0);
             self.tail.set(0);
+            self.client.map(|client| client.write_drained());
         } else {
             // Buffer not empty, go around again
             self.tail.set(tail);
@@ -404,6 +612,14 @@ impl DebugWriterWrapper {
     fn extract(&self) -> Option<(usize, usize, &mut [u8])> {
         self.dw.map_or(None, |dw| dw.extract())
     }
+
+    fn set_client(&self, client: &'static DebugWriterClient) {
+        self.dw.map(|dw| dw.set_client(client));
+    }
+
+    fn is_busy(&self) -> bool {
+        self.dw.map_or(false, |dw| dw.is_busy())
+    }
 }
 
 impl Write for DebugWriterWrapper {
@@ -422,6 +638,7 @@ impl Write for DebugWriterWrapper {
         //  -> there's no "full/empty" bit, so the effective buffer size is -1
 
         self.dw.map(|dw| {
+            dw.log_bytes(s.as_bytes());
             let mut head = dw.head.get();
             let tail = dw.tail.get();
             let len = dw.internal_buffer.map_or(0, |buffer| buffer.len());
@@ -495,6 +712,17 @@ impl Write for DebugWriterWrapper {
             }
 
             dw.head.set(head);
+
+            let used = if head >= tail {
+                head - tail
+            } else {
+                len - tail + head
+            };
+            if dw.held.get() && used > len / 2 {
+                // Stop holding output before the buffer fills up; the caller
+                // publishes it.
+                dw.held.set(false);
+            }
         });
 
         Ok(())
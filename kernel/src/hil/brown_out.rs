@@ -0,0 +1,26 @@
+//! Interface for the brown-out detector, which notices when the chip's supply
+//! voltage drops below a threshold.
+
+use returncode::ReturnCode;
+
+/// A brown-out detector. Thresholds are in the chip's own units, since how
+/// they map to voltages depends on the chip and its calibration.
+pub trait BrownOutDetector {
+    /// The lowest and highest thresholds `set_threshold` accepts.
+    fn threshold_range(&self) -> (u32, u32);
+
+    /// The threshold the detector is set to.
+    fn threshold(&self) -> u32;
+
+    /// Change the threshold. Returns `EINVAL` if it is outside
+    /// `threshold_range()`, or `EOFF` if the detector's configuration is
+    /// locked.
+    fn set_threshold(&self, threshold: u32) -> ReturnCode;
+
+    /// Whether the supply has dropped below the threshold since the chip
+    /// reset.
+    fn detected(&self) -> bool;
+
+    /// The detector's control register as the chip reports it.
+    fn raw(&self) -> u32;
+}
@@ -0,0 +1,20 @@
+//! Interface for changing the frequency the CPU runs at.
+
+use returncode::ReturnCode;
+
+/// A CPU clock that can be switched between a fixed set of frequencies.
+///
+/// Peripherals that divide down the CPU clock, such as UARTs, keep the
+/// divider they were configured with, so they must be configured again after
+/// the frequency changes.
+pub trait ClockScaling {
+    /// The frequency the CPU runs at now, in Hz.
+    fn frequency(&self) -> u32;
+
+    /// The frequencies, in Hz, that `set_frequency` accepts.
+    fn frequencies(&self) -> &'static [u32];
+
+    /// Switch the CPU to run at `frequency` Hz. Returns `EINVAL` if it is not
+    /// one of `frequencies`.
+    fn set_frequency(&self, frequency: u32) -> ReturnCode;
+}
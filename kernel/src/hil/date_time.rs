@@ -0,0 +1,42 @@
+//! Interface for real-time clocks that keep the calendar date and time of day.
+
+use returncode::ReturnCode;
+
+/// A calendar date and time of day.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DateTimeValues {
+    pub year: u16,
+    /// 1-12.
+    pub month: u8,
+    /// 1-31.
+    pub day: u8,
+    /// 0-23.
+    pub hour: u8,
+    /// 0-59.
+    pub minute: u8,
+    /// 0-59.
+    pub second: u8,
+}
+
+/// A real-time clock. Reading and setting it both finish with a call to the
+/// client, since many clocks sit on a bus.
+pub trait DateTime {
+    fn set_client(&self, client: &'static DateTimeClient);
+
+    /// Start reading the date and time. Returns `SUCCESS` if
+    /// `get_date_time_done` will be called.
+    fn get_date_time(&self) -> ReturnCode;
+
+    /// Start setting the date and time to `date_time`. Returns `SUCCESS` if
+    /// `set_date_time_done` will be called.
+    fn set_date_time(&self, date_time: DateTimeValues) -> ReturnCode;
+}
+
+/// Client for the results of reading and setting a real-time clock.
+pub trait DateTimeClient {
+    /// Called when a read started by `get_date_time` has completed.
+    fn get_date_time_done(&self, result: Result<DateTimeValues, ReturnCode>);
+
+    /// Called when setting the clock with `set_date_time` has completed.
+    fn set_date_time_done(&self, result: ReturnCode);
+}
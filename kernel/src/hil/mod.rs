@@ -3,8 +3,11 @@
 pub mod adc;
 pub mod analog_comparator;
 pub mod ble_advertising;
+pub mod brown_out;
+pub mod clock;
 pub mod crc;
 pub mod dac;
+pub mod date_time;
 pub mod entropy;
 pub mod flash;
 pub mod gpio;
@@ -13,6 +16,7 @@ pub mod i2c;
 pub mod led;
 pub mod nonvolatile_storage;
 pub mod radio;
+pub mod reset;
 pub mod rng;
 pub mod sensors;
 pub mod spi;
@@ -0,0 +1,35 @@
+//! Interface for finding out why the chip last reset.
+
+use returncode::ReturnCode;
+
+/// Why the chip last reset.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResetReason {
+    /// Power was applied.
+    PowerOn,
+    /// The supply voltage dropped too low.
+    BrownOut,
+    /// The reset pin was pulled low.
+    PinReset,
+    /// The watchdog timer expired.
+    Watchdog,
+    /// Software, or a debugger, asked for a reset.
+    SoftReset,
+    /// The chip woke from backup mode.
+    Backup,
+    /// The chip reported a cause this interface does not know about.
+    Unknown,
+}
+
+pub trait ResetCause {
+    /// The reset cause register as the chip reports it.
+    fn raw(&self) -> u32;
+
+    /// Why the chip last reset. If the chip reports several causes, the
+    /// most specific one.
+    fn reason(&self) -> ResetReason;
+
+    /// Clear the latched reset causes. Returns `ENOSUPPORT` on chips that
+    /// only update them at the next reset.
+    fn clear(&self) -> ReturnCode;
+}
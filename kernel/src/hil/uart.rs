@@ -35,6 +35,9 @@ pub enum Error {
     /// Overrun error during receive
     OverrunError,
 
+    /// Break condition (the line held low) detected during receive
+    BreakError,
+
     /// Repeat call of transmit or receive before initial command complete
     RepeatCallError,
 
@@ -101,6 +104,26 @@ pub trait UARTReceiveAdvanced: UART {
     fn receive_automatic(&self, rx_buffer: &'static mut [u8], interbyte_timeout: u8);
 }
 
+/// What a UART is doing, as `UARTStatus::status` reports it.
+#[derive(Copy, Clone, Debug)]
+pub struct UARTState {
+    /// A transmission is in progress.
+    pub transmitting: bool,
+    /// A receive is in progress.
+    pub receiving: bool,
+    /// How many bytes the transmission in progress was asked to send.
+    pub tx_len: usize,
+    /// How many bytes the receive in progress was asked for.
+    pub rx_len: usize,
+}
+
+/// Reports what a UART is doing, for debugging tools such as the process
+/// console's `uartstat`.
+pub trait UARTStatus: UART {
+    /// The transfers the UART has in progress.
+    fn status(&self) -> UARTState;
+}
+
 /// Implement Client to receive callbacks from UART.
 pub trait Client {
     /// UART transmit complete.
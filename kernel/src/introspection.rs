@@ -11,12 +11,36 @@
 //! correct capabilities to can use it.
 
 use core::cell::Cell;
+use core::ptr;
 
 use callback::AppId;
 use capabilities::ProcessManagementCapability;
 use common::cells::NumericCellExt;
 use process;
+use returncode::ReturnCode;
 use sched::Kernel;
+use syscall::SyscallRecord;
+
+/// A peripheral whose registers debugging tools may read, as registered by
+/// the board with `Kernel::set_peripherals`.
+pub struct Peripheral {
+    pub name: &'static str,
+    pub base: usize,
+    /// How many bytes of registers from `base` can be read without side
+    /// effects.
+    pub size: usize,
+    /// The bitfields of its registers, for tools to decode.
+    pub fields: &'static [RegisterField],
+}
+
+/// A bitfield of a peripheral register.
+pub struct RegisterField {
+    /// The register's offset from the peripheral's base address.
+    pub offset: usize,
+    pub name: &'static str,
+    pub shift: u32,
+    pub width: u32,
+}
 
 /// This struct provides the inspection functions.
 pub struct KernelInfo {
@@ -131,4 +155,173 @@ impl KernelInfo {
         });
         count.get()
     }
+
+    /// Returns the ticks the kernel has spent sleeping and the total ticks
+    /// since idle accounting started or was last cleared, or `None` if the
+    /// board does not measure idle time.
+    pub fn idle_time(&self, _capability: &ProcessManagementCapability) -> Option<(u32, u32)> {
+        self.kernel.idle_time()
+    }
+
+    /// Whether the kernel counts the processor cycles each process runs for,
+    /// as `ProcessType::debug_cycle_count` reports them.
+    pub fn counts_cycles(&self, _capability: &ProcessManagementCapability) -> bool {
+        self.kernel.counts_cycles()
+    }
+
+    /// Returns the index of the process the kernel most recently switched
+    /// to, or `None` if no process has run yet. The scheduler runs processes
+    /// round-robin in index order, starting again from the first index on
+    /// each pass.
+    pub fn last_scheduled(&self, _capability: &ProcessManagementCapability) -> Option<usize> {
+        self.kernel.last_scheduled()
+    }
+
+    /// Restart idle accounting from now.
+    pub fn clear_idle_time(&self, _capability: &ProcessManagementCapability) {
+        self.kernel.clear_idle_time();
+    }
+
+    /// Returns how many grants the board has created.
+    pub fn number_grants(&self, _capability: &ProcessManagementCapability) -> usize {
+        self.kernel.grant_count()
+    }
+
+    /// Returns whether the grant with index `grant_num` has allocated memory
+    /// for this app. A grant's memory is allocated the first time a capsule
+    /// enters it for the app, and stays allocated until the app restarts.
+    pub fn app_grant_allocated(
+        &self,
+        app: AppId,
+        grant_num: usize,
+        _capability: &ProcessManagementCapability,
+    ) -> bool {
+        if grant_num >= self.kernel.grant_count() {
+            return false;
+        }
+        self.kernel
+            .process_map_or(false, app.idx(), |process| unsafe {
+                !(*process.grant_ptr(grant_num)).is_null()
+            })
+    }
+
+    /// Returns the address of the memory the grant with index `grant_num`
+    /// has allocated for this app, if it has.
+    pub fn app_grant_address(
+        &self,
+        app: AppId,
+        grant_num: usize,
+        _capability: &ProcessManagementCapability,
+    ) -> Option<usize> {
+        if grant_num >= self.kernel.grant_count() {
+            return None;
+        }
+        self.kernel
+            .process_map_or(None, app.idx(), |process| unsafe {
+                let address = *process.grant_ptr(grant_num);
+                if address.is_null() {
+                    None
+                } else {
+                    Some(address as usize)
+                }
+            })
+    }
+
+    /// Returns the numbers of the oldest system call still in the syscall
+    /// trace and of the next one to be recorded. Both are 0 if the board did
+    /// not give the kernel a trace buffer.
+    pub fn syscall_trace_range(&self, _capability: &ProcessManagementCapability) -> (usize, usize) {
+        self.kernel.syscall_trace_range()
+    }
+
+    /// Returns the system call with number `n` in the syscall trace, if it
+    /// has not been overwritten yet.
+    pub fn syscall_trace_entry(
+        &self,
+        n: usize,
+        _capability: &ProcessManagementCapability,
+    ) -> Option<SyscallRecord> {
+        self.kernel.syscall_trace_entry(n)
+    }
+
+    /// Returns true if every byte of `[addr, addr + len)` lies within the
+    /// memory a loaded process can access, which is its flash and its RAM
+    /// below the app break, or within one of the regions the board
+    /// registered with `Kernel::set_readable_regions`. The grant region the
+    /// kernel keeps at the top of a process's RAM is not readable.
+    pub fn memory_readable(
+        &self,
+        addr: usize,
+        len: usize,
+        _capability: &ProcessManagementCapability,
+    ) -> bool {
+        let end = match addr.checked_add(len) {
+            Some(end) => end,
+            None => return false,
+        };
+        let inside = |start: usize, stop: usize| addr >= start && end <= stop;
+        let found = Cell::new(
+            self.kernel
+                .readable_regions()
+                .iter()
+                .any(|&(start, size)| inside(start, start.saturating_add(size))),
+        );
+        self.kernel.process_each(|process| {
+            if inside(
+                process.mem_start() as usize,
+                process.app_memory_break() as usize,
+            ) || inside(process.flash_start() as usize, process.flash_end() as usize)
+            {
+                found.set(true);
+            }
+        });
+        found.get()
+    }
+
+    /// Copies memory starting at `addr` into `buf`. Returns `EINVAL` without
+    /// reading anything if the range is not known to be readable (see
+    /// `memory_readable`).
+    pub fn read_memory(
+        &self,
+        addr: usize,
+        buf: &mut [u8],
+        capability: &ProcessManagementCapability,
+    ) -> ReturnCode {
+        if !self.memory_readable(addr, buf.len(), capability) {
+            return ReturnCode::EINVAL;
+        }
+        for (i, byte) in buf.iter_mut().enumerate() {
+            // The range was checked to lie within mapped memory above.
+            *byte = unsafe { ptr::read_volatile((addr + i) as *const u8) };
+        }
+        ReturnCode::SUCCESS
+    }
+
+    /// Returns the peripherals the board registered with
+    /// `Kernel::set_peripherals`.
+    pub fn peripherals(&self, _capability: &ProcessManagementCapability) -> &'static [Peripheral] {
+        self.kernel.peripherals()
+    }
+
+    /// Read the 32-bit register at byte `offset` of the peripheral at index
+    /// `peripheral` of `peripherals`. Returns `EINVAL` if there is no such
+    /// peripheral, or `offset` is not word aligned or not within its
+    /// registers.
+    pub fn read_register(
+        &self,
+        peripheral: usize,
+        offset: usize,
+        _capability: &ProcessManagementCapability,
+    ) -> Result<u32, ReturnCode> {
+        let peripheral = self
+            .kernel
+            .peripherals()
+            .get(peripheral)
+            .ok_or(ReturnCode::EINVAL)?;
+        if offset % 4 != 0 || offset.saturating_add(4) > peripheral.size {
+            return Err(ReturnCode::EINVAL);
+        }
+        // The board vouched for the peripheral's registers being readable.
+        Ok(unsafe { ptr::read_volatile((peripheral.base + offset) as *const u32) })
+    }
 }
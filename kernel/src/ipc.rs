@@ -7,7 +7,7 @@
 pub const DRIVER_NUM: usize = 0x00010000;
 
 use callback::{AppId, Callback};
-use capabilities::MemoryAllocationCapability;
+use capabilities::{MemoryAllocationCapability, ProcessManagementCapability};
 use driver::Driver;
 use grant::Grant;
 use mem::{AppSlice, Shared};
@@ -42,6 +42,31 @@ impl IPC {
         }
     }
 
+    /// Whether the process `appid` has registered as an IPC service.
+    pub fn is_service<C: ProcessManagementCapability>(&self, appid: AppId, _c: &C) -> bool {
+        self.data
+            .enter(appid, |data, _| data.callback.is_some())
+            .unwrap_or(false)
+    }
+
+    /// Calls `f` with the index of each process that has registered a client
+    /// callback or shared memory with the service `service`.
+    pub fn each_client<C: ProcessManagementCapability>(
+        &self,
+        service: AppId,
+        f: &Fn(usize),
+        _c: &C,
+    ) {
+        let index = service.idx();
+        self.data.each(|data| {
+            let callback = data.client_callbacks.get(index).map_or(false, |cb| cb.is_some());
+            let memory = data.shared_memory.get(index).map_or(false, |mem| mem.is_some());
+            if callback || memory {
+                f(data.appid().idx());
+            }
+        });
+    }
+
     pub unsafe fn schedule_callback(
         &self,
         appid: AppId,
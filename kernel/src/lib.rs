@@ -48,12 +48,15 @@ pub use platform::systick::SysTick;
 pub use platform::{mpu, Chip, Platform};
 pub use platform::{ClockInterface, NoClockControl, NO_CLOCK_CONTROL};
 pub use returncode::ReturnCode;
-pub use sched::Kernel;
+pub use sched::{CycleCounter, IdleClock, Kernel};
 
 // Export only select items from the process module. To remove the name conflict
 // this cannot be called `process`, so we use a shortened version. These
 // functions and types are used by board files to setup the platform and setup
 // processes.
 pub mod procs {
-    pub use process::{load_processes, FaultResponse, FunctionCall, Process, ProcessType};
+    pub use process::{
+        load_processes, FaultResponse, FunctionCall, IPCType, Process, ProcessType, State,
+        Task,
+    };
 }
@@ -92,6 +92,14 @@ pub trait ProcessType {
     /// `None`.
     fn dequeue_task(&self) -> Option<Task>;
 
+    /// Returns the `Task` `index` places from the front of the queue, without
+    /// removing it, or `None` if fewer `Task`s are queued.
+    fn peek_task(&self, index: usize) -> Option<Task>;
+
+    /// Drop every `Task` queued for this process without running it, and
+    /// return how many were dropped.
+    fn clear_tasks(&self) -> usize;
+
     /// Returns the current state the process is in. Common states are "running"
     /// or "yielded".
     fn get_state(&self) -> State;
@@ -109,6 +117,21 @@ pub trait ProcessType {
     /// `FaultResponse` for this process to occur.
     fn set_fault_state(&self);
 
+    /// Returns how the kernel responds when this process faults.
+    fn get_fault_response(&self) -> FaultResponse;
+
+    /// Change how the kernel responds when this process faults.
+    fn set_fault_response(&self, fault_response: FaultResponse);
+
+    /// Start the process over again from its entry point, dropping its
+    /// pending tasks and resetting its memory and grant region.
+    fn restart(&self);
+
+    /// The call that starts the process: its entry point and the arguments
+    /// the kernel passes to it, which are the start of its code in flash,
+    /// the start and length of its memory and its initial memory break.
+    fn init_call(&self) -> FunctionCall;
+
     /// Get the name of the process. Used for IPC.
     fn get_process_name(&self) -> &'static str;
 
@@ -138,6 +161,15 @@ pub trait ProcessType {
     /// The lowest address of the grant region for the process.
     fn kernel_memory_break(&self) -> *const u8;
 
+    /// The lowest address of what the kernel keeps for the process itself at
+    /// the top of the grant region: the grant pointers, the task queue and
+    /// the process's own state. Grants are allocated below it.
+    fn kernel_structures_start(&self) -> *const u8;
+
+    /// The first address after the end of the memory the process can access,
+    /// i.e. the current end of its heap.
+    fn app_memory_break(&self) -> *const u8;
+
     /// How many writeable flash regions defined in the TBF header for this
     /// process.
     fn number_writeable_flash_regions(&self) -> usize;
@@ -234,9 +266,28 @@ pub trait ProcessType {
     /// Returns how many times this process has been restarted.
     fn debug_restart_count(&self) -> usize;
 
+    /// Starts counting restarts of this process from zero again.
+    fn debug_clear_restart_count(&self);
+
     /// Returns how many times this process has exceeded its timeslice.
     fn debug_timeslice_expiration_count(&self) -> usize;
 
+    /// Returns how many processor cycles this process has run for since
+    /// boot, if the kernel counts them.
+    fn debug_cycle_count(&self) -> u64;
+
+    /// Add to the processor cycles this process has run for.
+    fn debug_add_cycles(&self, cycles: u32);
+
+    /// Returns the most memory, in bytes, this process may use for its own
+    /// memory and grants together before grant allocations fail, if limited.
+    fn debug_memory_limit(&self) -> Option<usize>;
+
+    /// Limit the memory this process may use for grants allocated from now
+    /// on, for testing how it copes with running out, or remove the limit
+    /// with `None`. Memory already allocated is not affected.
+    fn debug_set_memory_limit(&self, limit: Option<usize>);
+
     fn debug_timeslice_expired(&self);
 }
 
@@ -271,8 +322,12 @@ pub enum State {
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum FaultResponse {
+    /// Panic the kernel, printing the state of the faulted process.
     Panic,
+    /// Reset the process and start it over again.
     Restart,
+    /// Leave the process in the fault state without running it again.
+    Stop,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -335,6 +390,14 @@ struct ProcessDebug {
     /// How many times this process has been paused because it exceeded its
     /// timeslice.
     timeslice_expiration_count: usize,
+
+    /// How many processor cycles the process has run for since boot. Unlike
+    /// the other counts, this is kept when the process restarts.
+    cycle_count: u64,
+
+    /// How much memory the process may use before grant allocations fail,
+    /// if it is limited. Kept when the process restarts.
+    memory_limit: Option<usize>,
 }
 
 pub struct Process<'a, C: 'static + Chip> {
@@ -414,7 +477,7 @@ pub struct Process<'a, C: 'static + Chip> {
     state: Cell<State>,
 
     /// How to deal with Faults occurring in the process
-    fault_response: FaultResponse,
+    fault_response: Cell<FaultResponse>,
 
     /// Configuration data for the MPU
     mpu_config: MapCell<<<C as Chip>::MPU as MPU>::MpuConfig>,
@@ -490,74 +553,91 @@ impl<C: Chip> ProcessType for Process<'a, C> {
     fn set_fault_state(&self) {
         self.state.set(State::Fault);
 
-        match self.fault_response {
+        match self.fault_response.get() {
             FaultResponse::Panic => {
                 // process faulted. Panic and print status
                 panic!("Process {} had a fault", self.process_name);
             }
+            FaultResponse::Stop => {
+                // The process will not run again, so drop any work it had
+                // scheduled.
+                self.clear_tasks();
+            }
             FaultResponse::Restart => {
-                // Remove the tasks that were scheduled for the app from the
-                // amount of work queue.
-                let tasks_len = self.tasks.map_or(0, |tasks| tasks.len());
-                for _ in 0..tasks_len {
-                    self.kernel.decrement_work();
-                }
+                self.restart();
+            }
+        }
+    }
 
-                // And remove those tasks
-                self.tasks.map(|tasks| {
-                    tasks.empty();
-                });
+    fn restart(&self) {
+        // Remove the tasks that were scheduled for the app from the
+        // amount of work queue.
+        let tasks_len = self.tasks.map_or(0, |tasks| tasks.len());
+        for _ in 0..tasks_len {
+            self.kernel.decrement_work();
+        }
 
-                // Update debug information
-                self.debug.map(|debug| {
-                    // Mark that we restarted this process.
-                    debug.restart_count += 1;
+        // And remove those tasks
+        self.tasks.map(|tasks| {
+            tasks.empty();
+        });
 
-                    // Reset some state for the process.
-                    debug.syscall_count = 0;
-                    debug.last_syscall = None;
-                    debug.dropped_callback_count = 0;
-                });
+        // Update debug information
+        self.debug.map(|debug| {
+            // Mark that we restarted this process.
+            debug.restart_count += 1;
 
-                // We are going to start this process over again, so need
-                // the init_fn location.
-                let app_flash_address = self.flash_start();
-                let init_fn = unsafe {
-                    app_flash_address.offset(self.header.get_init_function_offset() as isize)
-                        as usize
-                };
-                self.state.set(State::Yielded);
-
-                // Need to reset the grant region.
-                unsafe {
-                    self.grant_ptrs_reset();
-                }
-                self.kernel_memory_break
-                    .set(self.original_kernel_memory_break);
-
-                // Reset other memory pointers.
-                self.app_break.set(self.original_app_break);
-                self.current_stack_pointer.set(self.original_stack_pointer);
-
-                // And queue up this app to be restarted.
-                let flash_protected_size = self.header.get_protected_size() as usize;
-                let flash_app_start = app_flash_address as usize + flash_protected_size;
-
-                self.tasks.map(|tasks| {
-                    tasks.enqueue(Task::FunctionCall(FunctionCall {
-                        pc: init_fn,
-                        argument0: flash_app_start,
-                        argument1: self.memory.as_ptr() as usize,
-                        argument2: self.memory.len() as usize,
-                        argument3: self.app_break.get() as usize,
-                    }));
-                });
+            // Reset some state for the process.
+            debug.syscall_count = 0;
+            debug.last_syscall = None;
+            debug.dropped_callback_count = 0;
+        });
 
-                self.kernel.increment_work();
-            }
+        self.state.set(State::Yielded);
+
+        // Need to reset the grant region.
+        unsafe {
+            self.grant_ptrs_reset();
+        }
+        self.kernel_memory_break
+            .set(self.original_kernel_memory_break);
+
+        // Reset other memory pointers.
+        self.app_break.set(self.original_app_break);
+        self.current_stack_pointer.set(self.original_stack_pointer);
+
+        // And queue up this app to be restarted.
+        let init_call = self.init_call();
+        self.tasks.map(|tasks| {
+            tasks.enqueue(Task::FunctionCall(init_call));
+        });
+
+        self.kernel.increment_work();
+    }
+
+    fn init_call(&self) -> FunctionCall {
+        let app_flash_address = self.flash_start();
+        let init_fn = unsafe {
+            app_flash_address.offset(self.header.get_init_function_offset() as isize)
+                as usize
+        };
+        FunctionCall {
+            pc: init_fn,
+            argument0: self.flash_non_protected_start() as usize,
+            argument1: self.memory.as_ptr() as usize,
+            argument2: self.memory.len() as usize,
+            argument3: self.original_app_break as usize,
         }
     }
 
+    fn get_fault_response(&self) -> FaultResponse {
+        self.fault_response.get()
+    }
+
+    fn set_fault_response(&self, fault_response: FaultResponse) {
+        self.fault_response.set(fault_response);
+    }
+
     fn dequeue_task(&self) -> Option<Task> {
         self.tasks.map_or(None, |tasks| {
             tasks.dequeue().map(|cb| {
@@ -567,6 +647,34 @@ impl<C: Chip> ProcessType for Process<'a, C> {
         })
     }
 
+    fn peek_task(&self, index: usize) -> Option<Task> {
+        self.tasks.map_or(None, |tasks| {
+            // Cycle through the whole queue so it ends up in its original
+            // order.
+            let mut found = None;
+            for i in 0..tasks.len() {
+                tasks.dequeue().map(|task| {
+                    if i == index {
+                        found = Some(task);
+                    }
+                    tasks.enqueue(task);
+                });
+            }
+            found
+        })
+    }
+
+    fn clear_tasks(&self) -> usize {
+        let tasks_len = self.tasks.map_or(0, |tasks| tasks.len());
+        for _ in 0..tasks_len {
+            self.kernel.decrement_work();
+        }
+        self.tasks.map(|tasks| {
+            tasks.empty();
+        });
+        tasks_len
+    }
+
     fn mem_start(&self) -> *const u8 {
         self.memory.as_ptr()
     }
@@ -591,6 +699,14 @@ impl<C: Chip> ProcessType for Process<'a, C> {
         self.kernel_memory_break.get()
     }
 
+    fn kernel_structures_start(&self) -> *const u8 {
+        self.original_kernel_memory_break
+    }
+
+    fn app_memory_break(&self) -> *const u8 {
+        self.app_break.get()
+    }
+
     fn number_writeable_flash_regions(&self) -> usize {
         self.header.number_writeable_flash_regions()
     }
@@ -711,7 +827,13 @@ impl<C: Chip> ProcessType for Process<'a, C> {
     unsafe fn alloc(&self, size: usize) -> Option<&mut [u8]> {
         self.mpu_config.and_then(|mut config| {
             let new_break = self.kernel_memory_break.get().offset(-(size as isize));
-            if new_break < self.app_break.get() {
+            let used = (self.app_break.get() as usize - self.mem_start() as usize)
+                + (self.mem_end() as usize - new_break as usize);
+            let limit = self
+                .debug
+                .map_or(None, |debug| debug.memory_limit)
+                .unwrap_or(usize::max_value());
+            if new_break < self.app_break.get() || used > limit {
                 None
             } else if let Err(_) = self.chip.mpu().update_app_memory_region(
                 self.app_break.get(),
@@ -860,11 +982,32 @@ impl<C: Chip> ProcessType for Process<'a, C> {
         self.debug.map_or(0, |debug| debug.restart_count)
     }
 
+    fn debug_clear_restart_count(&self) {
+        self.debug.map(|debug| debug.restart_count = 0);
+    }
+
     fn debug_timeslice_expiration_count(&self) -> usize {
         self.debug
             .map_or(0, |debug| debug.timeslice_expiration_count)
     }
 
+    fn debug_cycle_count(&self) -> u64 {
+        self.debug.map_or(0, |debug| debug.cycle_count)
+    }
+
+    fn debug_add_cycles(&self, cycles: u32) {
+        self.debug
+            .map(|debug| debug.cycle_count += cycles as u64);
+    }
+
+    fn debug_memory_limit(&self) -> Option<usize> {
+        self.debug.map_or(None, |debug| debug.memory_limit)
+    }
+
+    fn debug_set_memory_limit(&self, limit: Option<usize>) {
+        self.debug.map(|debug| debug.memory_limit = limit);
+    }
+
     fn debug_timeslice_expired(&self) {
         self.debug
             .map(|debug| debug.timeslice_expiration_count += 1);
@@ -1153,7 +1296,7 @@ impl<C: 'static + Chip> Process<'a, C> {
 
             process.stored_state = Cell::new(Default::default());
             process.state = Cell::new(State::Yielded);
-            process.fault_response = fault_response;
+            process.fault_response = Cell::new(fault_response);
 
             process.mpu_config = MapCell::new(mpu_config);
             process.mpu_regions = [
@@ -1176,6 +1319,8 @@ impl<C: 'static + Chip> Process<'a, C> {
                 dropped_callback_count: 0,
                 restart_count: 0,
                 timeslice_expiration_count: 0,
+                cycle_count: 0,
+                memory_limit: None,
             });
 
             if (init_fn & 0x1) != 1 {
@@ -5,8 +5,10 @@ use core::ptr::NonNull;
 
 use callback::Callback;
 use capabilities;
-use common::cells::NumericCellExt;
+use common::cells::{NumericCellExt, OptionalCell, TakeCell};
 use grant::Grant;
+use hil;
+use introspection::Peripheral;
 use ipc;
 use memop;
 use platform::mpu::MPU;
@@ -14,13 +16,34 @@ use platform::systick::SysTick;
 use platform::{Chip, Platform};
 use process::{self, Task};
 use returncode::ReturnCode;
-use syscall::{ContextSwitchReason, Syscall};
+use syscall::{ContextSwitchReason, Syscall, SyscallRecord};
 
-/// The time a process is permitted to run before being pre-empted
+/// The time a process is permitted to run before being pre-empted, unless
+/// changed with `set_timeslice_us()`
 const KERNEL_TICK_DURATION_US: u32 = 10000;
 /// Skip re-scheduling a process if its quanta is nearly exhausted
 const MIN_QUANTA_THRESHOLD_US: u32 = 500;
 
+/// A free-running counter the kernel reads to measure how long it sleeps.
+/// Every `hil::time::Alarm` is one.
+pub trait IdleClock {
+    /// The current value of the counter.
+    fn now(&self) -> u32;
+}
+
+/// A free-running counter of processor cycles, such as the Cortex-M DWT cycle
+/// counter, the kernel reads to count the cycles each process runs for.
+pub trait CycleCounter {
+    /// The current value of the counter.
+    fn cycles(&self) -> u32;
+}
+
+impl<A: hil::time::Alarm> IdleClock for A {
+    fn now(&self) -> u32 {
+        hil::time::Alarm::now(self)
+    }
+}
+
 /// Main object for the kernel. Each board will need to create one.
 pub struct Kernel {
     /// How many "to-do" items exist at any given time. These include
@@ -37,6 +60,34 @@ pub struct Kernel {
     /// created and the data structures for grants have already been
     /// established.
     grants_finalized: Cell<bool>,
+    /// Counter used to measure time spent sleeping, if the board set one.
+    idle_clock: OptionalCell<&'static IdleClock>,
+    /// Counter used to count the cycles processes run for, if the board set
+    /// one.
+    cycle_counter: OptionalCell<&'static CycleCounter>,
+    /// The time a process is permitted to run before being pre-empted.
+    timeslice_us: Cell<u32>,
+    /// The slot of the process most recently switched to, if any has run.
+    last_scheduled: Cell<Option<usize>>,
+    /// Ticks of `idle_clock` spent sleeping since `idle_since`.
+    idle_ticks: Cell<u32>,
+    /// When the idle accounting started or was last cleared.
+    idle_since: Cell<u32>,
+    /// Whether processes run with the MPU enabled.
+    mpu_enforced: Cell<bool>,
+    /// Whether the scheduler is holding off running processes.
+    scheduler_paused: Cell<bool>,
+    /// The most recent system calls, if the board set a buffer for them. Call
+    /// number `n` is kept at index `n % len`.
+    syscall_trace: TakeCell<'static, [SyscallRecord]>,
+    /// How many system calls have been recorded in total.
+    syscall_trace_count: Cell<usize>,
+    /// Memory outside of processes, as (start address, length), that
+    /// debugging tools may read, as set by the board.
+    readable_regions: Cell<&'static [(usize, usize)]>,
+    /// Peripherals whose registers debugging tools may read, as set by the
+    /// board.
+    peripherals: Cell<&'static [Peripheral]>,
 }
 
 impl Kernel {
@@ -46,7 +97,217 @@ impl Kernel {
             processes: processes,
             grant_counter: Cell::new(0),
             grants_finalized: Cell::new(false),
+            idle_clock: OptionalCell::empty(),
+            cycle_counter: OptionalCell::empty(),
+            idle_ticks: Cell::new(0),
+            idle_since: Cell::new(0),
+            timeslice_us: Cell::new(KERNEL_TICK_DURATION_US),
+            last_scheduled: Cell::new(None),
+            mpu_enforced: Cell::new(true),
+            scheduler_paused: Cell::new(false),
+            syscall_trace: TakeCell::empty(),
+            syscall_trace_count: Cell::new(0),
+            readable_regions: Cell::new(&[]),
+            peripherals: Cell::new(&[]),
+        }
+    }
+
+    /// The time, in microseconds, a process may run before being pre-empted.
+    pub fn timeslice_us(&self) -> u32 {
+        self.timeslice_us.get()
+    }
+
+    /// Change how long a process may run before being pre-empted. The change
+    /// applies from the next time a process is scheduled. Returns `EINVAL` if
+    /// `timeslice_us` is too short to run a process at all.
+    ///
+    /// Only callers with the `ProcessManagementCapability` can call this
+    /// function, since it affects how every process is scheduled.
+    pub fn set_timeslice_us<C: capabilities::ProcessManagementCapability>(
+        &self,
+        timeslice_us: u32,
+        _c: &C,
+    ) -> ReturnCode {
+        if timeslice_us <= MIN_QUANTA_THRESHOLD_US {
+            return ReturnCode::EINVAL;
         }
+        self.timeslice_us.set(timeslice_us);
+        ReturnCode::SUCCESS
+    }
+
+    /// The slot of the process the kernel most recently switched to, or
+    /// `None` if no process has run yet. Processes are scheduled round-robin
+    /// in slot order, each pass starting again from the first slot.
+    crate fn last_scheduled(&self) -> Option<usize> {
+        self.last_scheduled.get()
+    }
+
+    /// Whether processes run with the MPU protecting memory.
+    pub fn mpu_enforced(&self) -> bool {
+        self.mpu_enforced.get()
+    }
+
+    /// Run processes with or without the MPU enabled, from the next time a
+    /// process is scheduled. Without it, processes can read and write any
+    /// memory, including the kernel's, so this is only for finding out
+    /// whether a fault is caused by the MPU configuration.
+    ///
+    /// Only callers with the `ProcessManagementCapability` can call this
+    /// function, since it removes the isolation between processes.
+    pub fn set_mpu_enforced<C: capabilities::ProcessManagementCapability>(
+        &self,
+        enforced: bool,
+        _c: &C,
+    ) {
+        self.mpu_enforced.set(enforced);
+    }
+
+    /// Whether the scheduler is holding off running processes.
+    pub fn scheduler_paused(&self) -> bool {
+        self.scheduler_paused.get()
+    }
+
+    /// Stop or restart running processes. While paused the kernel still
+    /// handles interrupts and runs capsules, but no process is switched to,
+    /// so every process stays exactly where it is.
+    ///
+    /// Only callers with the `ProcessManagementCapability` can call this
+    /// function, since it stops every process.
+    pub fn set_scheduler_paused<C: capabilities::ProcessManagementCapability>(
+        &self,
+        paused: bool,
+        _c: &C,
+    ) {
+        self.scheduler_paused.set(paused);
+    }
+
+    /// Measure how long the kernel sleeps with `clock`. The accounting is
+    /// only correct while less than a full period of the counter passes
+    /// between clearing it and reading it.
+    pub fn set_idle_clock(
+        &self,
+        clock: &'static IdleClock,
+        _capability: &capabilities::MainLoopCapability,
+    ) {
+        self.idle_clock.set(clock);
+        self.idle_ticks.set(0);
+        self.idle_since.set(clock.now());
+    }
+
+    /// Count the processor cycles each process runs for with `counter`.
+    pub fn set_cycle_counter(
+        &self,
+        counter: &'static CycleCounter,
+        _capability: &capabilities::MainLoopCapability,
+    ) {
+        self.cycle_counter.set(counter);
+    }
+
+    /// Whether the kernel counts the cycles each process runs for.
+    crate fn counts_cycles(&self) -> bool {
+        self.cycle_counter.is_some()
+    }
+
+    /// Ticks spent sleeping and total ticks since idle accounting started or
+    /// was last cleared, or `None` if the board did not set an idle clock.
+    crate fn idle_time(&self) -> Option<(u32, u32)> {
+        self.idle_clock.map(|clock| {
+            let elapsed = clock.now().wrapping_sub(self.idle_since.get());
+            (self.idle_ticks.get(), elapsed)
+        })
+    }
+
+    /// Record every system call processes make in `buffer`, a static buffer
+    /// of the board's, keeping the most recent ones once it is full. Each
+    /// call is timestamped with the idle clock, if one is set. Without a
+    /// buffer, the kernel does not trace system calls at all.
+    pub fn set_syscall_trace_buffer(
+        &self,
+        buffer: &'static mut [SyscallRecord],
+        _capability: &capabilities::MainLoopCapability,
+    ) {
+        self.syscall_trace.replace(buffer);
+        self.syscall_trace_count.set(0);
+    }
+
+    /// Let debugging tools read `regions` of memory, given as (start address,
+    /// length) pairs, in addition to process memory. The board must only
+    /// register memory that can be read without faulting or side effects,
+    /// such as kernel RAM.
+    pub fn set_readable_regions(
+        &self,
+        regions: &'static [(usize, usize)],
+        _capability: &capabilities::MainLoopCapability,
+    ) {
+        self.readable_regions.set(regions);
+    }
+
+    /// The regions the board registered with `set_readable_regions`.
+    crate fn readable_regions(&self) -> &'static [(usize, usize)] {
+        self.readable_regions.get()
+    }
+
+    /// Let debugging tools read the registers of `peripherals`. The board
+    /// must only list registers that reading has no effect on.
+    pub fn set_peripherals(
+        &self,
+        peripherals: &'static [Peripheral],
+        _capability: &capabilities::MainLoopCapability,
+    ) {
+        self.peripherals.set(peripherals);
+    }
+
+    /// The peripherals the board registered with `set_peripherals`.
+    crate fn peripherals(&self) -> &'static [Peripheral] {
+        self.peripherals.get()
+    }
+
+    /// The numbers of the oldest system call still in the trace and of the
+    /// next one to be recorded, counting from 0 since the trace started.
+    crate fn syscall_trace_range(&self) -> (usize, usize) {
+        let len = self.syscall_trace.map_or(0, |trace| trace.len());
+        let next = self.syscall_trace_count.get();
+        (next.saturating_sub(len), next)
+    }
+
+    /// The system call with number `n`, if it is still in the trace.
+    crate fn syscall_trace_entry(&self, n: usize) -> Option<SyscallRecord> {
+        let (oldest, next) = self.syscall_trace_range();
+        if n < oldest || n >= next {
+            return None;
+        }
+        self.syscall_trace
+            .map_or(None, |trace| Some(trace[n % trace.len()]))
+    }
+
+    fn trace_syscall(&self, process: usize, syscall: Syscall) {
+        if self.syscall_trace.is_none() {
+            return;
+        }
+        let time = self.idle_clock.map_or(0, |clock| clock.now());
+        let n = self.syscall_trace_count.get();
+        let recorded = self.syscall_trace.map_or(false, |trace| {
+            if trace.is_empty() {
+                return false;
+            }
+            trace[n % trace.len()] = SyscallRecord {
+                time: time,
+                process: process,
+                syscall: syscall,
+            };
+            true
+        });
+        if recorded {
+            self.syscall_trace_count.set(n.wrapping_add(1));
+        }
+    }
+
+    /// Restart idle accounting from now.
+    crate fn clear_idle_time(&self) {
+        self.idle_clock.map(|clock| {
+            self.idle_ticks.set(0);
+            self.idle_since.set(clock.now());
+        });
     }
 
     /// Something was scheduled for a process, so there is more work to do.
@@ -181,6 +442,12 @@ impl Kernel {
         self.grant_counter.get()
     }
 
+    /// Returns the number of grants that have been setup in the system,
+    /// without finalizing them.
+    crate fn grant_count(&self) -> usize {
+        self.grant_counter.get()
+    }
+
     /// Cause all apps to fault.
     ///
     /// This will call `set_fault_state()` on each app, causing the app to enter
@@ -211,18 +478,29 @@ impl Kernel {
             unsafe {
                 chip.service_pending_interrupts();
 
-                for p in self.processes.iter() {
-                    p.map(|process| {
-                        self.do_process(platform, chip, process, ipc);
-                    });
-                    if chip.has_pending_interrupts() {
-                        break;
+                if !self.scheduler_paused.get() {
+                    for p in self.processes.iter() {
+                        p.map(|process| {
+                            self.do_process(platform, chip, process, ipc);
+                        });
+                        if chip.has_pending_interrupts() {
+                            break;
+                        }
                     }
                 }
 
                 chip.atomic(|| {
-                    if !chip.has_pending_interrupts() && self.processes_blocked() {
+                    if !chip.has_pending_interrupts()
+                        && (self.processes_blocked() || self.scheduler_paused.get())
+                    {
+                        let start = self.idle_clock.map(|clock| clock.now());
                         chip.sleep();
+                        start.map(|start| {
+                            self.idle_clock.map(|clock| {
+                                let slept = clock.now().wrapping_sub(start);
+                                self.idle_ticks.set(self.idle_ticks.get().wrapping_add(slept));
+                            });
+                        });
                     }
                 });
             };
@@ -239,7 +517,7 @@ impl Kernel {
         let appid = process.appid();
         let systick = chip.systick();
         systick.reset();
-        systick.set_timer(KERNEL_TICK_DURATION_US);
+        systick.set_timer(self.timeslice_us.get());
         systick.enable(true);
 
         loop {
@@ -257,11 +535,20 @@ impl Kernel {
                     // Running means that this process expects to be running,
                     // so go ahead and set things up and switch to executing
                     // the process.
-                    process.setup_mpu();
-                    chip.mpu().enable_mpu();
+                    if self.mpu_enforced.get() {
+                        process.setup_mpu();
+                        chip.mpu().enable_mpu();
+                    }
                     systick.enable(true);
+                    self.last_scheduled.set(Some(appid.idx()));
+                    let start = self.cycle_counter.map(|counter| counter.cycles());
                     let context_switch_reason = process.switch_to();
                     systick.enable(false);
+                    start.map(|start| {
+                        self.cycle_counter.map(|counter| {
+                            process.debug_add_cycles(counter.cycles().wrapping_sub(start));
+                        });
+                    });
                     chip.mpu().disable_mpu();
 
                     // Now the process has returned back to the kernel. Check
@@ -273,7 +560,9 @@ impl Kernel {
                         }
                         Some(ContextSwitchReason::SyscallFired) => {
                             // Handle each of the syscalls.
-                            match process.get_syscall() {
+                            let syscall = process.get_syscall();
+                            syscall.map(|syscall| self.trace_syscall(appid.idx(), syscall));
+                            match syscall {
                                 Some(Syscall::MEMOP { operand, arg0 }) => {
                                     let res = memop::memop(process, operand, arg0);
                                     process.set_syscall_return_value(res.into());
@@ -49,6 +49,19 @@ pub enum Syscall {
     MEMOP { operand: usize, arg0: usize },
 }
 
+/// A system call in the kernel's syscall trace, if the board gave the kernel
+/// a buffer for one with `Kernel::set_syscall_trace_buffer`.
+#[derive(Copy, Clone, Debug)]
+pub struct SyscallRecord {
+    /// When the call was made, in ticks of the kernel's idle clock, or 0 if
+    /// the board did not set one.
+    pub time: u32,
+    /// The index of the process that made the call.
+    pub process: usize,
+    /// The call and its arguments.
+    pub syscall: Syscall,
+}
+
 /// Why the process stopped executing and execution returned to the kernel.
 #[derive(PartialEq)]
 pub enum ContextSwitchReason {